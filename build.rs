@@ -0,0 +1,18 @@
+use std::process::Command;
+
+// Records the commit Locket was built from, for `--version --json`. Builds from outside of a git
+// checkout, such as from a source tarball, just don't have one.
+fn main() {
+    let hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_owned())
+        .unwrap_or_default();
+
+    println!("cargo:rustc-env=LOCKET_GIT_HASH={hash}");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
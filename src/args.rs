@@ -1,36 +1,613 @@
-use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+// Each of the flags is independent of the others.
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Parser, Debug)]
-#[command(name = "Safe")]
+#[command(name = "locket")]
 #[command(author = "needlesslygrim")]
-#[command(version = "1.0.0")]
+#[command(version)]
 #[command(about = "A simple password manager", long_about = None)]
+// clap's own `--version` exits as soon as it's seen, so it's replaced with one which can be asked
+// for JSON. A subcommand is still needed for anything else, see `main()`.
+#[command(disable_version_flag = true, subcommand_required = false)]
 pub struct Cli {
     #[command(subcommand)]
     pub subcommand: Subcommands,
 
+    #[arg(short = 'V', long, help = "Print version")]
+    pub version: bool,
+
+    #[arg(
+        long,
+        requires = "version",
+        help = "With `--version`, describe this build as JSON, with its features and the database schema version it uses"
+    )]
+    pub json: bool,
+
     #[command(flatten)]
-    pub verbosity: clap_verbosity_flag::Verbosity,
+    pub verbosity: clap_verbosity_flag::Verbosity<Level>,
+
+    #[arg(
+        long = "set",
+        global = true,
+        value_name = "KEY=VALUE",
+        value_parser = parse_override,
+        help = "Override a setting from the configuration file, for this run only"
+    )]
+    pub overrides: Vec<(String, String)>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Never take the lock, not even to save when logins were accessed. Only for commands which don't change the database"
+    )]
+    pub no_lock: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "SECS",
+        default_value_t = 0,
+        help = "How long to wait for another instance of Locket to finish changing the database, instead of giving up straight away"
+    )]
+    pub lock_timeout: u64,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Match accented letters exactly when searching, so that `resume` doesn't find `Résumé`"
+    )]
+    pub no_normalize: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Read the database from standard input, such as a backup, instead of opening it. Only works with commands which just read the database, and doesn't touch the configuration file or the data directory"
+    )]
+    pub db_stdin: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Refuse to open the configuration file or database if other users can read them, instead of warning. Only has an effect on Unix"
+    )]
+    pub strict_permissions: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        help = "The language to show prompts and messages in, instead of the one from `LC_ALL`, `LC_MESSAGES`, or `LANG`"
+    )]
+    pub lang: Option<Language>,
+}
+
+// The same default level as `clap_verbosity_flag`'s own, but with help which says that `--quiet`
+// also hides the messages saying what a command did.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Level;
+
+impl clap_verbosity_flag::LogLevel for Level {
+    fn default() -> Option<log::Level> {
+        Some(log::Level::Error)
+    }
+
+    fn quiet_help() -> Option<&'static str> {
+        Some("Decrease logging verbosity. Once is enough to hide the messages saying what was done, leaving only the data asked for, warnings, and errors")
+    }
+}
+
+fn parse_override(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("`{s}` should be in the form KEY=VALUE"))?;
+
+    Ok((String::from(key.trim()), String::from(value.trim())))
+}
+
+fn parse_rfc3339(s: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(s)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|e| format!("`{s}` isn't an RFC 3339 time, such as 2024-05-01T12:00:00Z: {e}"))
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Subcommands {
     #[command(about = "Initialise a database and configuration")]
     Init(InitArgs),
-    New,
+    New(NewArgs),
     Query(QueryArgs),
-    Remove,
+    #[command(about = "Show everything about one login")]
+    Show(ShowArgs),
+    Remove(RemoveArgs),
+    #[command(about = "Add a login to, or remove it from, your favorites")]
+    Favorite,
+    #[command(about = "Change the name of a login")]
+    Rename(RenameArgs),
+    #[command(about = "Attach a file to a login, replacing any attachment with the same name")]
+    Attach(AttachArgs),
+    #[command(about = "Save a file attached to a login")]
+    Extract(ExtractArgs),
+    #[command(about = "Import logins from a JSON file or another password manager's export")]
+    Import(ImportArgs),
+    #[command(about = "Merge the logins from another Locket database into this one")]
+    Merge(MergeArgs),
+    #[command(about = "Show how the logins in another Locket database differ from this one")]
+    Diff(DiffArgs),
+    #[command(about = "Export logins to a JSON file or another password manager's format")]
+    Export(ExportArgs),
+    #[command(about = "List the logins which need attention, such as those with reused passwords")]
+    Review,
+    #[command(about = "Count the logins, including those with weak or reused passwords")]
+    Stats(StatsArgs),
+    #[command(about = "Tidy up the database, removing anything left over which is no longer used")]
+    Compact(CompactArgs),
+    #[command(about = "Check for common problems with Locket's setup")]
+    Doctor,
+    #[command(about = "Inspect the configuration")]
+    Config(ConfigArgs),
+    #[command(about = "Print where the configuration file, database, and lockfile are")]
+    Paths(PathsArgs),
+    #[cfg(unix)]
+    #[command(about = "Make the configuration file, database, and backups readable only by you")]
+    FixPermissions,
+    #[command(about = "Print or install the completion script for your shell")]
+    Completions(CompletionsArgs),
+    #[command(about = "Generate a random password")]
+    Generate(GenerateCommandArgs),
     #[cfg(feature = "web")]
-    Serve,
+    Serve(ServeArgs),
+    // Accepted without the `web` feature too, so that `run()` can say why it isn't available,
+    // rather than clap saying there's no such command.
+    #[cfg(not(feature = "web"))]
+    #[command(hide = true)]
+    Serve(ServeArgs),
+    #[cfg(all(unix, feature = "socket"))]
+    #[command(about = "Serve JSON-RPC on a Unix domain socket, for launchers and editors")]
+    Socket(SocketArgs),
+}
+
+impl Subcommands {
+    // Commands which only read the database never write it back, so they don't need the lock and
+    // can run while another instance, such as `serve`, has it. The access times recorded by `show`
+    // are the exception, and are only saved if the lock happens to be free, see `run()`.
+    #[must_use]
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Self::Query(_)
+                | Self::Show(_)
+                | Self::Extract(_)
+                | Self::Export(_)
+                | Self::Review
+                | Self::Stats(_)
+                | Self::Diff(_)
+        )
+    }
+
+    // Commands which can run on a database read from standard input, which has nowhere to be
+    // written back to. `show` is left out since it records when the login was accessed, and may
+    // need to ask which login to show, and `extract` for the same reasons.
+    #[must_use]
+    pub fn works_without_file(&self) -> bool {
+        matches!(
+            self,
+            Self::Query(_) | Self::Export(_) | Self::Review | Self::Stats(_) | Self::Diff(_)
+        )
+    }
+
+    // Servers handle SIGINT themselves, to shut down cleanly.
+    #[must_use]
+    pub fn handles_interrupts(&self) -> bool {
+        match self {
+            #[cfg(feature = "web")]
+            Self::Serve(_) => true,
+            #[cfg(all(unix, feature = "socket"))]
+            Self::Socket(_) => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct ServeArgs {
+    #[arg(
+        long,
+        help = "Apply changes to the configuration file without restarting, where the setting allows it"
+    )]
+    pub watch: bool,
+}
+
+#[cfg(all(unix, feature = "socket"))]
+#[derive(Parser, Debug)]
+pub struct SocketArgs {
+    #[arg(
+        long,
+        help = "Where to create the socket, `locket.sock` beside the database by default"
+    )]
+    pub path: Option<PathBuf>,
 }
 
 #[derive(Parser, Debug)]
 pub struct InitArgs {
     #[arg(short, long)]
     pub port: Option<u16>,
+    #[arg(
+        long,
+        help = "Start over, even if Locket has already been initialised. The existing configuration file and database are moved aside to timestamped backups first"
+    )]
+    pub force: bool,
 }
 
+// These are all independent flags, so there's no state machine hiding in them.
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Parser, Debug)]
 pub struct QueryArgs {
     pub name: Option<String>,
+    #[arg(long, help = "Only show favorite logins")]
+    pub favorites: bool,
+    #[arg(
+        long,
+        help = "Only show logins in this folder, or in the folders inside it, such as `Work/Cloud`"
+    )]
+    pub folder: Option<String>,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    pub output: OutputFormat,
+    #[arg(
+        long,
+        visible_alias = "field",
+        value_enum,
+        value_delimiter = ',',
+        help = "Which fields to show, in order, separated by commas"
+    )]
+    pub fields: Vec<LoginField>,
+    #[arg(long, help = "Show passwords instead of hiding them")]
+    pub show_passwords: bool,
+    #[arg(long, help = "Only show the best match")]
+    pub first: bool,
+    #[arg(
+        long,
+        conflicts_with = "output",
+        help = "Print just the values of the fields, with no headers or decoration. The same as `--output plain`"
+    )]
+    pub raw: bool,
+    #[arg(long, value_enum, default_value_t = QuerySort::Relevance)]
+    pub sort: QuerySort,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum QuerySort {
+    #[value(help = "Favorites first, then the best matches")]
+    Relevance,
+    #[value(help = "The most recently shown logins first")]
+    Recent,
+}
+
+#[derive(Parser, Debug)]
+pub struct ShowArgs {
+    #[arg(help = "The name or id of the login to show")]
+    pub login: Option<String>,
+    #[arg(long, help = "Show passwords and secret fields instead of hiding them")]
+    pub show_passwords: bool,
+}
+
+// The languages which prompts have been translated into.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Language {
+    #[default]
+    #[value(name = "en")]
+    English,
+    #[value(name = "de")]
+    German,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum OutputFormat {
+    #[value(help = "A table, for reading")]
+    Table,
+    #[value(help = "A JSON array of objects")]
+    Json,
+    #[value(help = "One login per line, with fields separated by tabs")]
+    Plain,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoginField {
+    Name,
+    Username,
+    Password,
+    Favorite,
+    Url,
+    Notes,
+    Tags,
+    Folder,
+}
+
+#[derive(Parser, Debug)]
+pub struct NewArgs {
+    #[arg(
+        long,
+        conflicts_with_all = ["name", "username", "generate"],
+        help = "Add a JSON array of logins read from standard input, instead of asking for one"
+    )]
+    pub stdin: bool,
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Start with the values from a template in the configuration file. With `--stdin`, they fill in the fields each login leaves empty"
+    )]
+    pub template: Option<String>,
+    #[arg(
+        long,
+        help = "The name of the login. Only the password is asked for, unless it's generated, and the rest is left empty or taken from the template"
+    )]
+    pub name: Option<String>,
+    #[arg(long, help = "The username of the login, instead of asking for it")]
+    pub username: Option<String>,
+    #[arg(
+        long,
+        help = "Generate the password instead of asking for it, and print it once the login is added"
+    )]
+    pub generate: bool,
+    #[command(flatten)]
+    pub generator: GenerateArgs,
+    #[arg(
+        long,
+        requires = "generate",
+        help = "Copy the generated password to the clipboard instead of printing it"
+    )]
+    pub copy: bool,
+}
+
+// How passwords are generated, for `generate` and `new --generate`.
+#[derive(Args, Debug, Clone)]
+pub struct GenerateArgs {
+    #[arg(
+        long,
+        default_value_t = 20,
+        value_parser = clap::value_parser!(u16).range(8..=1024),
+        help = "How many characters the generated password has"
+    )]
+    pub length: u16,
+    #[arg(long, help = "Leave digits out of the generated password")]
+    pub no_digits: bool,
+    #[arg(long, help = "Leave symbols out of the generated password")]
+    pub no_symbols: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct GenerateCommandArgs {
+    #[command(flatten)]
+    pub generator: GenerateArgs,
+    #[arg(
+        long,
+        help = "Copy the password to the clipboard instead of printing it"
+    )]
+    pub copy: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct RemoveArgs {
+    #[arg(long, help = "Choose several logins to remove at once")]
+    pub multi: bool,
+    #[arg(
+        long,
+        help = "Show what would be removed without changing the database"
+    )]
+    pub dry_run: bool,
+    #[arg(short, long, help = "Don't ask for confirmation")]
+    pub yes: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct RenameArgs {
+    #[arg(help = "The name or id of the login to rename")]
+    pub login: Option<String>,
+    #[arg(long, help = "The new name, instead of asking for it")]
+    pub to: Option<String>,
+    #[arg(
+        long,
+        help = "Show what the login would be renamed to without changing the database"
+    )]
+    pub dry_run: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct MergeArgs {
+    pub path: PathBuf,
+    #[arg(long, value_enum, default_value_t = MergePolicy::Skip)]
+    pub on_conflict: MergePolicy,
+    #[arg(long, help = "Show what would be merged without changing the database")]
+    pub dry_run: bool,
+    #[arg(
+        long,
+        help = "Keep passwords which refer to a file or command as references, instead of storing them as they are"
+    )]
+    pub allow_references: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct AttachArgs {
+    #[arg(help = "The name or id of the login to attach the file to")]
+    pub login: String,
+    #[arg(help = "The file to attach")]
+    pub file: PathBuf,
+    #[arg(long, help = "What to call the attachment, the file's name by default")]
+    pub name: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct ExtractArgs {
+    #[arg(help = "The name or id of the login the file is attached to")]
+    pub login: String,
+    #[arg(help = "The name of the attachment")]
+    pub name: String,
+    #[arg(help = "Where to save the file, standard output by default")]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    pub command: ConfigCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    #[command(
+        about = "Print the settings this run would use, after `--set` and environment variables, with where each came from"
+    )]
+    Show(ConfigShowArgs),
+}
+
+#[derive(Parser, Debug)]
+pub struct ConfigShowArgs {
+    #[arg(long, help = "Print the settings as JSON instead of TOML")]
+    pub json: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct PathsArgs {
+    #[arg(long, help = "Print the paths as JSON")]
+    pub json: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct CompletionsArgs {
+    #[arg(
+        long,
+        value_enum,
+        help = "Which shell to generate completions for, the one in `$SHELL` by default"
+    )]
+    pub shell: Option<clap_complete::Shell>,
+    #[arg(
+        long,
+        help = "Write the completions to where your shell looks for them, instead of printing them"
+    )]
+    pub install: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct CompactArgs {
+    #[arg(
+        long,
+        help = "Show what would be removed without changing the database"
+    )]
+    pub dry_run: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct StatsArgs {
+    #[arg(long, value_enum, default_value_t = StatsFormat::Table)]
+    pub format: StatsFormat,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum StatsFormat {
+    #[value(help = "A table, for reading")]
+    Table,
+    #[value(help = "A JSON object, for scripts")]
+    Json,
+    #[value(
+        help = "Prometheus' text format, for monitoring, such as with node_exporter's textfile collector"
+    )]
+    Prometheus,
+}
+
+#[derive(Parser, Debug)]
+pub struct DiffArgs {
+    pub path: PathBuf,
+    #[arg(long, help = "Print the differences as JSON, instead of a table")]
+    pub json: bool,
+}
+
+/// What to do when a login from the other database has the same id, or the same name and username,
+/// as one in this database, but they differ. Also used for conflicts when importing.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergePolicy {
+    #[value(help = "Keep the login in this database")]
+    Skip,
+    #[value(help = "Replace the login in this database with the other one")]
+    Overwrite,
+    #[value(help = "Keep both, renaming the other one if they'd have the same name and username")]
+    KeepBoth,
+}
+
+#[derive(Parser, Debug)]
+pub struct ImportArgs {
+    pub path: PathBuf,
+    #[arg(long, value_enum, default_value_t = ImportFormat::Json)]
+    pub format: ImportFormat,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = MergePolicy::Skip,
+        help = "What to do with a login which has the same name and username as one in the database, but differs from it"
+    )]
+    pub on_conflict: MergePolicy,
+    #[arg(
+        long,
+        conflicts_with = "dry_run",
+        help = "Ask what to do about each conflict, instead of using `--on-conflict`. Falls back to `--on-conflict` when standard input isn't a terminal"
+    )]
+    pub interactive: bool,
+    #[arg(
+        long,
+        help = "Show what would be imported without changing the database"
+    )]
+    pub dry_run: bool,
+    #[arg(
+        long,
+        help = "Keep passwords which refer to a file or command as references, instead of storing them as they are"
+    )]
+    pub allow_references: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct ExportArgs {
+    #[arg(help = "Where to write the logins, instead of standard output")]
+    pub path: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+    pub format: ExportFormat,
+    #[arg(
+        long,
+        help = "Write a plain JSON array of logins, as `new --stdin` takes, without the format version and export time around it"
+    )]
+    pub bare: bool,
+    #[arg(
+        long,
+        value_parser = parse_rfc3339,
+        conflicts_with = "redacted",
+        help = "Only export the logins added or changed at or after this time, such as 2024-05-01T12:00:00Z, and mark the export as partial"
+    )]
+    pub since: Option<DateTime<Utc>>,
+    #[arg(
+        long,
+        conflicts_with = "bare",
+        help = "Write the whole database with the passwords, secret fields and attachments replaced by \"<redacted>\", to share when reporting a problem"
+    )]
+    pub redacted: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ImportFormat {
+    #[value(
+        help = "An export from `locket export`, or a JSON array of logins as accepted by `/api/v1/new`"
+    )]
+    Json,
+    #[value(help = "The unencrypted XML export from KeePass or KeePassXC")]
+    KeepassXml,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ExportFormat {
+    #[value(
+        help = "The logins as JSON, with the version of the format, which `locket import` can read back"
+    )]
+    Json,
+    #[value(help = "A CSV file that KeePassXC can import")]
+    KeepassCsv,
 }
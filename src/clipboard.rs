@@ -0,0 +1,66 @@
+use std::{
+    env,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use color_eyre::eyre::{bail, Context, Result};
+use log::debug;
+
+// Copies `text` to the clipboard with the tool the platform provides for it, so that Locket doesn't
+// need a display server connection of its own. The text is written to the tool's standard input,
+// where other users can't see it, as they could its arguments.
+pub(crate) fn copy(text: &str) -> Result<()> {
+    let tools = tools();
+    for (program, args) in &tools {
+        let mut child = match Command::new(program)
+            .args(*args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) => {
+                debug!("Failed to run `{program}` to copy to the clipboard: {e}");
+                continue;
+            }
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(text.as_bytes())
+                .wrap_err_with(|| format!("Failed to write to `{program}`"))?;
+        }
+        let status = child
+            .wait()
+            .wrap_err_with(|| format!("Failed to wait for `{program}`"))?;
+        if !status.success() {
+            bail!("`{program}` failed to copy to the clipboard with {status}");
+        }
+        return Ok(());
+    }
+
+    bail!(
+        "Failed to copy to the clipboard, install one of {}",
+        tools
+            .iter()
+            .map(|(program, _)| format!("`{program}`"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+// The tools to try, in order.
+fn tools() -> Vec<(&'static str, &'static [&'static str])> {
+    if cfg!(target_os = "macos") {
+        vec![("pbcopy", &[])]
+    } else if cfg!(windows) {
+        vec![("clip", &[])]
+    } else if env::var_os("WAYLAND_DISPLAY").is_some() {
+        vec![("wl-copy", &[]), ("xclip", &["-selection", "clipboard"])]
+    } else {
+        vec![
+            ("xclip", &["-selection", "clipboard"]),
+            ("xsel", &["--clipboard", "--input"]),
+        ]
+    }
+}
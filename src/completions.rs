@@ -0,0 +1,86 @@
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::CommandFactory;
+use clap_complete::Shell;
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use directories::BaseDirs;
+
+use crate::{args::Cli, program::bin_name};
+
+// Prints the completion script for `shell`, or for the one in `$SHELL` if it isn't given. With
+// `install`, the script is written to where the shell looks for completions instead.
+pub(crate) fn completions(shell: Option<Shell>, install: bool) -> Result<()> {
+    let shell = shell.or_else(Shell::from_env).ok_or_else(|| {
+        eyre!("Couldn't tell which shell you use from `$SHELL`, choose one with `--shell`")
+    })?;
+    let name = bin_name();
+
+    // Generated into a buffer first, since `generate()` panics if writing fails, such as when
+    // standard output is piped into something which exits early.
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut Cli::command(), &name, &mut script);
+
+    if !install {
+        return io::stdout()
+            .write_all(&script)
+            .wrap_err("Failed to write the completions");
+    }
+
+    let (path, reload) = install_path(shell, &name)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+    }
+    fs::write(&path, script).wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+
+    status!("Installed the {shell} completions to {}", path.display());
+    status!("{reload}");
+    Ok(())
+}
+
+// Where each shell looks for completions of its own accord, along with what the user needs to do
+// for them to be picked up. The XDG directories are used on every platform, since that's where the
+// shells look, even on macOS.
+fn install_path(shell: Shell, name: &str) -> Result<(PathBuf, String)> {
+    let home = BaseDirs::new()
+        .ok_or_else(|| eyre!("Failed to find your home directory"))?
+        .home_dir()
+        .to_path_buf();
+    let data_dir = xdg_dir("XDG_DATA_HOME", &home, ".local/share");
+    let config_dir = xdg_dir("XDG_CONFIG_HOME", &home, ".config");
+    let new_shell = String::from("Open a new shell to start using them");
+
+    Ok(match shell {
+        Shell::Bash => (
+            data_dir.join("bash-completion/completions").join(name),
+            new_shell,
+        ),
+        Shell::Fish => (
+            config_dir
+                .join("fish/completions")
+                .join(format!("{name}.fish")),
+            new_shell,
+        ),
+        Shell::Zsh => {
+            let dir = data_dir.join("zsh/site-functions");
+            let reload = format!(
+                "If {} isn't in your `fpath` already, add it in your .zshrc before `compinit` is run, then open a new shell",
+                dir.display()
+            );
+            (dir.join(format!("_{name}")), reload)
+        }
+        _ => bail!(
+            "Completions can't be installed for {shell} automatically, load the output of `{name} completions --shell {shell}` from your shell's profile instead"
+        ),
+    })
+}
+
+fn xdg_dir(var: &str, home: &Path, default: &str) -> PathBuf {
+    env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .unwrap_or_else(|| home.join(default))
+}
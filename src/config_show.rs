@@ -0,0 +1,149 @@
+use std::{env, fmt, fs};
+
+use color_eyre::eyre::{eyre, Context, Result};
+use log::LevelFilter;
+use serde::{Serialize, Serializer};
+
+use crate::{
+    models::{Config, CONFIG_KEYS},
+    program::{Program, DATA_DIR_VAR},
+};
+
+// Where the effective value of a setting came from.
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    Default,
+    File,
+    // The environment variable which overrides the setting.
+    Env(&'static str),
+    Flag,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => f.write_str("default"),
+            Self::File => f.write_str("config file"),
+            Self::Env(var) => write!(f, "env {var}"),
+            Self::Flag => f.write_str("flag"),
+        }
+    }
+}
+
+impl Serialize for Source {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+struct Setting {
+    key: String,
+    // `None` for settings which are unset, such as `log_level` by default.
+    value: Option<toml::Value>,
+    source: Source,
+}
+
+// Prints every setting as it's actually used by this run, after the configuration file, `--set`,
+// the environment variables and the logging flags have all been applied, with where each value came
+// from. `verbosity` is the level given with `-v` or `-q`, if either was.
+pub(crate) fn show(
+    program: &Program,
+    overrides: &[(String, String)],
+    verbosity: Option<LevelFilter>,
+    json: bool,
+) -> Result<()> {
+    let config = program.open_config()?.with_overrides(overrides)?;
+    // Read again as plain TOML, to tell the settings which are in the file from those left to
+    // their defaults.
+    let file: toml::Table = fs::read_to_string(&program.conf_path)
+        .wrap_err("Failed to read the configuration file")?
+        .parse()
+        .wrap_err("Failed to parse the configuration file")?;
+    let effective = toml::Value::try_from(&config)
+        .wrap_err("Failed to serialise the configuration")?
+        .try_into::<toml::Table>()
+        .map_err(|_| eyre!("The configuration didn't serialise to a table"))?;
+
+    let mut settings: Vec<Setting> = CONFIG_KEYS
+        .iter()
+        .chain(&["templates"])
+        .map(|key| {
+            let source = if overrides.iter().any(|(overridden, _)| overridden == key) {
+                Source::Flag
+            } else if lookup(&file, key).is_some() {
+                Source::File
+            } else {
+                Source::Default
+            };
+            Setting {
+                key: String::from(*key),
+                value: lookup(&effective, key).cloned(),
+                source,
+            }
+        })
+        .collect();
+    apply_environment(&mut settings, program, &config, verbosity);
+
+    if json {
+        let settings: serde_json::Map<String, serde_json::Value> = settings
+            .into_iter()
+            .map(|Setting { key, value, source }| {
+                (key, serde_json::json!({ "value": value, "source": source }))
+            })
+            .collect();
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&settings)
+                .wrap_err("Failed to serialise the configuration as JSON")?
+        );
+    } else {
+        // Settings in sections are written with dotted keys, such as `fuzzy.case = "smart"`, so
+        // that the output is still valid TOML, which could be copied into a configuration file.
+        for Setting { key, value, source } in settings {
+            match value {
+                Some(value) => println!("{key} = {value}  # {source}"),
+                None => println!("# {key} is unset  # {source}"),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// The setting called `key`, which may be in a section, as with `fuzzy.case`.
+pub(crate) fn lookup<'a>(table: &'a toml::Table, key: &str) -> Option<&'a toml::Value> {
+    match key.split_once('.') {
+        Some((section, key)) => table.get(section)?.as_table()?.get(key),
+        None => table.get(key),
+    }
+}
+
+// Settings which are overridden from outside the configuration, in the same order of precedence
+// that `main()` and `Program::database_path()` give them.
+fn apply_environment(
+    settings: &mut [Setting],
+    program: &Program,
+    config: &Config,
+    verbosity: Option<LevelFilter>,
+) {
+    for setting in settings {
+        match setting.key.as_str() {
+            "path" if program.data_dir_overridden() => {
+                setting.value = Some(toml::Value::String(
+                    program.database_path(config).display().to_string(),
+                ));
+                setting.source = Source::Env(DATA_DIR_VAR);
+            }
+            "log_level" => {
+                if let Some(level) = verbosity {
+                    setting.value = Some(toml::Value::String(level.to_string().to_lowercase()));
+                    setting.source = Source::Flag;
+                } else if let Ok(filters) = env::var("LOCKET_LOG") {
+                    setting.value = Some(toml::Value::String(filters));
+                    setting.source = Source::Env("LOCKET_LOG");
+                }
+            }
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    fmt::Display,
+    fs::{File, OpenOptions},
+    path::Path,
+};
+
+use color_eyre::eyre::Result;
+use console::style;
+
+use crate::{
+    models::{Config, Database, SCHEMA_VERSION},
+    program::{self, bin_name, LockStatus, Program},
+};
+
+// Checks for the problems that people most often run into when setting up Locket, and prints a
+// checklist of the results. Nothing in here creates, modifies, or removes any files, so it's safe
+// to run at any time, even while another instance is running. Returns whether everything passed.
+pub(crate) fn doctor(program: &Program) -> Result<bool> {
+    let mut report = Report::default();
+
+    let config = check_config(&mut report, &program.conf_path);
+    let db_path = config.as_ref().map_or(program.db_path.as_path(), |config| {
+        program.database_path(config)
+    });
+    check_database(&mut report, db_path);
+    check_lockfile(&mut report, db_path)?;
+    #[cfg(feature = "web")]
+    if let Some(config) = &config {
+        check_port(&mut report, config.port, &program.conf_path);
+    }
+
+    println!(
+        "{} passed, {} warning(s), {} problem(s)",
+        report.passed, report.warnings, report.failures
+    );
+
+    Ok(report.failures == 0)
+}
+
+#[derive(Default)]
+struct Report {
+    passed: usize,
+    warnings: usize,
+    failures: usize,
+}
+
+impl Report {
+    fn pass(&mut self, message: impl Display) {
+        self.passed += 1;
+        println!("{} {message}", style("✔").green());
+    }
+
+    fn warn(&mut self, message: impl Display, fix: impl Display) {
+        self.warnings += 1;
+        println!("{} {message}", style("!").yellow());
+        println!("    {fix}");
+    }
+
+    fn fail(&mut self, message: impl Display, fix: impl Display) {
+        self.failures += 1;
+        println!("{} {message}", style("✘").red());
+        println!("    {fix}");
+    }
+}
+
+fn check_config(report: &mut Report, path: &Path) -> Option<Config> {
+    match path.try_exists() {
+        Ok(true) => (),
+        Ok(false) => {
+            report.fail(
+                format!("There is no configuration file at {}", path.display()),
+                format!("Run `{} init` to create one", bin_name()),
+            );
+            return None;
+        }
+        Err(e) => {
+            report.fail(
+                format!("Couldn't check whether {} exists: {e}", path.display()),
+                "Check the permissions of the directories leading up to it",
+            );
+            return None;
+        }
+    }
+
+    match Config::open(path) {
+        Ok(config) => {
+            report.pass(format!("Configuration file at {} is valid", path.display()));
+            Some(config)
+        }
+        Err(e) => {
+            report.fail(
+                format!(
+                    "Configuration file at {} couldn't be read: {e:#}",
+                    path.display()
+                ),
+                format!(
+                    "Fix it by hand, or delete it and run `{} init` again",
+                    bin_name()
+                ),
+            );
+            None
+        }
+    }
+}
+
+fn check_database(report: &mut Report, path: &Path) {
+    if !path.try_exists().unwrap_or(false) {
+        report.fail(
+            format!("There is no database at {}", path.display()),
+            format!(
+                "Run `{} init`, or change `path` in the configuration file to point at your database",
+                bin_name()
+            ),
+        );
+        return;
+    }
+
+    if let Err(e) = File::open(path) {
+        report.fail(
+            format!("The database at {} isn't readable: {e}", path.display()),
+            "Check the permissions of the database file",
+        );
+        return;
+    }
+
+    // Opening the file for writing without truncating or creating it doesn't change anything.
+    if let Err(e) = OpenOptions::new().write(true).open(path) {
+        report.fail(
+            format!("The database at {} isn't writable: {e}", path.display()),
+            "Check the permissions of the database file",
+        );
+        return;
+    }
+
+    match Database::open(path) {
+        Ok(db) if db.file_version() < SCHEMA_VERSION => report.pass(format!(
+            "Database at {} is readable and writable ({} logins, schema version {}, which will be upgraded to {SCHEMA_VERSION} when it's next changed)",
+            path.display(),
+            db.logins.len(),
+            db.file_version()
+        )),
+        Ok(db) => report.pass(format!(
+            "Database at {} is readable and writable ({} logins, schema version {})",
+            path.display(),
+            db.logins.len(),
+            db.file_version()
+        )),
+        Err(e) => report.fail(
+            format!(
+                "The database at {} couldn't be loaded: {e:#}",
+                path.display()
+            ),
+            "The database may be corrupt, or have been written by a newer version of Locket",
+        ),
+    }
+}
+
+fn check_lockfile(report: &mut Report, db_path: &Path) -> Result<()> {
+    // The lock is released by the OS when the process holding it exits, so unlike the old
+    // lockfiles, it can't be left behind.
+    match program::lock_status(db_path)? {
+        LockStatus::Free => {
+            report.pass("No other instance of Locket is changing the database");
+        }
+        LockStatus::HeldByUnknown => {
+            let path = program::lockfile_path(db_path);
+            let path = path.display();
+            report.warn(
+                format!("The lock at {path} is held, but it doesn't say which process has it"),
+                format!("If Locket isn't running, check which process has {path} open"),
+            );
+        }
+        LockStatus::HeldBy(pid) => report.pass(format!(
+            "Locket is running with PID {pid}, and is changing the database"
+        )),
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "web")]
+fn check_port(report: &mut Report, port: u16, conf_path: &Path) {
+    match std::net::TcpListener::bind(("127.0.0.1", port)) {
+        Ok(_) => report.pass(format!(
+            "Port {port} is available for `{} serve`",
+            bin_name()
+        )),
+        Err(e) => report.fail(
+            format!("Port {port} can't be bound: {e}"),
+            format!(
+                "Stop whatever is using it, or change `port` in {}",
+                conf_path.display()
+            ),
+        ),
+    }
+}
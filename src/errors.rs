@@ -1,9 +1,207 @@
+use std::path::PathBuf;
+
 use thiserror::Error;
 
+/// Failures which callers may want to tell apart from the rest, by downcasting the
+/// [`color_eyre::Report`] they're returned in.
 #[derive(Debug, Copy, Clone, Error)]
 pub enum LocketError {
     #[error("Tried to initialise a configuration file where one already exists")]
-    ConfigAlreadyExistsError,
+    ConfigAlreadyExists,
     #[error("Tried to initialise a database file where one already exists")]
-    DatabaseAlreadyExistsError,
+    DatabaseAlreadyExists,
+    #[error("Timed out waiting for an answer to a prompt")]
+    PromptTimedOut,
+    #[error("Locket hasn't been initialised yet, there is no configuration file")]
+    NotInitialised,
+}
+
+/// The database file exists and isn't empty, but couldn't be decoded, e.g. because something other
+/// than Locket has written to it. `backup` is the most recent backup which can be decoded, if any.
+#[derive(Debug, Clone, Error)]
+#[error(
+    "The database at {} appears to be corrupt{}",
+    .path.display(),
+    .backup.as_ref().map_or_else(
+        || String::from(", and there are no backups to restore it from"),
+        |backup| format!(
+            ", copy the most recent readable backup, {}, over it to restore it",
+            backup.display()
+        )
+    )
+)]
+pub struct CorruptDatabase {
+    pub path: PathBuf,
+    pub backup: Option<PathBuf>,
+}
+
+/// Why a login couldn't be added to the database.
+#[derive(Debug, Clone, Error)]
+pub enum AddError {
+    #[error("There is already a login called {name:?} with the username {username:?}")]
+    Duplicate { name: String, username: String },
+    #[error("Failed to find an unused id for the login after {0} attempts")]
+    NoFreeId(usize),
+    #[error("The database has {count} logins and `max_logins` allows at most {limit}, so {adding} more can't be added")]
+    QuotaExceeded {
+        count: usize,
+        adding: usize,
+        limit: usize,
+    },
+}
+
+impl AddError {
+    // A short, stable name for the error, for API clients to match on.
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Duplicate { .. } => "duplicate",
+            Self::NoFreeId(_) => "no_free_id",
+            Self::QuotaExceeded { .. } => "quota_exceeded",
+        }
+    }
+}
+
+/// Why a file couldn't be attached to a login.
+#[derive(Debug, Clone, Error)]
+pub enum AttachError {
+    #[error("There is no login with the id {0}")]
+    NoSuchLogin(uuid::Uuid),
+    #[error("The attachment is {size} bytes, but attachments can be at most {max} bytes")]
+    TooLarge { size: u64, max: u64 },
+}
+
+/// Something about a login which is allowed, but is probably a mistake. These are reported
+/// alongside the login rather than stopping it from being added, see `Login::validate()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum LoginWarning {
+    #[error("The password is empty")]
+    EmptyPassword,
+}
+
+impl LoginWarning {
+    // A short, stable name for the warning, for API clients to match on.
+    #[must_use]
+    pub fn kind(self) -> &'static str {
+        match self {
+            Self::EmptyPassword => "empty_password",
+        }
+    }
+}
+
+// Errors returned by the web server's handlers. Each one maps onto a status code, and `serve()`
+// turns them into responses in one place, so the handlers don't each have to.
+#[cfg(feature = "web")]
+#[derive(Debug, Clone, Error)]
+pub(crate) enum ApiError {
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+    #[error("Unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+    #[error("Not found: {0}")]
+    NotFound(String),
+    // `allow` lists the methods which the path does accept, for the `Allow` header.
+    #[error("Method not allowed: {message}")]
+    MethodNotAllowed { message: String, allow: String },
+    #[error("Conflict: {0}")]
+    Conflict(String),
+    #[error("Payload too large: {0}")]
+    PayloadTooLarge(String),
+    #[error("Internal server error: {0}")]
+    Internal(String),
+    #[error("Service unavailable: {0}")]
+    ServiceUnavailable(String),
+    #[error("Insufficient storage: {0}")]
+    InsufficientStorage(String),
+}
+
+#[cfg(feature = "web")]
+impl ApiError {
+    #[must_use]
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::BadRequest(_) => 400,
+            Self::Forbidden(_) => 403,
+            Self::NotFound(_) => 404,
+            Self::MethodNotAllowed { .. } => 405,
+            Self::Conflict(_) => 409,
+            Self::PayloadTooLarge(_) => 413,
+            Self::UnsupportedMediaType(_) => 415,
+            Self::Internal(_) => 500,
+            Self::ServiceUnavailable(_) => 503,
+            Self::InsufficientStorage(_) => 507,
+        }
+    }
+
+    // A short, stable name for the error, for API clients to match on, like `AddError::kind()`.
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::BadRequest(_) => "bad_request",
+            Self::UnsupportedMediaType(_) => "unsupported_media_type",
+            Self::Forbidden(_) => "forbidden",
+            Self::NotFound(_) => "not_found",
+            Self::MethodNotAllowed { .. } => "method_not_allowed",
+            Self::Conflict(_) => "conflict",
+            Self::PayloadTooLarge(_) => "payload_too_large",
+            Self::Internal(_) => "internal",
+            Self::ServiceUnavailable(_) => "service_unavailable",
+            Self::InsufficientStorage(_) => "insufficient_storage",
+        }
+    }
+
+    // The body is JSON, `{"error": <kind>, "message": ...}`, like the results for each login from
+    // `/api/v1/new`. The message is sent back to the client, except for internal errors, which
+    // might give away details about the machine the server is running on. Those are only logged.
+    #[must_use]
+    pub fn into_response(self) -> tiny_http::Response<std::io::Cursor<Vec<u8>>> {
+        let status = self.status();
+        let kind = self.kind();
+        let mut allow = None;
+        let message = match self {
+            Self::Internal(_) => {
+                String::from(tiny_http::StatusCode(status).default_reason_phrase())
+            }
+            Self::MethodNotAllowed {
+                message,
+                allow: methods,
+            } => {
+                allow = Some(methods);
+                message
+            }
+            Self::BadRequest(message)
+            | Self::UnsupportedMediaType(message)
+            | Self::Forbidden(message)
+            | Self::NotFound(message)
+            | Self::Conflict(message)
+            | Self::PayloadTooLarge(message)
+            | Self::ServiceUnavailable(message)
+            | Self::InsufficientStorage(message) => message,
+        };
+
+        let body = serde_json::json!({ "error": kind, "message": message }).to_string();
+        let mut response = tiny_http::Response::from_string(body)
+            .with_status_code(status)
+            .with_header(
+                tiny_http::Header::from_bytes("Content-Type", "application/json")
+                    .expect("A constant header is valid"),
+            );
+        if let Some(allow) = allow {
+            response.add_header(
+                tiny_http::Header::from_bytes("Allow", allow)
+                    .expect("A list of methods is a valid header value"),
+            );
+        }
+        if status == 503 {
+            // The queue drains quickly, so there's no need for clients to back off for long.
+            response.with_header(
+                tiny_http::Header::from_bytes("Retry-After", "1")
+                    .expect("A constant header is valid"),
+            )
+        } else {
+            response
+        }
+    }
 }
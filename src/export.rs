@@ -0,0 +1,92 @@
+use std::io::Write;
+
+use color_eyre::eyre::{Context, Result};
+use serde_derive::Serialize;
+
+use crate::models::{unix_time, Login};
+
+// Bumped whenever JSON exports change in a way that `import` needs to know about, so that it can
+// adapt older exports, and refuse newer ones with a clear message rather than misreading them.
+pub(crate) const EXPORT_FORMAT_VERSION: u64 = 1;
+
+// What the logins are wrapped in, unless a bare array was asked for.
+#[derive(Serialize)]
+struct Envelope<'a> {
+    version: u64,
+    // In seconds since the Unix epoch, like `Login::last_accessed`.
+    exported_at: u64,
+    // Whether only some of the logins were exported, as with `--since`, so that a partial export
+    // isn't mistaken for the whole database.
+    partial: bool,
+    logins: &'a [&'a Login],
+}
+
+// Writes the logins as JSON, which `locket import` reads back. With `bare`, they're written as a
+// plain array, as `/api/v1/new` and `new --stdin` take, without the version and time around them.
+pub(crate) fn write_json(
+    logins: &[&Login],
+    bare: bool,
+    partial: bool,
+    mut writer: impl Write,
+) -> Result<()> {
+    if bare {
+        serde_json::to_writer_pretty(&mut writer, logins)
+    } else {
+        let envelope = Envelope {
+            version: EXPORT_FORMAT_VERSION,
+            exported_at: unix_time(),
+            partial,
+            logins,
+        };
+        serde_json::to_writer_pretty(&mut writer, &envelope)
+    }
+    .wrap_err("Failed to write the logins as JSON")?;
+    writeln!(writer).wrap_err("Failed to write the logins as JSON")?;
+
+    writer
+        .flush()
+        .wrap_err("Failed to flush the exported logins")
+}
+
+/// Writes the logins in the CSV layout that `KeePassXC`'s CSV import expects. `KeePassXC` is
+/// strict about the header, and every field is quoted, even when it doesn't need to be, since
+/// that's what `KeePassXC` itself writes. The login's folder is used as the group, or failing that
+/// its first tag, for logins which were filed with tags alone. Passwords are written as they are,
+/// so references should be resolved first, with [`Login::resolve_password()`].
+///
+/// # Errors
+///
+/// Returns an error if `writer` fails.
+pub fn write_keepass_csv(logins: &[&Login], mut writer: impl Write) -> Result<()> {
+    writeln!(
+        writer,
+        r#""Group","Title","Username","Password","URL","Notes""#
+    )
+    .wrap_err("Failed to write the CSV header")?;
+
+    for login in logins {
+        let group = login
+            .folder
+            .as_ref()
+            .or(login.tags.first())
+            .map_or("", String::as_str);
+        let fields = [
+            group,
+            &login.name,
+            &login.username,
+            &login.password,
+            &login.url,
+            &login.notes,
+        ];
+        let row = fields.map(quote_csv).join(",");
+        writeln!(writer, "{row}").wrap_err("Failed to write a CSV row")?;
+    }
+
+    writer
+        .flush()
+        .wrap_err("Failed to flush the exported logins")
+}
+
+fn quote_csv(field: &str) -> String {
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
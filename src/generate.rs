@@ -0,0 +1,53 @@
+use color_eyre::eyre::{eyre, Result};
+
+use crate::args::GenerateArgs;
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+// Only symbols which sites commonly accept, and which don't need quoting in a shell or escaping in
+// JSON or TOML, so that generated passwords can be pasted anywhere.
+const SYMBOLS: &str = "!#%+,-.:=?@^_~";
+
+// Generates a random password from the operating system's secure random number generator, which
+// has at least one character from each of the kinds of character that `args` allows, since many
+// sites insist on that.
+pub(crate) fn generate(args: &GenerateArgs) -> Result<String> {
+    let mut classes = vec![LOWERCASE, UPPERCASE];
+    if !args.no_digits {
+        classes.push(DIGITS);
+    }
+    if !args.no_symbols {
+        classes.push(SYMBOLS);
+    }
+    let alphabet: Vec<char> = classes.iter().flat_map(|class| class.chars()).collect();
+
+    // With the lengths allowed, a password missing one of the kinds is rare, so trying again is
+    // simpler than placing one of each and shuffling, and doesn't skew the distribution.
+    loop {
+        let password = (0..args.length)
+            .map(|_| random_below(alphabet.len()).map(|index| alphabet[index]))
+            .collect::<Result<String>>()?;
+        if classes
+            .iter()
+            .all(|class| password.chars().any(|c| class.contains(c)))
+        {
+            return Ok(password);
+        }
+    }
+}
+
+// A uniformly random number below `bound`, which must be at most 256. Bytes which would make some
+// numbers more likely than others are thrown away rather than wrapped around.
+fn random_below(bound: usize) -> Result<usize> {
+    let limit = 256 - 256 % bound;
+    loop {
+        let mut byte = [0];
+        getrandom::getrandom(&mut byte)
+            .map_err(|e| eyre!("Failed to get random numbers from the operating system: {e}"))?;
+        let byte = usize::from(byte[0]);
+        if byte < limit {
+            return Ok(byte % bound);
+        }
+    }
+}
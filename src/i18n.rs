@@ -0,0 +1,229 @@
+use std::{
+    env,
+    fmt::{self, Display},
+    sync::OnceLock,
+};
+
+use crate::{args::Language, models::Login};
+
+// Set once by `run()`, before anything is shown. Anything shown before then, or by Locket used as a
+// library, is in English.
+static LANGUAGE: OnceLock<Language> = OnceLock::new();
+
+// Chooses the language from `--lang` if it was given, and otherwise from the locale in the
+// environment, falling back to English for languages which haven't been translated.
+pub(crate) fn set_language(language: Option<Language>) {
+    let language = language.unwrap_or_else(language_from_env);
+    let _ = LANGUAGE.set(language);
+}
+
+fn language() -> Language {
+    LANGUAGE.get().copied().unwrap_or_default()
+}
+
+// The first of these which is set decides the language, as it does for other programs, even if it
+// names a language which hasn't been translated.
+fn language_from_env() -> Language {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .into_iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|locale| !locale.is_empty())
+        .map_or_else(Language::default, |locale| language_from_locale(&locale))
+}
+
+// Locales look like `de_DE.UTF-8` or `de_AT@euro`, of which only the language is needed.
+fn language_from_locale(locale: &str) -> Language {
+    let code = locale
+        .split(['_', '-', '.', '@'])
+        .next()
+        .unwrap_or_default();
+    match code {
+        "de" => Language::German,
+        _ => Language::English,
+    }
+}
+
+// The prompts and messages which are translated, each shown in the chosen language by its
+// `Display` implementation. Every message has to be given in every language, so a translation can't
+// be forgotten when a message is added.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Message<'a> {
+    #[cfg(feature = "web")]
+    PortPrompt,
+    #[cfg(feature = "web")]
+    InvalidPort,
+    NamePrompt,
+    NewNamePrompt,
+    UsernamePrompt,
+    FolderPrompt,
+    PasswordPrompt,
+    ConfirmEmptyPassword,
+    GeneratedPassword,
+    CopyGeneratedPassword,
+    CopiedPassword,
+    AnotherFieldPrompt,
+    FieldNamePrompt,
+    FieldSecretPrompt,
+    FieldValuePrompt,
+    ImportConflict {
+        login: &'a Login,
+        index: usize,
+        total: usize,
+    },
+    KeepExisting,
+    UseImported,
+    KeepBoth,
+    KeepExistingForAll,
+    UseImportedForAll,
+    KeepBothForAll,
+    ChooseLogin,
+    ChooseLoginToRemove,
+    ChooseFavorite,
+    ChooseLoginsToRemove,
+    ConfirmRemove {
+        count: usize,
+    },
+    ConfirmRestore,
+    Initialised,
+    Removed {
+        login: &'a Login,
+    },
+}
+
+impl Display for Message<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match language() {
+            Language::English => self.english(f),
+            Language::German => self.german(f),
+        }
+    }
+}
+
+impl Message<'_> {
+    fn english(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            #[cfg(feature = "web")]
+            Self::PortPrompt => f.write_str("Enter the port number for the server"),
+            #[cfg(feature = "web")]
+            Self::InvalidPort => f.write_str("Not a valid port number"),
+            Self::NamePrompt => f.write_str("Enter the name for the login"),
+            Self::NewNamePrompt => f.write_str("Enter the new name for the login"),
+            Self::UsernamePrompt => f.write_str("Enter the username for this login"),
+            Self::FolderPrompt => {
+                f.write_str("Enter the folder for this login, such as Work/Cloud, if any")
+            }
+            Self::PasswordPrompt => f.write_str("Enter the password for this login"),
+            Self::ConfirmEmptyPassword => f.write_str("Store an empty password?"),
+            Self::GeneratedPassword => {
+                f.write_str("No password was entered, so one was generated for the login")
+            }
+            Self::CopyGeneratedPassword => {
+                f.write_str("Copy the generated password to the clipboard?")
+            }
+            Self::CopiedPassword => f.write_str("Copied the password to the clipboard"),
+            Self::AnotherFieldPrompt => f.write_str("Add another field?"),
+            Self::FieldNamePrompt => f.write_str("Enter the name of the field"),
+            Self::FieldSecretPrompt => f.write_str("Should this field be hidden like a password?"),
+            Self::FieldValuePrompt => f.write_str("Enter the value of the field"),
+            Self::ImportConflict {
+                login,
+                index,
+                total,
+            } => write!(
+                f,
+                "{login} is already in the database, but differs ({index} of {total})"
+            ),
+            Self::KeepExisting => f.write_str("Keep the login in the database"),
+            Self::UseImported => f.write_str("Use the imported login"),
+            Self::KeepBoth => f.write_str("Keep both"),
+            Self::KeepExistingForAll => {
+                f.write_str("Keep the logins in the database for this and every remaining conflict")
+            }
+            Self::UseImportedForAll => {
+                f.write_str("Use the imported logins for this and every remaining conflict")
+            }
+            Self::KeepBothForAll => f.write_str("Keep both for this and every remaining conflict"),
+            Self::ChooseLogin => f.write_str("Choose a login"),
+            Self::ChooseLoginToRemove => f.write_str("Choose a login to remove"),
+            Self::ChooseFavorite => {
+                f.write_str("Choose a login to add to or remove from your favorites")
+            }
+            Self::ChooseLoginsToRemove => {
+                f.write_str("Choose the logins to remove (space to select, enter to confirm)")
+            }
+            Self::ConfirmRemove { count } => write!(f, "Remove {count} login(s)?"),
+            Self::ConfirmRestore => f.write_str("Restore the database from this backup?"),
+            Self::Initialised => {
+                f.write_str("Successfully initialised a database and configuration file")
+            }
+            Self::Removed { login } => write!(f, "Removed {login}"),
+        }
+    }
+
+    fn german(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            #[cfg(feature = "web")]
+            Self::PortPrompt => f.write_str("Portnummer für den Server eingeben"),
+            #[cfg(feature = "web")]
+            Self::InvalidPort => f.write_str("Keine gültige Portnummer"),
+            Self::NamePrompt => f.write_str("Namen des Eintrags eingeben"),
+            Self::NewNamePrompt => f.write_str("Neuen Namen des Eintrags eingeben"),
+            Self::UsernamePrompt => f.write_str("Benutzernamen für diesen Eintrag eingeben"),
+            Self::FolderPrompt => f.write_str(
+                "Ordner für diesen Eintrag eingeben, z. B. Arbeit/Cloud, falls gewünscht",
+            ),
+            Self::PasswordPrompt => f.write_str("Passwort für diesen Eintrag eingeben"),
+            Self::ConfirmEmptyPassword => f.write_str("Ein leeres Passwort speichern?"),
+            Self::GeneratedPassword => f.write_str(
+                "Es wurde kein Passwort eingegeben, daher wurde eines für den Eintrag erzeugt",
+            ),
+            Self::CopyGeneratedPassword => {
+                f.write_str("Das erzeugte Passwort in die Zwischenablage kopieren?")
+            }
+            Self::CopiedPassword => f.write_str("Passwort in die Zwischenablage kopiert"),
+            Self::AnotherFieldPrompt => f.write_str("Ein weiteres Feld hinzufügen?"),
+            Self::FieldNamePrompt => f.write_str("Namen des Feldes eingeben"),
+            Self::FieldSecretPrompt => {
+                f.write_str("Soll dieses Feld wie ein Passwort verborgen werden?")
+            }
+            Self::FieldValuePrompt => f.write_str("Wert des Feldes eingeben"),
+            Self::ImportConflict {
+                login,
+                index,
+                total,
+            } => write!(
+                f,
+                "{login} ist bereits in der Datenbank, unterscheidet sich aber ({index} von {total})"
+            ),
+            Self::KeepExisting => f.write_str("Den Eintrag in der Datenbank behalten"),
+            Self::UseImported => f.write_str("Den importierten Eintrag verwenden"),
+            Self::KeepBoth => f.write_str("Beide behalten"),
+            Self::KeepExistingForAll => f.write_str(
+                "Die Einträge in der Datenbank für diesen und alle weiteren Konflikte behalten",
+            ),
+            Self::UseImportedForAll => f.write_str(
+                "Die importierten Einträge für diesen und alle weiteren Konflikte verwenden",
+            ),
+            Self::KeepBothForAll => {
+                f.write_str("Beide für diesen und alle weiteren Konflikte behalten")
+            }
+            Self::ChooseLogin => f.write_str("Eintrag auswählen"),
+            Self::ChooseLoginToRemove => f.write_str("Zu entfernenden Eintrag auswählen"),
+            Self::ChooseFavorite => f.write_str(
+                "Eintrag auswählen, der zu den Favoriten hinzugefügt oder daraus entfernt werden soll",
+            ),
+            Self::ChooseLoginsToRemove => f.write_str(
+                "Zu entfernende Einträge auswählen (Leertaste zum Auswählen, Enter zum Bestätigen)",
+            ),
+            Self::ConfirmRemove { count: 1 } => f.write_str("1 Eintrag entfernen?"),
+            Self::ConfirmRemove { count } => write!(f, "{count} Einträge entfernen?"),
+            Self::ConfirmRestore => {
+                f.write_str("Die Datenbank aus dieser Sicherung wiederherstellen?")
+            }
+            Self::Initialised => {
+                f.write_str("Datenbank und Konfigurationsdatei wurden erfolgreich angelegt")
+            }
+            Self::Removed { login } => write!(f, "{login} entfernt"),
+        }
+    }
+}
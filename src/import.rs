@@ -0,0 +1,229 @@
+use std::io::BufRead;
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use itertools::Itertools;
+use log::debug;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde_json::{Map, Value};
+
+use crate::export::EXPORT_FORMAT_VERSION;
+use crate::models::{normalise_folder, Login};
+
+/// Told about each entry as a parser works through a file, so that the caller can show how far it's
+/// got. The parsers don't know or care how that's shown, if at all.
+pub trait Progress {
+    /// Called before the first entry, with the number of entries if it's known up front.
+    fn start(&mut self, _total: Option<u64>) {}
+    /// Called after each entry.
+    fn advance(&mut self);
+    /// Called after the last entry.
+    fn finish(&mut self) {}
+}
+
+// Parses either an export from `locket export`, or a bare JSON array of logins in the same format
+// accepted by `/api/v1/new`. Entries which aren't valid logins are collected as errors rather than
+// failing the whole import.
+pub(crate) fn parse_json(
+    content: &str,
+    progress: &mut dyn Progress,
+) -> Result<(Vec<Login>, Vec<String>)> {
+    let entries =
+        match serde_json::from_str(content).wrap_err("Failed to parse the logins as JSON")? {
+            Value::Array(entries) => entries,
+            Value::Object(envelope) => unwrap_export(envelope)?,
+            _ => bail!("Expected a JSON array of logins, or an export from `locket export`"),
+        };
+
+    progress.start(Some(entries.len() as u64));
+    let mut logins = Vec::with_capacity(entries.len());
+    let mut malformed = Vec::new();
+    for (i, entry) in entries.into_iter().enumerate() {
+        match serde_json::from_value::<Login>(entry) {
+            Ok(login) => logins.push(login),
+            Err(e) => malformed.push(format!("Entry {i}: {e}")),
+        }
+        progress.advance();
+    }
+    progress.finish();
+
+    Ok((logins, malformed))
+}
+
+// Takes the logins out of the envelope written by `export::write_json()`, after checking that its
+// version of the format is one this version of Locket can read.
+fn unwrap_export(mut envelope: Map<String, Value>) -> Result<Vec<Value>> {
+    let version = envelope
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| eyre!("Expected an export from `locket export`, but it has no `version`"))?;
+    // Exports in older versions of the format will be adapted here, once there are any.
+    match version {
+        EXPORT_FORMAT_VERSION => (),
+        newer if newer > EXPORT_FORMAT_VERSION => bail!(
+            "The export is in version {newer} of the format, but this version of Locket only understands up to version {EXPORT_FORMAT_VERSION}, update Locket to import it"
+        ),
+        unknown => bail!("The export is in version {unknown} of the format, which doesn't exist"),
+    }
+    // Importing never removes logins, so a partial export can be imported like any other.
+    if envelope.get("partial").and_then(Value::as_bool) == Some(true) {
+        debug!("The export only has the logins which changed since an earlier one");
+    }
+
+    match envelope.remove("logins") {
+        Some(Value::Array(logins)) => Ok(logins),
+        _ => bail!("The export doesn't have a `logins` array"),
+    }
+}
+
+// Parses the unencrypted XML export written by KeePass and KeePassXC. The file is read one event
+// at a time, so that a large export doesn't have to be held in memory all at once.
+//
+/// Parses a `KeePass` XML export into logins, along with a message for each entry which was
+/// skipped.
+///
+/// Each entry's fields are stored as `<String><Key>Title</Key><Value>...</Value></String>`, and
+/// entries are nested in `<Group>`s. The outermost group is the database itself, so it's left out
+/// of the folder built from the group path. Old versions of each entry are kept under `<History>`,
+/// and are ignored, as are entries without a title.
+/// The number of entries isn't known until the end of the file, so no total is given to `progress`.
+///
+/// # Errors
+///
+/// Returns an error if the XML is malformed.
+pub fn parse_keepass_xml(
+    input: impl BufRead,
+    progress: &mut dyn Progress,
+) -> Result<(Vec<Login>, Vec<String>)> {
+    progress.start(None);
+    let mut reader = Reader::from_reader(input);
+    let mut buf = Vec::new();
+
+    let mut elements: Vec<Vec<u8>> = Vec::new();
+    let mut groups: Vec<String> = Vec::new();
+    let mut entry: Option<Vec<(String, String)>> = None;
+    let mut key = String::new();
+    let mut value = String::new();
+    let mut entries = 0;
+
+    let mut logins = Vec::new();
+    let mut skipped = Vec::new();
+    loop {
+        let event = reader.read_event_into(&mut buf).wrap_err_with(|| {
+            format!(
+                "Failed to parse the KeePass XML export at byte {}",
+                reader.buffer_position()
+            )
+        })?;
+        let in_history = elements.iter().any(|e| e == b"History");
+
+        match event {
+            Event::Start(start) => {
+                let name = start.local_name().as_ref().to_vec();
+                match name.as_slice() {
+                    b"Group" => groups.push(String::new()),
+                    b"Entry" if !in_history => entry = Some(Vec::new()),
+                    b"String" => {
+                        key.clear();
+                        value.clear();
+                    }
+                    _ => (),
+                }
+                elements.push(name);
+            }
+            Event::Text(text) => {
+                let text = text
+                    .unescape()
+                    .wrap_err("Failed to unescape text in the KeePass XML export")?;
+                push_text(&elements, &mut groups, &mut key, &mut value, &text);
+            }
+            Event::CData(cdata) => {
+                let cdata = cdata.into_inner();
+                let text = String::from_utf8_lossy(&cdata);
+                push_text(&elements, &mut groups, &mut key, &mut value, &text);
+            }
+            Event::End(_) => {
+                let Some(name) = elements.pop() else {
+                    continue;
+                };
+                match name.as_slice() {
+                    b"Group" => {
+                        groups.pop();
+                    }
+                    b"String" if !in_history => {
+                        if let Some(fields) = &mut entry {
+                            fields.push((key.clone(), value.clone()));
+                        }
+                    }
+                    b"Entry" if !in_history => {
+                        entries += 1;
+                        progress.advance();
+                        let Some(fields) = entry.take() else {
+                            continue;
+                        };
+                        let path = groups.iter().skip(1).join("/");
+                        match entry_to_login(fields, &path) {
+                            Some(login) => logins.push(login),
+                            None => skipped.push(format!(
+                                "Entry {entries} in group \"{path}\": skipped because it has no title"
+                            )),
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+
+        buf.clear();
+    }
+    progress.finish();
+
+    Ok((logins, skipped))
+}
+
+// Appends text to whichever value the innermost open element is for, if we care about it.
+fn push_text(
+    elements: &[Vec<u8>],
+    groups: &mut [String],
+    key: &mut String,
+    value: &mut String,
+    text: &str,
+) {
+    let [.., parent, element] = elements else {
+        return;
+    };
+
+    match (parent.as_slice(), element.as_slice()) {
+        (b"Group", b"Name") => {
+            if let Some(group) = groups.last_mut() {
+                group.push_str(text);
+            }
+        }
+        (b"String", b"Key") => key.push_str(text),
+        (b"String", b"Value") => value.push_str(text),
+        _ => (),
+    }
+}
+
+fn entry_to_login(fields: Vec<(String, String)>, path: &str) -> Option<Login> {
+    let mut login = Login::default();
+    for (key, value) in fields {
+        match key.as_str() {
+            "Title" => login.name = value,
+            "UserName" => login.username = value,
+            "Password" => login.password = value,
+            "URL" => login.url = value,
+            "Notes" => login.notes = value,
+            _ => debug!("Ignoring the unsupported KeePass field {key:?}"),
+        }
+    }
+
+    if login.name.is_empty() {
+        return None;
+    }
+    login.folder = normalise_folder(path);
+
+    Some(login)
+}
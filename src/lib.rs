@@ -1,113 +1,518 @@
+//! Locket's password database, for use from other programs.
+//!
+//! [`Database`] holds the [`Login`]s and reads and writes the database file, and [`Config`] is the
+//! configuration file, which says where the database is. Everything else the library offers is
+//! re-exported here too, and only these items are its API. The command line interface, the web
+//! server, and the prompts they use are internal to the `locket` binary, and may change at any
+//! time, even where they have to be public for the binary or the tests to reach them.
 #![warn(clippy::all)]
 #![warn(clippy::pedantic)]
 
-use std::{env, fs, fs::OpenOptions, hint::unreachable_unchecked, io::ErrorKind};
+use std::{
+    io, process,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+#[cfg(any(feature = "web", all(unix, feature = "socket")))]
+use std::{
+    mem,
+    sync::{Arc, Mutex, PoisonError, TryLockError},
+};
 
-use color_eyre::eyre::bail;
-use color_eyre::{eyre::Context, Result};
+use color_eyre::{
+    eyre::{bail, Context},
+    Result,
+};
 
+// Set by `--quiet`, see `status!()`.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+// Like `eprintln!()`, for telling the user what a command has done. Standard output is kept for the
+// data a command was asked for, such as `query`'s table, so that it can be piped somewhere, and
+// these are left out entirely with `--quiet`. Warnings and errors should use `eprintln!()` instead,
+// so they're still shown.
+macro_rules! status {
+    ($($arg:tt)*) => {{
+        if !$crate::QUIET.load(::std::sync::atomic::Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        }
+    }};
+}
+
+// Only public for `main.rs`, see the crate's documentation.
+#[doc(hidden)]
 pub mod args;
+mod clipboard;
+mod completions;
+mod config_show;
+mod doctor;
 mod errors;
+mod export;
+mod generate;
+mod i18n;
+mod import;
 mod models;
 #[cfg(feature = "web")]
 mod net;
+#[cfg(feature = "web")]
+mod openapi;
+mod pager;
+mod paths;
+mod permissions;
+mod program;
+mod progress;
+mod prompt;
+mod redact;
+mod reference;
+mod reveal;
+mod review;
+#[cfg(feature = "web")]
+mod router;
+#[cfg(all(unix, feature = "socket"))]
+mod socket;
+mod stats;
+#[cfg(feature = "web")]
+mod threadpool;
+#[cfg(feature = "web")]
+mod watch;
 
-use crate::args::InitArgs;
-use crate::models::Config;
-use args::Cli;
-use models::Database;
+pub use crate::args::MergePolicy;
+pub use crate::errors::{AddError, AttachError, CorruptDatabase, LocketError, LoginWarning};
+pub use crate::models::{
+    Attachment, ChangedLogin, CompactReport, Config, Database, DiffReport, FieldChange, FuzzyCase,
+    FuzzyConfig, ImportPlan, Login, LoginPatch, MergeReport, QueryBehavior, Template,
+    SCHEMA_VERSION,
+};
+pub use crate::reference::PasswordReference;
 
-static DATABASE_FILE_NAME: &str = "locket.db";
-static CONFIG_FILE_NAME: &str = "locket.toml";
-static LCK_FILE_NAME: &str = "locket.lck";
+// Public for the integration tests, which check that exports can be imported again.
+#[doc(hidden)]
+pub use crate::export::write_keepass_csv;
+#[doc(hidden)]
+pub use crate::import::{parse_keepass_xml, Progress};
 
-// TODO: Extract the logic of opening and closing the config, database, and lockfile into either a set of functions, or an empty struct called
-// `Program` or something, which is responsible for all of this stuff. That would also improve the shutdown logic in `net::serve()`, and would
-// ensure that both functions stayed up to date. This is not especially urgent since it's just another abstraction which would overcomplicate
-// this project even more, but at some point this should be done.
-pub fn run(args: Cli) -> Result<()> {
-    let Some(proj_dirs) = directories::ProjectDirs::from("com.github", "needlesslygrim", "Locket")
-    else {
-        bail!("Failed to get project directories")
-    };
+// Public for the integration tests, which start servers and threadpools of their own.
+#[cfg(feature = "web")]
+#[doc(hidden)]
+pub use crate::net::Server;
+#[cfg(feature = "web")]
+#[doc(hidden)]
+pub use crate::router::{Params, RouteError, Router};
+#[cfg(feature = "web")]
+#[doc(hidden)]
+pub use crate::threadpool::{Threadpool, TryExecError};
 
-    let conf_dir = proj_dirs.config_dir();
-    let data_dir = proj_dirs.data_dir();
+use crate::args::{
+    AttachArgs, CompactArgs, CompletionsArgs, ConfigArgs, ConfigCommand, ConfigShowArgs, DiffArgs,
+    ExportArgs, ExtractArgs, GenerateCommandArgs, InitArgs, NewArgs, OutputFormat, PathsArgs,
+    QueryArgs, RemoveArgs, RenameArgs, ShowArgs, StatsArgs,
+};
+use crate::i18n::Message;
+use crate::models::GivenFields;
+use crate::program::Program;
+use args::Cli;
 
-    if !conf_dir
-        .try_exists()
-        .wrap_err("Failed to check if configuration dir exists")?
-        || !data_dir
-            .try_exists()
-            .wrap_err("Failed to check if data dir exists")?
-    {
-        fs::create_dir_all(conf_dir).wrap_err("Failed to create configuration dir")?;
-        fs::create_dir_all(data_dir).wrap_err("Failed to create data dir")?;
+/// Returns the default log level set in the configuration file, or overridden with `--set`, if
+/// there is one.
+///
+/// Any problems reading the configuration are ignored here, since they'll be reported properly by
+/// [`run()`] once logging has been set up.
+#[doc(hidden)]
+#[must_use]
+pub fn configured_log_level(overrides: &[(String, String)]) -> Option<log::LevelFilter> {
+    let program = Program::new().ok()?;
+    if !program.conf_path.try_exists().ok()? {
+        return None;
     }
 
-    let conf_path = conf_dir.join(CONFIG_FILE_NAME);
-    let db_path = data_dir.join(DATABASE_FILE_NAME);
+    // Its permissions are checked when `run()` opens it again, after `--strict-permissions` has
+    // been seen.
+    Config::read(&program.conf_path)
+        .ok()?
+        .with_overrides(overrides)
+        .ok()?
+        .log_level
+}
 
+/// Describes this build of Locket as JSON, for `--version --json`, so scripts can check what
+/// they're talking to before using the API or opening a database.
+#[doc(hidden)]
+#[must_use]
+pub fn version_json() -> String {
+    let features: &[&str] = &[
+        #[cfg(feature = "web")]
+        "web",
+        #[cfg(feature = "parallel_queries")]
+        "parallel_queries",
+        // `locket socket` only exists on Unix, whatever the features.
+        #[cfg(all(unix, feature = "socket"))]
+        "socket",
+    ];
+    let git = Some(env!("LOCKET_GIT_HASH")).filter(|hash| !hash.is_empty());
+
+    serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "features": features,
+        "git": git,
+        "schema_version": SCHEMA_VERSION,
+    })
+    .to_string()
+}
+
+/// Runs Locket with the given command line arguments.
+///
+/// # Errors
+///
+/// Returns an error if the configuration, database, or lockfile couldn't be created, opened, or
+/// removed, or if the subcommand itself fails.
+#[doc(hidden)]
+pub fn run(args: Cli) -> Result<()> {
     // Alias it to `C` (Command)
     use args::Subcommands as C;
-    if let C::Init(InitArgs { port }) = args.subcommand {
-        Config::init_interactive(&conf_path, &db_path, port)
-            .wrap_err("Failed to initialise configuration file")?;
-        Database::init(&db_path).wrap_err("Failed to initialise database")?;
 
-        println!("Successfully initialised a database and configuration file");
+    QUIET.store(args.verbosity.is_silent(), Ordering::Relaxed);
+    permissions::STRICT.store(args.strict_permissions, Ordering::Relaxed);
+    i18n::set_language(args.lang);
+    // These need nothing from the configuration or the database, so they work before `init`.
+    if let C::Completions(CompletionsArgs { shell, install }) = args.subcommand {
+        return completions::completions(shell, install);
+    }
+    if let C::Generate(GenerateCommandArgs { generator, copy }) = &args.subcommand {
+        let password = generate::generate(generator)?;
+        if *copy {
+            clipboard::copy(&password)?;
+            status!("Copied the password to the clipboard");
+        } else {
+            println!("{password}");
+        }
         return Ok(());
     }
+    #[cfg(not(feature = "web"))]
+    if let C::Serve(_) = args.subcommand {
+        bail!("This build of Locket was compiled without the `web` feature, so it can't serve the web interface. Build it with `--features web` to use `serve`");
+    }
+    if args.db_stdin {
+        return run_with_stdin_db(args);
+    }
+    let program = Program::new().wrap_err("Failed to locate Locket's files")?;
 
-    let config =
-        Config::open_interactive(&conf_path).wrap_err("Failed to open config interactively")?;
+    if let C::Init(InitArgs { port, force }) = args.subcommand {
+        program
+            .init(port, force)
+            .wrap_err("Failed to initialise Locket")?;
 
-    let mut db = Database::open(&config.path).wrap_err("Failed to open the existing database")?;
+        status!("{}", Message::Initialised);
+        return Ok(());
+    }
 
-    let mut lck_path = env::temp_dir();
-    lck_path.push(LCK_FILE_NAME);
+    #[cfg(unix)]
+    if let C::FixPermissions = args.subcommand {
+        return permissions::fix_permissions(&program).wrap_err("Failed to fix permissions");
+    }
 
-    if let Err(err) = OpenOptions::new()
-        .create_new(true)
-        .write(true)
-        .open(&lck_path)
+    if let C::Config(ConfigArgs {
+        command: ConfigCommand::Show(ConfigShowArgs { json }),
+    }) = args.subcommand
     {
-        match err.kind() {
-            ErrorKind::AlreadyExists => {
-                eprintln!("An instance of Locket is already running, please kill it or wait for it to quit before trying to run another instance");
-                std::process::exit(1);
-            }
-            _ => bail!("Failed to open the lockfile: {}", err),
+        let verbosity = args
+            .verbosity
+            .is_present()
+            .then(|| args.verbosity.log_level_filter());
+        return config_show::show(&program, &args.overrides, verbosity, json)
+            .wrap_err("Failed to show the configuration");
+    }
+
+    if let C::Paths(PathsArgs { json }) = args.subcommand {
+        return paths::paths(&program, &args.overrides, json)
+            .wrap_err("Failed to print Locket's paths");
+    }
+
+    if let C::Doctor = args.subcommand {
+        if !doctor::doctor(&program).wrap_err("Failed to run diagnostics")? {
+            process::exit(1);
         }
+        return Ok(());
+    }
+
+    #[cfg(unix)]
+    if !args.subcommand.handles_interrupts() {
+        prompt::restore_terminal_on_interrupt();
+    }
+
+    let config = program.open_config()?.with_overrides(&args.overrides)?;
+
+    program.prepare_data_dir()?;
+    // Taken before the database is opened, so that a command which waited for another instance to
+    // finish sees what it wrote, rather than writing back the copy from before it.
+    let read_only = args.subcommand.is_read_only();
+    if args.no_lock && !read_only {
+        bail!("`--no-lock` only works with commands which don't change the database, such as `query` and `show`, since changes made without the lock can be lost");
+    }
+    let lock = if read_only {
+        None
+    } else {
+        Some(program::lock(
+            program.database_path(&config),
+            Duration::from_secs(args.lock_timeout),
+        )?)
     };
+    let mut db =
+        Database::open_interactive(program.database_path(&config), config.prompt_timeout())
+            .wrap_err("Failed to open the existing database")?;
+    db.configure(&config);
+    db.exact_search = args.no_normalize;
+
+    let db = execute(args.subcommand, db, &config)?;
+
+    // Dry runs, cancelled prompts and the like leave the database as it was, so there's nothing to
+    // write.
+    if !read_only {
+        db.sync_if_changed()
+            .wrap_err("Failed to sync database to disk")?;
+    } else if db.accessed && !args.no_lock {
+        save_access_times(&db);
+    }
+    lock.map_or(Ok(()), program::Lock::release)
+}
 
-    match args.subcommand {
-        // Hopefully this isn't a bad idea :)
-        C::Init(_) => unsafe { unreachable_unchecked() },
-        C::New => db
-            .add_login_interactive()
-            .wrap_err("Failed to add a new login to the database")?,
-        C::Query(name) => db.query_interactive(name.name.as_deref()),
-        C::Remove => {
-            db.remove_interactive()
-                .wrap_err("Failed to remove a login from the database interactively")?;
+// With `--db-stdin` there's no file to lock or sync, and the configuration file isn't read either,
+// so that a database can be inspected somewhere Locket was never set up, such as in CI.
+fn run_with_stdin_db(args: Cli) -> Result<()> {
+    if !args.subcommand.works_without_file() {
+        bail!("`--db-stdin` only works with `query`, `export`, `review`, `stats`, and `diff`, since there's nowhere to write the database back to");
+    }
+
+    let config = Config::without_file().with_overrides(&args.overrides)?;
+    let mut db = Database::from_reader(io::stdin().lock())
+        .wrap_err("Failed to read the database from standard input")?;
+    db.fuzzy = config.fuzzy;
+    db.allow_password_commands = config.allow_password_commands;
+    db.exact_search = args.no_normalize;
+
+    execute(args.subcommand, db, &config)?;
+    Ok(())
+}
+
+// Commands which only read the database don't hold the lock, so another instance may have changed
+// it since it was opened. The access times are copied into a fresh copy instead of writing `db`
+// back. They aren't worth waiting or failing for, so if the lock is held they're dropped, and they
+// aren't worth a backup either.
+fn save_access_times(db: &Database) {
+    let Ok(lock) = program::lock(&db.path, Duration::ZERO) else {
+        log::debug!("Not saving access times, another instance has the lock");
+        return;
+    };
+    let saved = Database::open(&db.path).and_then(|mut fresh| {
+        fresh.merge_access_times(db);
+        fresh.sync_without_backup()
+    });
+    if let Err(e) = saved.and_then(|()| lock.release()) {
+        log::warn!("Failed to save access times: {e:#}");
+    }
+}
+
+// Runs everything but `init`, `doctor`, `config`, `paths`, `fix-permissions`, `completions`, and
+// `generate`, which are handled before the database is opened.
+// Servers need to own the database while they share it between their threads, so it's given back
+// afterwards to be synced.
+fn execute(subcommand: args::Subcommands, mut db: Database, config: &Config) -> Result<Database> {
+    use args::Subcommands as C;
+
+    let timeout = config.prompt_timeout();
+
+    match subcommand {
+        // Handled by `run()` before the database is opened.
+        C::Init(_)
+        | C::Doctor
+        | C::Config(_)
+        | C::Paths(_)
+        | C::Completions(_)
+        | C::Generate(_) => {
+            unreachable!("`{subcommand:?}` is handled before the database is opened")
+        }
+        #[cfg(unix)]
+        C::FixPermissions => {
+            unreachable!("`fix-permissions` is handled before the database is opened")
         }
+        C::New(new_args) => new(&mut db, &new_args, config)?,
+        C::Query(query_args) => query(&mut db, query_args, config)?,
+        C::Show(ShowArgs {
+            login,
+            show_passwords,
+        }) => db
+            .show_interactive(login.as_deref(), timeout, show_passwords)
+            .wrap_err("Failed to show a login")?,
+        C::Remove(remove_args) => remove(&mut db, &remove_args, timeout)?,
+        C::Favorite => db
+            .toggle_favorite_interactive(timeout)
+            .wrap_err("Failed to toggle a favorite interactively")?,
+        C::Rename(RenameArgs { login, to, dry_run }) => db
+            .rename_interactive(login.as_deref(), to, dry_run, timeout)
+            .wrap_err("Failed to rename a login")?,
+        C::Attach(AttachArgs { login, file, name }) => db
+            .attach_interactive(&login, &file, name, timeout)
+            .wrap_err("Failed to attach the file")?,
+        C::Extract(ExtractArgs { login, name, out }) => db
+            .extract_interactive(&login, &name, out.as_deref(), timeout)
+            .wrap_err("Failed to save the attachment")?,
+        C::Import(args) => db
+            .import_interactive(&args, timeout)
+            .wrap_err("Failed to import logins")?,
+        C::Merge(args) => db
+            .merge_interactive(&args)
+            .wrap_err("Failed to merge the other database")?,
+        C::Review => review::review(&db),
+        C::Stats(StatsArgs { format }) => {
+            stats::stats(&db, format).wrap_err("Failed to print the statistics")?;
+        }
+        C::Compact(CompactArgs { dry_run }) => db
+            .compact_interactive(dry_run)
+            .wrap_err("Failed to compact the database")?,
+        C::Diff(DiffArgs { path, json }) => db
+            .diff_interactive(&path, json)
+            .wrap_err("Failed to compare with the other database")?,
+        C::Export(ExportArgs {
+            path,
+            format,
+            bare,
+            redacted,
+            since,
+        }) => db
+            .export(path.as_deref(), format, bare, redacted, since)
+            .wrap_err("Failed to export logins")?,
+        #[cfg(not(feature = "web"))]
+        C::Serve(_) => unreachable!("`serve` is refused by builds without the `web` feature"),
         #[cfg(feature = "web")]
-        C::Serve => {
-            net::serve(&mut db, config.port, &lck_path).wrap_err("Failed to serve webpage")?;
+        C::Serve(args::ServeArgs { watch }) => {
+            db = serve_shared(db, |db| {
+                net::serve(db, config, watch).wrap_err("Failed to serve webpage")
+            })?;
         }
-    };
+        #[cfg(all(unix, feature = "socket"))]
+        C::Socket(args::SocketArgs { path }) => {
+            let path = path.unwrap_or_else(|| db.path.with_file_name("locket.sock"));
+            db = serve_shared(db, |db| {
+                socket::serve(db, &path).wrap_err("Failed to serve the socket")
+            })?;
+        }
+    }
+
+    Ok(db)
+}
 
-    db.sync().wrap_err("Failed to sync database to disk")?;
-    if let Err(err) = fs::remove_file(lck_path) {
-        match err.kind() {
-            ErrorKind::NotFound => {
-                // TODO: Improve this message.
-                eprintln!("Tried to remove the lockfile, but it was already gone");
-                std::process::exit(1);
+// Shares the database between the threads of a server for as long as `serve` runs, then takes it
+// back so that it can be synced.
+#[cfg(any(feature = "web", all(unix, feature = "socket")))]
+fn serve_shared(
+    db: Database,
+    serve: impl FnOnce(&Arc<Mutex<Database>>) -> Result<()>,
+) -> Result<Database> {
+    let shared = Arc::new(Mutex::new(db));
+    serve(&shared)?;
+
+    match Arc::try_unwrap(shared) {
+        Ok(db) => Ok(db.into_inner().unwrap_or_else(PoisonError::into_inner)),
+        // A request which didn't finish in time still holds a reference. As long as it isn't
+        // using the database right now, the database can be taken from under it, since the
+        // process is about to exit anyway.
+        Err(shared) => match shared.try_lock() {
+            Ok(mut db) => Ok(mem::take(&mut *db)),
+            Err(TryLockError::Poisoned(e)) => Ok(mem::take(&mut *e.into_inner())),
+            Err(TryLockError::WouldBlock) => {
+                bail!("The database was still in use after the server shut down")
             }
-            _ => bail!("Failed to remove the lockfile: {}", err),
+        },
+    }
+}
+
+// A template which isn't in the configuration file is an error before anything is asked for.
+fn new(db: &mut Database, args: &NewArgs, config: &Config) -> Result<()> {
+    let template = args
+        .template
+        .as_deref()
+        .map(|name| config.template(name))
+        .transpose()?;
+
+    if args.stdin {
+        return db
+            .add_logins_from(io::stdin().lock(), template)
+            .wrap_err("Failed to add the logins from standard input");
+    }
+
+    let generated = args
+        .generate
+        .then(|| generate::generate(&args.generator))
+        .transpose()?;
+    let given = GivenFields {
+        name: args.name.clone(),
+        username: args.username.clone(),
+        password: generated.clone(),
+    };
+    let generate_on_empty = config.generate_on_empty_password.then_some(&args.generator);
+    db.add_login_interactive(template, given, generate_on_empty, config.prompt_timeout())
+        .wrap_err("Failed to add a new login to the database")?;
+
+    // Only shown once the login has been added, so that a password which was never stored isn't
+    // put to use.
+    if let Some(password) = generated {
+        if args.copy {
+            clipboard::copy(&password).wrap_err(
+                "The login was added, but its password couldn't be copied, use `locket show` to see it",
+            )?;
+            status!("Copied the generated password to the clipboard");
+        } else {
+            println!("{password}");
         }
+    }
+    Ok(())
+}
+
+fn remove(db: &mut Database, args: &RemoveArgs, timeout: Option<Duration>) -> Result<()> {
+    let removed = if args.multi {
+        db.remove_many_interactive(timeout, args.dry_run, args.yes)
+            .wrap_err("Failed to remove logins from the database interactively")?
+    } else {
+        db.remove_interactive(timeout, args.dry_run)
+            .wrap_err("Failed to remove a login from the database interactively")?
+            .into_iter()
+            .collect()
     };
+    for removed in removed {
+        status!("{}", Message::Removed { login: &removed });
+    }
+    Ok(())
+}
+
+// Scripts using `--first` or `--raw` get an exit code of 1 when nothing matches, so they can tell
+// without having to parse the output.
+fn query(db: &mut Database, mut args: QueryArgs, config: &Config) -> Result<()> {
+    // The configured behaviour only replaces a bare `locket query`, so any options given for
+    // scripting are still respected.
+    if args.name.is_none() {
+        match config.default_query_behavior {
+            QueryBehavior::Favorites => args.favorites = true,
+            QueryBehavior::Interactive
+                if matches!(args.output, OutputFormat::Table)
+                    && !(args.favorites || args.first || args.raw) =>
+            {
+                return db
+                    .show_interactive(None, config.prompt_timeout(), args.show_passwords)
+                    .wrap_err("Failed to show a login");
+            }
+            QueryBehavior::All | QueryBehavior::Interactive => (),
+        }
+    }
+
+    if args.raw {
+        args.output = OutputFormat::Plain;
+    }
+    let found = db
+        .query_interactive(&args, config.terminal_reveal())
+        .wrap_err("Failed to show the matching logins")?;
+    // `query` never changes the database, so there's nothing to lose by exiting here.
+    if !found && (args.first || args.raw) {
+        process::exit(1);
+    }
+
     Ok(())
 }
@@ -1,39 +1,52 @@
-use std::env;
+use std::{
+    env,
+    io::{self, IsTerminal},
+};
 
-use clap::Parser;
-use color_eyre::eyre::{eyre, Context};
-use log::error;
+use clap::{CommandFactory, FromArgMatches};
+use color_eyre::eyre::Context;
+use locket::args::Cli;
+use pretty_env_logger::env_logger::WriteStyle;
 
 // TODO: Find a way to exit properly, instead of using `std::process::exit()`.
 // This might give us a way to run destructors automagically. However, it may
 // not work with color_eyre, so research is needed :^).
 fn main() -> color_eyre::Result<()> {
-    let args = locket::args::Cli::parse();
+    // `--version` works without a subcommand, so it's looked for before they're required, when
+    // the matches are turned into `Cli`.
+    let matches = Cli::command().get_matches();
+    if matches.get_flag("version") {
+        if matches.get_flag("json") {
+            println!("{}", locket::version_json());
+        } else {
+            print!("{}", Cli::command().render_version());
+        }
+        return Ok(());
+    }
+    let args =
+        Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.format(&mut Cli::command()).exit());
     color_eyre::install()?;
-    match env::vars().find(|(var, _)| var == "LOCKET_LOG") {
-        Some((_, value)) => {
-            match pretty_env_logger::formatted_builder()
-                .parse_env(value)
-                .try_init()
-            {
-                Ok(_) => (),
-                Err(e) => {
-                    pretty_env_logger::formatted_builder()
-                        .filter_level(args.verbosity.log_level_filter())
-                        .try_init()
-                        .wrap_err_with(|| eyre!("Failed to initialise pretty_env_logger, already failed to parse LOCKET_LOG with error: {e}"))?;
 
-                    error!("Failed to parse LOCKET_LOG with error: {e}");
-                }
-            };
-        }
-        None => {
-            pretty_env_logger::formatted_builder()
-                .filter_level(args.verbosity.log_level_filter())
-                .try_init()
-                .wrap_err("Failed to initialise pretty_env_logger")?;
-        }
+    let mut builder = pretty_env_logger::formatted_builder();
+    if env::var_os("NO_COLOR").is_some() || !io::stderr().is_terminal() {
+        builder.write_style(WriteStyle::Never);
     }
 
+    // The `-v`/`-q` flags take precedence over `LOCKET_LOG`, which takes precedence over the
+    // configuration file, which takes precedence over the default level of `clap_verbosity_flag`.
+    if args.verbosity.is_present() {
+        builder.filter_level(args.verbosity.log_level_filter());
+    } else if let Ok(filters) = env::var("LOCKET_LOG") {
+        builder.parse_filters(&filters);
+    } else if let Some(level) = locket::configured_log_level(&args.overrides) {
+        builder.filter_level(level);
+    } else {
+        builder.filter_level(args.verbosity.log_level_filter());
+    }
+
+    builder
+        .try_init()
+        .wrap_err("Failed to initialise pretty_env_logger")?;
+
     locket::run(args)
 }
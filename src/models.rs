@@ -1,60 +1,633 @@
 use std::io::ErrorKind;
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    cmp::Reverse,
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet},
     fmt::Display,
     fs,
     fs::{File, OpenOptions},
-    io::{prelude::*, BufReader, BufWriter},
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{prelude::*, BufReader, BufWriter, IsTerminal},
     path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use color_eyre::eyre::{bail, Context, Result};
+use chrono::{DateTime, SubsecRound, Utc};
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use console::style;
 use dialoguer::theme::ColorfulTheme;
-use dialoguer::{FuzzySelect, Input, Password};
+use dialoguer::{Confirm, FuzzySelect, Input, MultiSelect, Password, Select};
+use fs2::FileExt;
 use itertools::Itertools;
+use log::{debug, warn, LevelFilter};
 use serde_derive::{Deserialize, Serialize};
+use serde_with::{base64::Base64, serde_as, Bytes, IfIsHumanReadable};
 use tabled::{
+    builder::Builder,
     settings::Style,
     tables::{PoolTable, TableValue},
-    Table, Tabled,
 };
 use uuid::Uuid;
 
-use crate::errors::LocketError;
+use crate::args::{
+    ExportFormat, GenerateArgs, ImportArgs, ImportFormat, LoginField, MergeArgs, MergePolicy,
+    OutputFormat, QueryArgs, QuerySort,
+};
+use crate::errors::{AddError, AttachError, CorruptDatabase, LocketError, LoginWarning};
+use crate::i18n::Message;
+use crate::progress::ImportProgress;
+use crate::prompt::prompt;
+use crate::redact::RedactedDatabase;
+use crate::reference::PasswordReference;
+use crate::{clipboard, export, generate, import, pager, permissions, reveal};
 
-#[derive(Serialize, Deserialize)]
+/// The settings from the configuration file, which also says where the database is.
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Config {
     pub path: PathBuf,
+    /// Configuration files written by a build of Locket without the `web` feature don't have
+    /// one, in which case `serve` uses the port `init` suggests.
     #[cfg(feature = "web")]
+    #[serde(default = "default_port")]
     pub port: u16,
+    /// Used when neither `-v`/`-q` nor `LOCKET_LOG` are given.
+    #[serde(default)]
+    pub log_level: Option<LevelFilter>,
+    /// How long to wait for an answer to a prompt before giving up, 0 means forever.
+    #[serde(default)]
+    pub prompt_timeout_secs: u64,
+    /// How long passwords shown by `query --show-passwords` stay on the screen before they're
+    /// cleared, 0 means they're left there. Only applies when printing to a terminal. Rows which
+    /// have scrolled out of view can't be cleared, so this is best with output that fits.
+    #[serde(default)]
+    pub terminal_reveal_secs: u64,
+    /// How many backups of the database to keep, 0 turns them off. A new one is made on each sync.
+    #[serde(default)]
+    pub backup_retention: usize,
+    /// What `query` does when it isn't given a name.
+    #[serde(default)]
+    pub default_query_behavior: QueryBehavior,
+    /// How searches match login names, in a `[fuzzy]` section of its own.
+    #[serde(default)]
+    pub fuzzy: FuzzyConfig,
+    /// If set, the web UI's static files are read from here when they exist, instead of using the
+    /// ones built into Locket.
+    #[cfg(feature = "web")]
+    #[serde(default)]
+    pub web_assets_dir: Option<PathBuf>,
+    /// How long the server remembers an `Idempotency-Key` sent to `/api/v1/new`, 0 turns them off.
+    #[cfg(feature = "web")]
+    #[serde(default = "default_idempotency_ttl_secs")]
+    pub idempotency_ttl_secs: u64,
+    /// How many requests `serve` handles at once, between 1 and 64.
+    #[cfg(feature = "web")]
+    #[serde(default = "default_web_workers")]
+    pub web_workers: usize,
+    /// Whether `serve` lets `/api/v1/snapshot` download the whole database, passwords and all. Off
+    /// by default, since the API has no authentication.
+    #[cfg(feature = "web")]
+    #[serde(default)]
+    pub allow_snapshot_download: bool,
+    /// The largest file which can be attached to a login, 0 means there's no limit.
+    #[serde(default = "default_max_attachment_bytes")]
+    pub max_attachment_bytes: u64,
+    /// Whether passwords written as `cmd:` followed by a command are found by running it. Off by
+    /// default, since anyone who can add a login could otherwise run commands as you.
+    #[serde(default)]
+    pub allow_password_commands: bool,
+    /// The most logins the database can hold, 0 means there's no limit. Adding logins beyond it
+    /// fails, rather than letting a runaway script or client fill the disk.
+    #[serde(default)]
+    pub max_logins: usize,
+    /// Whether `new` generates a password when the password prompt is left empty, instead of
+    /// asking whether to store an empty one. It's generated as `generate` would, with the
+    /// generator flags given to `new`.
+    #[serde(default)]
+    pub generate_on_empty_password: bool,
+    /// Values to start new logins with, each in a `[templates.<name>]` section and chosen with
+    /// `new --template <name>`.
+    #[serde(default)]
+    pub templates: BTreeMap<String, Template>,
+    // The file this was read from and the `--set` overrides applied on top, so that `serve --watch`
+    // can read it again the same way.
+    #[cfg(feature = "web")]
+    #[serde(skip)]
+    file: Option<PathBuf>,
+    #[cfg(feature = "web")]
+    #[serde(skip)]
+    overrides: Vec<(String, String)>,
+}
+
+#[cfg(feature = "web")]
+fn default_port() -> u16 {
+    DEFAULT_PORT
+}
+
+fn default_max_attachment_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+#[cfg(feature = "web")]
+fn default_idempotency_ttl_secs() -> u64 {
+    10 * 60
+}
+
+// One per CPU, falling back to the number the server used before this was configurable.
+#[cfg(feature = "web")]
+fn default_web_workers() -> usize {
+    std::thread::available_parallelism().map_or(4, std::num::NonZeroUsize::get)
+}
+
+// Configuration files are shared between builds of Locket with and without the `web` feature, such
+// as when one is installed from a package and another built from source. Without it, the settings
+// for `serve` would otherwise be silently ignored. The file is read more than once by some commands,
+// so this only warns the first time.
+#[cfg(not(feature = "web"))]
+fn warn_about_web_settings(content: &str) {
+    use std::sync::Once;
+
+    static WARNED: Once = Once::new();
+
+    let Ok(table) = content.parse::<toml::Table>() else {
+        // Reported when the file is parsed properly.
+        return;
+    };
+    let present: Vec<String> = WEB_KEYS
+        .iter()
+        .filter(|key| table.contains_key(**key))
+        .map(|key| format!("`{key}`"))
+        .collect();
+    if present.is_empty() {
+        return;
+    }
+
+    WARNED.call_once(|| {
+        eprintln!(
+            "{}",
+            style(format!(
+                "! This build of Locket was compiled without the `web` feature, so {} in the configuration file are ignored",
+                present.join(", ")
+            ))
+            .yellow()
+        );
+    });
+}
+
+// Paths which aren't UTF-8 can't contain anything to expand, so they're left as they are.
+fn expand_path(path: &Path) -> Result<PathBuf> {
+    let Some(path) = path.to_str() else {
+        return Ok(PathBuf::from(path));
+    };
+
+    shellexpand::full(path)
+        .map(|expanded| PathBuf::from(expanded.as_ref()))
+        .map_err(|e| {
+            eyre!(
+                "Failed to expand `{path}` from the configuration file, the environment variable `{}` isn't set",
+                e.var_name
+            )
+        })
+}
+
+/// What `query` does when it isn't given a name, see [`Config::default_query_behavior`].
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryBehavior {
+    /// Show every login.
+    #[default]
+    All,
+    /// Choose a login with the fuzzy selector, then show everything about it like `show` does.
+    Interactive,
+    /// Show only the favorites, as if `--favorites` had been given.
+    Favorites,
+}
+
+/// Tuning for the fuzzy matching used to search for logins by name, by `query`, the fuzzy selector,
+/// and the web and socket APIs. Leaving these at their defaults searches the same way as Locket
+/// always has.
+///
+/// Any of them is safe to change, since they only affect which logins are found and the order
+/// they're in, never what's stored. Whether accents are ignored is set with `--no-normalize`
+/// instead, and nucleo doesn't allow the scoring weights themselves to be changed.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FuzzyConfig {
+    /// Rank matches nearer the start of a name higher, so that `git` puts `GitHub` above
+    /// `Digital Ocean`. Best when you tend to type the start of the name you're after.
+    pub prefer_prefix: bool,
+    /// Score names as if they were paths, favouring matches which start just after a `/`, for
+    /// names with a consistent structure such as `work/aws/prod`.
+    pub match_paths: bool,
+    /// How capital letters in the search are treated.
+    pub case: FuzzyCase,
+}
+
+/// See [`FuzzyConfig::case`].
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FuzzyCase {
+    /// Ignore the case of both the search and the names.
+    #[default]
+    Ignore,
+    /// Ignore case unless the search has a capital letter in it, like `vim`'s `smartcase`.
+    Smart,
+    /// Always match case exactly.
+    Respect,
+}
+
+impl FuzzyConfig {
+    // Starts from nucleo's defaults, so that settings which are left alone keep their behaviour.
+    fn matcher_config(self) -> nucleo_matcher::Config {
+        let mut config = nucleo_matcher::Config::DEFAULT;
+        if self.match_paths {
+            config.set_match_paths();
+        }
+        config.prefer_prefix = self.prefer_prefix;
+        config
+    }
+
+    fn case_matching(self) -> nucleo_matcher::pattern::CaseMatching {
+        use nucleo_matcher::pattern::CaseMatching;
+
+        match self.case {
+            FuzzyCase::Ignore => CaseMatching::Ignore,
+            FuzzyCase::Smart => CaseMatching::Smart,
+            FuzzyCase::Respect => CaseMatching::Respect,
+        }
+    }
+}
+
+/// Values to start a new login with, for adding many similar logins, such as those which share a
+/// username. They're only used when adding a login, so changing a template doesn't change the
+/// logins made from it.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Template {
+    /// Offered as the username, which can be changed before it's saved.
+    pub username: Option<String>,
+    /// Offered as the folder, which can be changed before it's saved.
+    pub folder: Option<String>,
+    /// Added to the login's tags.
+    pub tags: Vec<String>,
+    /// The login's url, with `{name}` replaced by the login's name, such as
+    /// `https://{name}.example.com/login`.
+    pub url: Option<String>,
+}
+
+impl Template {
+    // Adds the tags `login` doesn't have already, and the url if it has none. The username and
+    // folder are left to the prompts, so that clearing them sticks.
+    fn add_tags_and_url(&self, login: &mut Login) {
+        for tag in &self.tags {
+            if !login.tags.contains(tag) {
+                login.tags.push(tag.clone());
+            }
+        }
+        if let (true, Some(url)) = (login.url.is_empty(), &self.url) {
+            login.url = url.replace("{name}", &login.name);
+        }
+    }
+
+    // For logins which weren't prompted for, the username and folder are filled in too, if they're
+    // empty.
+    fn apply(&self, login: &mut Login) {
+        if let (true, Some(username)) = (login.username.is_empty(), &self.username) {
+            login.username.clone_from(username);
+        }
+        if login.folder.is_none() {
+            login.folder = self.folder.as_deref().and_then(normalise_folder);
+        }
+        self.add_tags_and_url(login);
+    }
+}
+
+// The fields of a new login which were given on the command line, rather than asked for.
+#[derive(Debug, Default)]
+pub(crate) struct GivenFields {
+    pub name: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
 }
 
+// How many times to try generating an id for a new login before giving up.
+const MAX_ID_ATTEMPTS: usize = 8;
+
+const BACKUP_PREFIX: &str = "locket-";
+const BACKUP_SUFFIX: &str = ".db";
+
+// Suggested by `init`, and used by `serve` when the configuration file has no port.
+#[cfg(feature = "web")]
+const DEFAULT_PORT: u16 = 56423;
+
+// The settings which are only used by `serve`. Builds without the `web` feature ignore them, and
+// warn when they're in the configuration file.
+#[cfg(not(feature = "web"))]
+const WEB_KEYS: &[&str] = &[
+    "port",
+    "web_assets_dir",
+    "idempotency_ttl_secs",
+    "web_workers",
+    "allow_snapshot_download",
+];
+
+// The settings which can be given to `--set`. This has to be kept in step with `Config`.
+pub(crate) const CONFIG_KEYS: &[&str] = &[
+    "path",
+    #[cfg(feature = "web")]
+    "port",
+    "log_level",
+    "prompt_timeout_secs",
+    "terminal_reveal_secs",
+    "backup_retention",
+    "default_query_behavior",
+    "fuzzy.prefer_prefix",
+    "fuzzy.match_paths",
+    "fuzzy.case",
+    #[cfg(feature = "web")]
+    "web_assets_dir",
+    #[cfg(feature = "web")]
+    "idempotency_ttl_secs",
+    #[cfg(feature = "web")]
+    "web_workers",
+    #[cfg(feature = "web")]
+    "allow_snapshot_download",
+    "max_attachment_bytes",
+    "allow_password_commands",
+    "max_logins",
+    "generate_on_empty_password",
+];
+
+/// Bumped whenever the format of the database changes in a way older versions can't read.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Every login, along with where they're saved. Changes are only written to the file by
+/// [`Database::sync()`].
+///
+/// `Database` does no locking of its own. When it's shared between threads, as it is by `serve`, it
+/// must be kept behind a `Mutex`, and the lock held for the whole of each operation, including
+/// building responses from the results of `query()` and writing the file in `sync()`. That way a
+/// reader waits for a sync in progress to finish, rather than seeing a half-applied change.
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct Database {
     pub logins: HashMap<Uuid, Login>,
+    /// Databases written before this was added don't have it, so they're treated as version 0.
+    #[serde(default)]
+    pub version: u32,
     #[serde(skip)]
     pub path: PathBuf,
+    /// Copied from the configuration after opening the database, see `Config::backup_retention`.
+    #[serde(skip)]
+    pub backup_retention: usize,
+    /// Likewise, see `Config::max_attachment_bytes`.
+    #[serde(skip)]
+    pub max_attachment_bytes: u64,
+    /// Likewise, see [`Config::fuzzy`].
+    #[serde(skip)]
+    pub fuzzy: FuzzyConfig,
+    /// Likewise, see [`Config::allow_password_commands`].
+    #[serde(skip)]
+    pub allow_password_commands: bool,
+    /// Likewise, see [`Config::max_logins`].
+    #[serde(skip)]
+    pub max_logins: usize,
+    /// Set by `--no-normalize`, to match accented letters exactly when searching.
+    #[serde(skip)]
+    pub exact_search: bool,
+    // Whether a login has been accessed since the database was opened, so that commands which
+    // otherwise only read the database know to save the new access times.
+    #[serde(skip)]
+    pub(crate) accessed: bool,
+    // A hash of the database as it was last read from or written to its file, so that
+    // `sync_if_changed()` can tell whether there's anything to write. `None` if it's been neither,
+    // as with a database which has just been created.
+    #[serde(skip)]
+    written: Mutex<Option<u64>>,
+    // `version` as it was in the file, before it was upgraded to `SCHEMA_VERSION`.
+    #[serde(skip)]
+    file_version: u32,
 }
 
-#[derive(Debug, Serialize, Deserialize, Tabled)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "web", derive(schemars::JsonSchema))]
 pub struct Login {
     pub name: String,
     pub username: String,
     pub password: String,
+    #[serde(default)]
+    pub favorite: bool,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub notes: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Anything else that needs to be kept with the login, such as security questions or recovery
+    /// codes. They aren't shown in `query`'s table.
+    #[serde(default)]
+    pub custom_fields: HashMap<String, String>,
+    /// The keys of the custom fields which are masked like passwords.
+    #[serde(default)]
+    pub secret_fields: HashSet<String>,
+    /// When the login was last shown on its own, for `query --sort recent`. Listing logins doesn't
+    /// count. Stored as seconds since the Unix epoch, as it was before it was a `DateTime`.
+    #[serde(default, with = "chrono::serde::ts_seconds_option")]
+    #[cfg_attr(feature = "web", schemars(with = "Option<i64>"))]
+    pub last_accessed: Option<DateTime<Utc>>,
+    /// Where the login is filed, as a path of folders separated by slashes, such as `Work/Cloud`.
+    /// Unlike tags, each login is in at most one folder.
+    #[serde(default)]
+    pub folder: Option<String>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    /// When the login was added or last changed in this database, for `export --since`. Logins
+    /// from before this was recorded don't have it. Stored like [`Login::last_accessed`].
+    #[serde(default, with = "chrono::serde::ts_seconds_option")]
+    #[cfg_attr(feature = "web", schemars(with = "Option<i64>"))]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// A file kept with a login, such as a recovery key. The contents are stored as they are in the
+/// database, but as base64 in JSON, where an array of numbers would be several times the size.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "web", derive(schemars::JsonSchema))]
+pub struct Attachment {
+    pub name: String,
+    #[serde_as(as = "IfIsHumanReadable<Base64, Bytes>")]
+    #[cfg_attr(feature = "web", schemars(with = "String"))]
+    pub bytes: Vec<u8>,
+}
+
+// Above this, every change to the database starts to take noticeably longer to save, since the
+// whole file is rewritten each time.
+const LARGE_DATABASE_BYTES: u64 = 64 * 1024 * 1024;
+
+// The fields shown by `query` when `--fields` isn't given.
+const DEFAULT_FIELDS: &[LoginField] = &[
+    LoginField::Name,
+    LoginField::Username,
+    LoginField::Password,
+    LoginField::Favorite,
+    LoginField::Url,
+];
+
+// Shown in place of passwords unless `--show-passwords` is given.
+const HIDDEN_PASSWORD: &str = "********";
+
+/// The outcome of importing a set of logins into the database, computed without modifying it. The
+/// `--dry-run` preview and the real import both use the same plan, so they can't disagree.
+#[derive(Debug, Default)]
+pub struct ImportPlan {
+    pub new: Vec<Login>,
+    pub duplicates: Vec<Login>,
+    /// Logins with the same name and username as one already in the database, but which differ
+    /// from it. Each is dealt with by its own `resolution`.
+    pub conflicts: Vec<ImportConflict>,
+    pub malformed: Vec<String>,
+}
+
+/// A login being imported which has the same name and username as one in the database, but
+/// differs from it.
+#[derive(Debug)]
+pub struct ImportConflict {
+    /// The id of the login already in the database.
+    pub existing: Uuid,
+    pub login: Login,
+    pub resolution: MergePolicy,
+}
+
+/// What `Database::merge()` did, or would do, with each login from the other database.
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    pub added: Vec<String>,
+    pub overwritten: Vec<String>,
+    pub skipped: Vec<String>,
+    /// Logins which differed from one in this database with the same id, or the same name and
+    /// username. Each of them is also in one of the other lists, depending on the policy.
+    pub conflicted: usize,
+}
+
+/// What `Database::compact()` removed.
+#[derive(Debug, Default)]
+pub struct CompactReport {
+    /// Secret fields naming custom fields which the login doesn't have.
+    pub dangling_secret_fields: usize,
+    /// Tags which were empty, or which the login already had.
+    pub redundant_tags: usize,
+}
+
+/// How two databases differ, from the point of view of this one, see `Database::diff()`. Logins are
+/// described by name and username, and secrets are masked, so the report is safe to print.
+#[derive(Debug, Default, Serialize)]
+pub struct DiffReport {
+    pub only_here: Vec<String>,
+    pub only_there: Vec<String>,
+    pub changed: Vec<ChangedLogin>,
+    pub unchanged: usize,
+}
+
+/// A login which is in both databases, but differs between them.
+#[derive(Debug, Serialize)]
+pub struct ChangedLogin {
+    pub login: String,
+    pub fields: Vec<FieldChange>,
+}
+
+/// One field of a [`ChangedLogin`], with its value in each database.
+#[derive(Debug, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub here: String,
+    pub there: String,
 }
 
 impl Config {
+    fn new(db_path: &Path, #[cfg(feature = "web")] port: u16) -> Self {
+        Self {
+            path: PathBuf::from(db_path),
+            #[cfg(feature = "web")]
+            port,
+            log_level: None,
+            prompt_timeout_secs: 0,
+            terminal_reveal_secs: 0,
+            backup_retention: 0,
+            default_query_behavior: QueryBehavior::All,
+            fuzzy: FuzzyConfig::default(),
+            #[cfg(feature = "web")]
+            web_assets_dir: None,
+            #[cfg(feature = "web")]
+            idempotency_ttl_secs: default_idempotency_ttl_secs(),
+            #[cfg(feature = "web")]
+            web_workers: default_web_workers(),
+            #[cfg(feature = "web")]
+            allow_snapshot_download: false,
+            max_attachment_bytes: default_max_attachment_bytes(),
+            allow_password_commands: false,
+            max_logins: 0,
+            generate_on_empty_password: false,
+            templates: BTreeMap::new(),
+            #[cfg(feature = "web")]
+            file: None,
+            #[cfg(feature = "web")]
+            overrides: Vec::new(),
+        }
+    }
+
+    // The template called `name`, or an error listing the ones there are.
+    pub(crate) fn template(&self, name: &str) -> Result<&Template> {
+        self.templates.get(name).ok_or_else(|| {
+            if self.templates.is_empty() {
+                eyre!("There is no template called `{name}`, and none are set up in the configuration file")
+            } else {
+                eyre!(
+                    "There is no template called `{name}`, the templates are {}",
+                    self.templates.keys().map(|name| format!("`{name}`")).join(", ")
+                )
+            }
+        })
+    }
+
+    // The settings used when there's no configuration file to read them from, as with
+    // `--db-stdin`. Nothing can be served in that mode, so the port doesn't matter.
+    pub(crate) fn without_file() -> Self {
+        Self::new(
+            Path::new(""),
+            #[cfg(feature = "web")]
+            0,
+        )
+    }
+
+    /// How long to wait for an answer to a prompt, or `None` to wait forever.
+    #[must_use]
+    pub fn prompt_timeout(&self) -> Option<Duration> {
+        (self.prompt_timeout_secs != 0).then(|| Duration::from_secs(self.prompt_timeout_secs))
+    }
+
+    /// How long shown passwords stay in the terminal, or `None` to leave them there.
+    #[must_use]
+    pub fn terminal_reveal(&self) -> Option<Duration> {
+        (self.terminal_reveal_secs != 0).then(|| Duration::from_secs(self.terminal_reveal_secs))
+    }
+
+    /// Writes `config` to a new configuration file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file already exists or couldn't be written.
     pub fn init(path: &Path, config: &Config) -> Result<()> {
         let exists = path
             .try_exists()
             .wrap_err("Failed to check whether the configuration file already exists")?;
 
         if exists {
-            bail!(LocketError::ConfigAlreadyExistsError);
+            bail!(LocketError::ConfigAlreadyExists);
         }
 
-        let mut writer =
-            BufWriter::new(File::create(path).wrap_err("Failed to create configuration file")?);
+        let file = permissions::private(OpenOptions::new().write(true).create(true).truncate(true))
+            .open(path)
+            .wrap_err("Failed to create configuration file")?;
+        let mut writer = BufWriter::new(file);
         let buf = toml::ser::to_string_pretty(config)
             .wrap_err("Failed to serialise configuration file")?;
         writer
@@ -64,51 +637,52 @@ impl Config {
         Ok(())
     }
 
+    // Builds without the `web` feature have no port, so `port` is ignored and nothing is asked.
+    #[cfg_attr(not(feature = "web"), allow(unused_variables))]
     pub(crate) fn init_interactive(path: &Path, db_path: &Path, port: Option<u16>) -> Result<Self> {
-        if let Some(port) = port {
-            let config = Config {
-                path: PathBuf::from(db_path),
-                #[cfg(feature = "web")]
-                port,
-            };
-            Self::init(path, &config).wrap_err(
-                "Failed to initialise configuration file after interactively getting config",
-            )?;
+        #[cfg(feature = "web")]
+        let port = match port {
+            Some(port) => port,
+            None => Self::prompt_port()?,
+        };
 
-            return Ok(config);
-        }
+        let config = Self::new(
+            db_path,
+            #[cfg(feature = "web")]
+            port,
+        );
+
+        Self::init(path, &config).wrap_err(
+            "Failed to initialise configuration file after interactively getting config",
+        )?;
 
-        let theme = ColorfulTheme::default();
+        Ok(config)
+    }
 
-        #[cfg(feature = "web")]
-        let port = dialoguer::Input::<u16>::with_theme(&theme)
-            .with_prompt("Enter the port number for the server")
-            .default(56423)
+    #[cfg(feature = "web")]
+    fn prompt_port() -> Result<u16> {
+        dialoguer::Input::<u16>::with_theme(&ColorfulTheme::default())
+            .with_prompt(Message::PortPrompt.to_string())
+            .default(DEFAULT_PORT)
             .validate_with(|port: &u16| {
                 if 0 < *port && *port < u16::MAX {
                     Ok(())
                 } else {
-                    Err("Not a valid port number")
+                    Err(Message::InvalidPort.to_string())
                 }
             })
             .allow_empty(false)
             .interact_text()
-            .wrap_err("Failed to get port number")?;
-
-        let config = Config {
-            path: PathBuf::from(db_path),
-            #[cfg(feature = "web")]
-            port,
-        };
-
-        Self::init(path, &config).wrap_err(
-            "Failed to initialise configuration file after interactively getting config",
-        )?;
+            .wrap_err("Failed to get port number")
+    }
 
-        Ok(config)
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        permissions::check(path)?;
+        Self::read(path)
     }
 
-    fn open(path: &Path) -> Result<Self> {
+    // Like `open()`, but without checking who can read the file.
+    pub(crate) fn read(path: &Path) -> Result<Self> {
         let f = File::open(path).wrap_err("Failed to open file handle to configuration file")?;
         let mut reader = BufReader::new(f);
         let mut buf = String::with_capacity(
@@ -123,16 +697,106 @@ impl Config {
             .read_to_string(&mut buf)
             .wrap_err("Failed to read configuration file from disk")?;
 
-        toml::de::from_str(&buf).wrap_err("Failed to parse configuration file")
+        #[cfg(not(feature = "web"))]
+        warn_about_web_settings(&buf);
+        let config = toml::de::from_str::<Self>(&buf)
+            .wrap_err("Failed to parse configuration file")?
+            .expand_paths()?;
+        #[cfg(feature = "web")]
+        let config = Self {
+            file: Some(PathBuf::from(path)),
+            ..config
+        };
+        Ok(config)
+    }
+
+    #[cfg(feature = "web")]
+    pub(crate) fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    // Reads the configuration file again, with the same overrides, to pick up changes to it.
+    #[cfg(feature = "web")]
+    pub(crate) fn reload(&self) -> Result<Self> {
+        let Some(file) = &self.file else {
+            bail!("The configuration wasn't read from a file, so it can't be read again");
+        };
+        Self::open(file)?.with_overrides(&self.overrides)
+    }
+
+    // Expands environment variables and `~` in the paths, so that the same configuration file can
+    // be used on machines where they're in different places, e.g. `path = "$HOME/locket.db"`.
+    fn expand_paths(mut self) -> Result<Self> {
+        self.path = expand_path(&self.path)?;
+        #[cfg(feature = "web")]
+        if let Some(dir) = &self.web_assets_dir {
+            self.web_assets_dir = Some(expand_path(dir)?);
+        }
+
+        Ok(self)
+    }
+
+    // Applies `--set` overrides on top of the configuration. The values are parsed as TOML where
+    // possible, so `port=8080` is a number, and as plain strings otherwise, so `path=/tmp/x` needn't
+    // be quoted.
+    pub(crate) fn with_overrides(self, overrides: &[(String, String)]) -> Result<Self> {
+        if overrides.is_empty() {
+            return Ok(self);
+        }
+
+        let toml::Value::Table(mut table) =
+            toml::Value::try_from(&self).wrap_err("Failed to serialise the configuration")?
+        else {
+            bail!("The configuration didn't serialise to a table");
+        };
+
+        for (key, value) in overrides {
+            if !CONFIG_KEYS.contains(&key.as_str()) {
+                bail!(
+                    "Unknown setting `{key}`, the valid settings are: {}",
+                    CONFIG_KEYS.join(", ")
+                );
+            }
+
+            let value = toml::from_str::<toml::Table>(&format!("value = {value}"))
+                .ok()
+                .and_then(|mut parsed| parsed.remove("value"))
+                .unwrap_or_else(|| toml::Value::String(value.clone()));
+            // Settings in a section, such as `fuzzy.case`, are set within the section's table.
+            match key.split_once('.') {
+                Some((section, key)) => match table.get_mut(section) {
+                    Some(toml::Value::Table(section)) => {
+                        section.insert(String::from(key), value);
+                    }
+                    _ => bail!("The configuration has no `{section}` section"),
+                },
+                None => {
+                    table.insert(key.clone(), value);
+                }
+            }
+        }
+
+        let config = toml::Value::Table(table)
+            .try_into::<Self>()
+            .wrap_err("Invalid value given with `--set`")?
+            .expand_paths()?;
+        #[cfg(feature = "web")]
+        let config = Self {
+            file: self.file,
+            overrides: overrides.to_vec(),
+            ..config
+        };
+        Ok(config)
     }
 
-    pub(crate) fn open_interactive(path: &Path) -> Result<Self> {
+    // Like `open()`, but a missing file is reported as `LocketError::NotInitialised`, which
+    // `Program::open_config()` turns into advice on what to run.
+    pub(crate) fn open_initialised(path: &Path) -> Result<Self> {
         if !path
             .try_exists()
-            .wrap_err("Failed to check whether the database exists")?
+            .wrap_err("Failed to check whether the configuration file exists")?
         {
-            eprintln!("You have not initialised Locket yet, please run `locket init` to initialise, then run this command again.");
-            std::process::exit(0);
+            bail!(LocketError::NotInitialised);
         }
 
         Self::open(path).wrap_err("Failed to load configuration from disk")
@@ -140,19 +804,32 @@ impl Config {
 }
 
 impl Database {
+    // Copies the settings which the database uses from the configuration. `serve --watch` calls
+    // this again when the configuration file changes.
+    pub(crate) fn configure(&mut self, config: &Config) {
+        self.backup_retention = config.backup_retention;
+        self.max_attachment_bytes = config.max_attachment_bytes;
+        self.fuzzy = config.fuzzy;
+        self.allow_password_commands = config.allow_password_commands;
+        self.max_logins = config.max_logins;
+    }
+
+    /// Creates a new, empty database file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file already exists or couldn't be created.
     pub fn init(path: &Path) -> Result<Self> {
         // Discard the file descriptor because we don't need to actually write to the file on
         // initialisation, we only need to create the file. Ideally there would be an
         // `fs::create_file()`, but there is not.
-        if let Err(err) = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create_new(true)
-            .open(path)
+        if let Err(err) =
+            permissions::private(OpenOptions::new().read(true).write(true).create_new(true))
+                .open(path)
         {
             match err.kind() {
                 ErrorKind::AlreadyExists => {
-                    bail!(crate::errors::LocketError::DatabaseAlreadyExistsError)
+                    bail!(crate::errors::LocketError::DatabaseAlreadyExists)
                 }
                 _ => bail!("Failed to create a new database file: {err}"),
             };
@@ -160,11 +837,29 @@ impl Database {
 
         Ok(Self {
             logins: HashMap::new(),
+            version: SCHEMA_VERSION,
             path: PathBuf::from(path),
+            backup_retention: 0,
+            max_attachment_bytes: 0,
+            fuzzy: FuzzyConfig::default(),
+            allow_password_commands: false,
+            max_logins: 0,
+            exact_search: false,
+            accessed: false,
+            written: Mutex::new(None),
+            file_version: SCHEMA_VERSION,
         })
     }
 
+    /// Loads the database at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file couldn't be read or parsed, or was written by a newer version
+    /// of Locket, or if other users can read it and `--strict-permissions` was given.
     pub fn open(path: &Path) -> Result<Self> {
+        permissions::check(path)?;
+        let _read_lock = data_lock(path, false);
         let reader =
             BufReader::new(File::open(path).wrap_err("Failed to open file handle to database")?);
         let is_empty = match fs::metadata(path) {
@@ -175,159 +870,1945 @@ impl Database {
             },
         } == 0;
 
+        // A file which has only just been created has nothing to upgrade.
         let mut db = if is_empty {
-            Self::default()
+            Self {
+                version: SCHEMA_VERSION,
+                ..Self::default()
+            }
         } else {
-            rmp_serde::decode::from_read(reader).wrap_err("Failed to parse database contents")?
+            match rmp_serde::decode::from_read(reader) {
+                Ok(db) => db,
+                Err(e) => {
+                    return Err(e).wrap_err(CorruptDatabase {
+                        path: PathBuf::from(path),
+                        backup: Self::latest_readable_backup(path),
+                    })
+                }
+            }
         };
+        db.upgrade()?;
         db.path = PathBuf::from(path);
+        *db.written() = Some(content_hash(&db.encode()?));
 
         Ok(db)
     }
 
-    pub fn add_login(&mut self, login: Login) {
-        let id = Uuid::new_v4();
-        // TODO: However unlikely it is that there will be a collision, do proper things here.
-        let old_val = self.logins.insert(id, login);
-        assert!(old_val.is_none());
+    /// Loads a database from `reader`, such as a backup piped to standard input, without touching
+    /// the filesystem. The database has no path, so it can't be synced.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database couldn't be read or parsed, or was written by a newer
+    /// version of Locket.
+    pub fn from_reader(reader: impl Read) -> Result<Self> {
+        let mut db: Self =
+            rmp_serde::decode::from_read(reader).wrap_err("Failed to parse the database")?;
+        db.upgrade()?;
+        Ok(db)
     }
 
-    pub(crate) fn add_login_interactive(&mut self) -> Result<()> {
-        let theme = ColorfulTheme::default();
-
-        let name = Input::<String>::with_theme(&theme)
-            .with_prompt("Enter the name for the login")
-            .allow_empty(true)
-            .interact_text()
-            .wrap_err("Failed to read name from console")?;
+    fn upgrade(&mut self) -> Result<()> {
+        self.file_version = self.version;
+        if self.version > SCHEMA_VERSION {
+            bail!(
+                "The database uses schema version {}, but this version of Locket only supports up to {SCHEMA_VERSION}",
+                self.version
+            );
+        }
+        self.version = SCHEMA_VERSION;
+        Ok(())
+    }
 
-        let username = Input::<String>::with_theme(&theme)
-            .with_prompt("Enter the username for this login")
-            .allow_empty(true)
-            .interact_text()
-            .wrap_err("Failed to read username from console")?;
+    /// The schema version the database was written with, which [`Database::version`] no longer
+    /// says once it's been opened, since it's upgraded to [`SCHEMA_VERSION`] straight away. The
+    /// file itself is only upgraded when the database is next written.
+    #[must_use]
+    pub fn file_version(&self) -> u32 {
+        self.file_version
+    }
 
-        let password = Password::with_theme(&theme)
-            .with_prompt("Enter the password for this login")
-            .allow_empty_password(true)
-            .interact()
-            .wrap_err("Failed to read password from console")?;
+    /// Adds `login` to the database under a new id, and returns the id.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database is already holding as many logins as
+    /// [`Database::max_logins`] allows, or if no unused id could be found. With random UUIDs the
+    /// latter should never happen, unless the random number generator is broken.
+    pub fn add_login(&mut self, mut login: Login) -> Result<Uuid, AddError> {
+        self.check_quota(1)?;
+        login.folder = login.folder.as_deref().and_then(normalise_folder);
+        login.updated_at = Some(now());
+        for _ in 0..MAX_ID_ATTEMPTS {
+            let id = Uuid::new_v4();
+            if let Entry::Vacant(entry) = self.logins.entry(id) {
+                entry.insert(login);
+                return Ok(id);
+            }
+            warn!("Generated the id {id}, which is already in use, trying again");
+        }
 
-        let new_login = Login::new(name, username, password);
-        self.add_login(new_login);
-        Ok(())
+        Err(AddError::NoFreeId(MAX_ID_ATTEMPTS))
     }
 
-    pub fn append_logins(&mut self, logins: Vec<Login>) {
-        for login in logins {
-            self.add_login(login);
+    /// Checks that `adding` more logins would fit in the database, see [`Database::max_logins`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`AddError::QuotaExceeded`] if they wouldn't.
+    pub fn check_quota(&self, adding: usize) -> Result<(), AddError> {
+        let count = self.logins.len();
+        if self.max_logins == 0 || count.saturating_add(adding) <= self.max_logins {
+            return Ok(());
         }
+
+        Err(AddError::QuotaExceeded {
+            count,
+            adding,
+            limit: self.max_logins,
+        })
     }
 
-    pub fn query(&self, name: Option<&str>) -> Vec<(&Uuid, &Login)> {
-        use nucleo_matcher::{
-            pattern::{CaseMatching, Pattern},
-            Matcher,
-        };
+    // With a template, its username and folder are filled in already, ready to be changed or
+    // accepted with Enter. A full database is reported before asking anything, rather than after
+    // everything has been typed in. Whatever was given in `given` isn't asked for, and if that
+    // includes the name, neither is anything besides the password, so that a login can be added
+    // from a script. With `generator`, a password prompt left empty gets a generated password,
+    // which is offered to be copied once the login has been added.
+    pub(crate) fn add_login_interactive(
+        &mut self,
+        template: Option<&Template>,
+        given: GivenFields,
+        generator: Option<&GenerateArgs>,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        self.check_quota(1)?;
+        let initial_username = template
+            .and_then(|template| template.username.clone())
+            .unwrap_or_default();
+        let initial_folder = template
+            .and_then(|template| template.folder.clone())
+            .unwrap_or_default();
+        let quick = given.name.is_some();
 
-        if self.logins.is_empty() {
-            return Vec::new();
-        }
-        let Some(name) = name else {
-            return self.logins.iter().collect();
+        let name = match given.name {
+            Some(name) => name,
+            None => prompt(timeout, || {
+                Input::<String>::with_theme(&ColorfulTheme::default())
+                    .with_prompt(Message::NamePrompt.to_string())
+                    .allow_empty(true)
+                    .interact_text()
+            })?
+            .wrap_err("Failed to read name from console")?,
         };
-        if name.is_empty() {
-            return self.logins.iter().collect();
-        }
 
-        let mut matcher = Matcher::new(nucleo_matcher::Config::DEFAULT);
-        let logins: Vec<LoginAndId> = self.logins.iter().map_into().collect();
+        let username = match given.username {
+            Some(username) => username,
+            None if quick => initial_username,
+            None => prompt(timeout, move || {
+                Input::<String>::with_theme(&ColorfulTheme::default())
+                    .with_prompt(Message::UsernamePrompt.to_string())
+                    .with_initial_text(initial_username)
+                    .allow_empty(true)
+                    .interact_text()
+            })?
+            .wrap_err("Failed to read username from console")?,
+        };
 
-        Pattern::parse(name, CaseMatching::Ignore)
-            .match_list(logins, &mut matcher)
-            .into_iter()
-            .map(|(login, _)| login)
-            .map(|login| (login.0, login.1))
-            .collect()
-    }
+        let mut generated = false;
+        let password = match (given.password, generator) {
+            (Some(password), _) => password,
+            (None, Some(generator)) => {
+                if let Some(password) = Self::read_password_interactive(timeout, true)? {
+                    password
+                } else {
+                    generated = true;
+                    generate::generate(generator)?
+                }
+            }
+            (None, None) => Self::read_password_interactive(timeout, false)?.unwrap_or_default(),
+        };
 
-    pub(crate) fn query_interactive(&mut self, name: Option<&str>) {
-        if self.logins.is_empty() {
-            let data = TableValue::Cell(String::from("No records"));
+        let folder = if quick {
+            initial_folder
+        } else {
+            prompt(timeout, move || {
+                Input::<String>::with_theme(&ColorfulTheme::default())
+                    .with_prompt(Message::FolderPrompt.to_string())
+                    .with_initial_text(initial_folder)
+                    .allow_empty(true)
+                    .interact_text()
+            })?
+            .wrap_err("Failed to read folder from console")?
+        };
 
-            println!(
-                "{table}",
-                table = PoolTable::from(data).with(Style::rounded())
-            );
-            return;
+        let mut new_login = Login::new(name, username, password);
+        new_login.folder = normalise_folder(&folder);
+        if let Some(template) = template {
+            template.add_tags_and_url(&mut new_login);
         }
+        if !quick {
+            Self::add_custom_fields_interactive(&mut new_login, timeout)?;
+        }
+        let password = generated.then(|| new_login.password.clone());
+        self.add_login(new_login)?;
 
-        if let Some(name) = name {
-            // Fix?
-            let matches: Vec<&Login> = self
-                .query(Some(name))
-                .iter()
-                .map(|(_, login)| *login)
-                .collect();
-            if matches.is_empty() {
-                let data = TableValue::Cell(String::from("No records"));
-
-                println!(
-                    "{table}",
-                    table = PoolTable::from(data).with(Style::rounded())
-                );
-                return;
-            }
-            println!("{}", Table::new(matches).with(Style::rounded()));
-        } else {
-            println!(
-                "{}",
-                Table::new(self.logins.values()).with(Style::rounded())
-            );
+        if let Some(password) = password {
+            status!("{}", Message::GeneratedPassword);
+            Self::offer_to_copy(&password, timeout)?;
         }
+        Ok(())
     }
 
-    pub fn remove(&mut self, id: Uuid) -> Option<Login> {
-        self.logins.remove(&id)
+    // Copies a generated password if the user wants it copied, and prints it otherwise, since it's
+    // of no use if they never see it. If it can't be copied, it's printed instead.
+    fn offer_to_copy(password: &str, timeout: Option<Duration>) -> Result<()> {
+        let copy = std::io::stdin().is_terminal()
+            && prompt(timeout, || {
+                Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt(Message::CopyGeneratedPassword.to_string())
+                    .default(true)
+                    .interact()
+            })?
+            .wrap_err("Failed to read whether to copy the password from console")?;
+        if copy {
+            match clipboard::copy(password) {
+                Ok(()) => {
+                    status!("{}", Message::CopiedPassword);
+                    return Ok(());
+                }
+                Err(e) => eprintln!("{}", style(format!("! {e}")).yellow()),
+            }
+        }
+        println!("{password}");
+        Ok(())
     }
 
-    pub(crate) fn remove_interactive(&mut self) -> Result<Option<Login>> {
-        let options: Vec<_> = self.logins.iter().collect();
-        let choice = FuzzySelect::with_theme(&ColorfulTheme::default())
-            .items(
-                options
-                    .iter()
-                    .map(|(_, login)| login)
-                    .collect::<Vec<&&Login>>()
-                    .as_slice(),
-            )
-            .interact_opt()
-            .wrap_err("Failed to read choice of login to be removed from console")?;
+    // Returns `None` if the password was left empty and `allow_generate` is set, in which case
+    // there's no need to confirm that an empty password is wanted.
+    fn read_password_interactive(
+        timeout: Option<Duration>,
+        allow_generate: bool,
+    ) -> Result<Option<String>> {
+        loop {
+            let password = prompt(timeout, || {
+                Password::with_theme(&ColorfulTheme::default())
+                    .with_prompt(Message::PasswordPrompt.to_string())
+                    .allow_empty_password(true)
+                    .interact()
+            })?
+            .wrap_err("Failed to read password from console")?;
+            if !password.is_empty() {
+                return Ok(Some(password));
+            }
+            if allow_generate {
+                return Ok(None);
+            }
 
-        if let Some(index) = choice {
-            let id = *options[index].0;
-            return Ok(self.logins.remove(&id));
+            let keep_empty = prompt(timeout, || {
+                Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt(Message::ConfirmEmptyPassword.to_string())
+                    .default(false)
+                    .interact()
+            })?
+            .wrap_err("Failed to read whether to store an empty password from console")?;
+            if keep_empty {
+                return Ok(Some(password));
+            }
         }
-
-        Ok(None)
     }
 
-    pub fn sync(&self) -> Result<()> {
-        let f = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .read(false)
-            .open(&self.path)
-            .wrap_err("Failed to open the database file for sync")?;
-        let mut writer = BufWriter::new(f);
+    fn add_custom_fields_interactive(login: &mut Login, timeout: Option<Duration>) -> Result<()> {
+        loop {
+            let another = prompt(timeout, || {
+                Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt(Message::AnotherFieldPrompt.to_string())
+                    .default(false)
+                    .interact()
+            })?
+            .wrap_err("Failed to read whether to add another field from console")?;
+            if !another {
+                return Ok(());
+            }
 
-        let doc = rmp_serde::encode::to_vec(&self).wrap_err("Failed to serialise the database")?;
+            let key = prompt(timeout, || {
+                Input::<String>::with_theme(&ColorfulTheme::default())
+                    .with_prompt(Message::FieldNamePrompt.to_string())
+                    .interact_text()
+            })?
+            .wrap_err("Failed to read the name of the field from console")?;
+
+            let secret = prompt(timeout, || {
+                Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt(Message::FieldSecretPrompt.to_string())
+                    .default(false)
+                    .interact()
+            })?
+            .wrap_err("Failed to read whether the field is secret from console")?;
+
+            let value = if secret {
+                prompt(timeout, || {
+                    Password::with_theme(&ColorfulTheme::default())
+                        .with_prompt(Message::FieldValuePrompt.to_string())
+                        .allow_empty_password(true)
+                        .interact()
+                })?
+            } else {
+                prompt(timeout, || {
+                    Input::<String>::with_theme(&ColorfulTheme::default())
+                        .with_prompt(Message::FieldValuePrompt.to_string())
+                        .allow_empty(true)
+                        .interact_text()
+                })?
+            }
+            .wrap_err("Failed to read the value of the field from console")?;
+
+            if secret {
+                login.secret_fields.insert(key.clone());
+            } else {
+                login.secret_fields.remove(&key);
+            }
+            login.custom_fields.insert(key, value);
+        }
+    }
+
+    /// Adds each of `logins` to the database, returning the outcome for each of them in the same
+    /// order. A login with the same name and username as one already in the database, including
+    /// one added earlier in the same call, is skipped as a duplicate, but the rest are still
+    /// added. If the rest wouldn't all fit in the database, see [`Database::max_logins`], none of
+    /// them are added, and each fails with [`AddError::QuotaExceeded`].
+    pub fn append_logins(&mut self, logins: Vec<Login>) -> Vec<Result<Uuid, AddError>> {
+        let mut seen: HashSet<(String, String)> = self
+            .logins
+            .values()
+            .map(|login| (login.name.clone(), login.username.clone()))
+            .collect();
+
+        // Duplicates are picked out first, since they aren't added and so don't count towards the
+        // quota.
+        let unique: Vec<Result<Login, AddError>> = logins
+            .into_iter()
+            .map(|login| {
+                let key = (login.name.clone(), login.username.clone());
+                if seen.contains(&key) {
+                    let (name, username) = key;
+                    return Err(AddError::Duplicate { name, username });
+                }
+
+                seen.insert(key);
+                Ok(login)
+            })
+            .collect();
+        let quota = self.check_quota(unique.iter().filter(|login| login.is_ok()).count());
+
+        unique
+            .into_iter()
+            .map(|login| {
+                let login = login?;
+                quota.clone()?;
+                self.add_login(login)
+            })
+            .collect()
+    }
+
+    /// Adds all of `logins` to the database without checking for duplicates, returning their ids
+    /// in the same order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if they wouldn't all fit in the database, in which case none of them are
+    /// added, or if one of them can't be added, see [`Database::add_login()`]. The ones before it
+    /// stay in the database.
+    pub fn add_logins(&mut self, logins: Vec<Login>) -> Result<Vec<Uuid>, AddError> {
+        self.check_quota(logins.len())?;
+        logins
+            .into_iter()
+            .map(|login| self.add_login(login))
+            .collect()
+    }
+
+    /// Works out what importing `incoming` would do. A login which has the same name and username
+    /// as one already in the database is a conflict if it differs from it, and starts out resolved
+    /// with `policy`. Otherwise it's a duplicate, as is one which appears earlier in `incoming`.
+    #[must_use]
+    pub fn plan_import(&self, incoming: Vec<Login>, policy: MergePolicy) -> ImportPlan {
+        let existing: HashMap<(&str, &str), Uuid> = self
+            .logins
+            .iter()
+            .map(|(id, login)| ((login.name.as_str(), login.username.as_str()), *id))
+            .collect();
+        let mut seen = HashSet::new();
+
+        let mut plan = ImportPlan::default();
+        for login in incoming {
+            if !seen.insert((login.name.clone(), login.username.clone())) {
+                plan.duplicates.push(login);
+                continue;
+            }
+            match existing.get(&(login.name.as_str(), login.username.as_str())) {
+                None => plan.new.push(login),
+                Some(&id) if self.logins[&id].changed_fields(&login).is_empty() => {
+                    plan.duplicates.push(login);
+                }
+                Some(&id) => plan.conflicts.push(ImportConflict {
+                    existing: id,
+                    login,
+                    resolution: policy,
+                }),
+            }
+        }
+
+        plan
+    }
+
+    /// Adds the new logins from an import plan to the database, and deals with the conflicts as
+    /// they've been resolved.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if they wouldn't all fit in the database, in which case nothing is changed,
+    /// or if one of them can't be added, see [`Database::add_logins()`].
+    pub fn apply_import(&mut self, plan: ImportPlan) -> Result<()> {
+        // Logins kept alongside the ones they conflict with are added too, so they're counted
+        // before anything is changed.
+        let kept_both = plan
+            .conflicts
+            .iter()
+            .filter(|conflict| conflict.resolution == MergePolicy::KeepBoth)
+            .count();
+        self.check_quota(plan.new.len() + kept_both)?;
+        self.add_logins(plan.new)?;
+        for ImportConflict {
+            existing,
+            mut login,
+            resolution,
+        } in plan.conflicts
+        {
+            login.folder = login.folder.as_deref().and_then(normalise_folder);
+            match resolution {
+                MergePolicy::Skip => (),
+                MergePolicy::Overwrite => {
+                    login.updated_at = Some(now());
+                    self.logins.insert(existing, login);
+                }
+                MergePolicy::KeepBoth => {
+                    self.rename_apart(&mut login);
+                    self.add_login(login)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Asks what to do about each conflict in turn, showing the fields which differ, with secrets
+    // masked. Choosing to do the same for all the remaining conflicts stops the questions.
+    fn resolve_conflicts_interactive(
+        &self,
+        conflicts: &mut [ImportConflict],
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        const CHOICES: [(Message, MergePolicy, bool); 6] = [
+            (Message::KeepExisting, MergePolicy::Skip, false),
+            (Message::UseImported, MergePolicy::Overwrite, false),
+            (Message::KeepBoth, MergePolicy::KeepBoth, false),
+            (Message::KeepExistingForAll, MergePolicy::Skip, true),
+            (Message::UseImportedForAll, MergePolicy::Overwrite, true),
+            (Message::KeepBothForAll, MergePolicy::KeepBoth, true),
+        ];
+
+        let total = conflicts.len();
+        let mut for_the_rest = None;
+        for (i, conflict) in conflicts.iter_mut().enumerate() {
+            if let Some(resolution) = for_the_rest {
+                conflict.resolution = resolution;
+                continue;
+            }
+
+            let mut builder = Builder::new();
+            builder.set_header(["field", "in the database", "imported"]);
+            for change in self.logins[&conflict.existing].changed_fields(&conflict.login) {
+                builder.push_record([change.field, change.here, change.there]);
+            }
+            // Standard error, where the prompt is shown too.
+            eprintln!("{}", builder.build().with(Style::rounded()));
+
+            let question = Message::ImportConflict {
+                login: &conflict.login,
+                index: i + 1,
+                total,
+            }
+            .to_string();
+            let choice = prompt(timeout, move || {
+                Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt(question)
+                    .items(&CHOICES.map(|(label, _, _)| label.to_string()))
+                    .default(0)
+                    .interact()
+            })?
+            .wrap_err("Failed to read what to do about the conflict from console")?;
+
+            let (_, resolution, all) = CHOICES[choice];
+            conflict.resolution = resolution;
+            if all {
+                for_the_rest = Some(resolution);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn import_interactive(
+        &mut self,
+        args: &ImportArgs,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        let ImportArgs {
+            ref path,
+            format,
+            on_conflict: policy,
+            interactive,
+            dry_run,
+            allow_references,
+        } = *args;
+        let mut progress = ImportProgress::new();
+        let (mut incoming, malformed) = match format {
+            ImportFormat::Json => import::parse_json(
+                &fs::read_to_string(path).wrap_err("Failed to read the file to import")?,
+                &mut progress,
+            )?,
+            ImportFormat::KeepassXml => import::parse_keepass_xml(
+                BufReader::new(File::open(path).wrap_err("Failed to open the file to import")?),
+                &mut progress,
+            )?,
+        };
+
+        if !allow_references {
+            escape_references(&mut incoming);
+        }
+
+        let mut plan = self.plan_import(incoming, policy);
+        plan.malformed = malformed;
+        if interactive && !plan.conflicts.is_empty() {
+            if std::io::stdin().is_terminal() {
+                self.resolve_conflicts_interactive(&mut plan.conflicts, timeout)?;
+            } else {
+                eprintln!("Standard input isn't a terminal, so conflicts are resolved with `--on-conflict` instead of asking");
+            }
+        }
+
+        for login in &plan.new {
+            status!("{}", style(format!("+ {login}")).green());
+            warn_about(login);
+        }
+        for ImportConflict {
+            login, resolution, ..
+        } in &plan.conflicts
+        {
+            let outcome = match resolution {
+                MergePolicy::Skip => "kept the login in the database",
+                MergePolicy::Overwrite => "replaced the login in the database",
+                MergePolicy::KeepBoth => "kept both",
+            };
+            status!(
+                "{}",
+                style(format!("~ {login} (conflict, {outcome})")).yellow()
+            );
+        }
+        for login in &plan.duplicates {
+            status!(
+                "{}",
+                style(format!("~ {login} (duplicate, skipped)")).yellow()
+            );
+        }
+        for err in &plan.malformed {
+            eprintln!("{}", style(format!("! {err}")).red());
+        }
+        status!(
+            "{} to add, {} conflicts, {} duplicates skipped, {} malformed",
+            plan.new.len(),
+            plan.conflicts.len(),
+            plan.duplicates.len(),
+            plan.malformed.len()
+        );
+
+        if dry_run {
+            status!("Dry run, the database was not changed");
+        } else {
+            let resolved = |policy| {
+                plan.conflicts
+                    .iter()
+                    .filter(|conflict| conflict.resolution == policy)
+                    .count()
+            };
+            let added = plan.new.len() + resolved(MergePolicy::KeepBoth);
+            let replaced = resolved(MergePolicy::Overwrite);
+            let skipped =
+                plan.duplicates.len() + plan.malformed.len() + resolved(MergePolicy::Skip);
+            self.apply_import(plan)?;
+            status!("Imported {added} logins, replaced {replaced}, skipped {skipped}");
+        }
+
+        Ok(())
+    }
+
+    // For piping logins in from another tool, e.g. through `jq`. The input is the same JSON array
+    // that `import` takes, but duplicates are decided by `append_logins()`, and there's no dry
+    // run or preview, only a report of what was skipped and why.
+    pub(crate) fn add_logins_from(
+        &mut self,
+        mut input: impl Read,
+        template: Option<&Template>,
+    ) -> Result<()> {
+        let mut content = String::new();
+        input
+            .read_to_string(&mut content)
+            .wrap_err("Failed to read the logins")?;
+        let (mut incoming, malformed) = import::parse_json(&content, &mut ImportProgress::new())?;
+        if let Some(template) = template {
+            for login in &mut incoming {
+                template.apply(login);
+            }
+        }
+
+        for login in &incoming {
+            warn_about(login);
+        }
+        let outcomes = self.append_logins(incoming);
+        if let Some(e) = outcomes
+            .iter()
+            .filter_map(|outcome| outcome.as_ref().err())
+            .find(|e| matches!(e, AddError::QuotaExceeded { .. }))
+        {
+            bail!(e.clone());
+        }
+        let mut added = 0;
+        for outcome in &outcomes {
+            match outcome {
+                Ok(_) => added += 1,
+                Err(e) => eprintln!("{}", style(format!("~ {e}, skipped")).yellow()),
+            }
+        }
+        for err in &malformed {
+            eprintln!("{}", style(format!("! {err}")).red());
+        }
+        status!(
+            "Added {added} logins, skipped {} duplicates and {} malformed",
+            outcomes.len() - added,
+            malformed.len()
+        );
+
+        Ok(())
+    }
+
+    /// Merges the logins from `other` into this database. Logins are matched up by id, or failing
+    /// that by name and username. Logins which only differ in when they were accessed or changed
+    /// are skipped, new ones are added with the id they had in `other`, and `policy` decides what
+    /// happens to the ones which differ. Logins taken from `other` count as changed in this
+    /// database, see [`Login::updated_at`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`AddError::QuotaExceeded`] if the logins which would be added don't fit within
+    /// `max_logins`, in which case nothing is changed.
+    pub fn merge(&mut self, other: Database, policy: MergePolicy) -> Result<MergeReport, AddError> {
+        // As with `apply_import()`, logins kept alongside the ones they conflict with are added
+        // too. Logins are only matched against this database here, so one which would match a
+        // login added earlier in the merge is counted as well, which can only overestimate.
+        let adding = other
+            .logins
+            .iter()
+            .filter(|(id, login)| match self.counterpart(**id, login) {
+                None => true,
+                Some(existing) => {
+                    policy == MergePolicy::KeepBoth && !self.logins[&existing].same_as(login)
+                }
+            })
+            .count();
+        self.check_quota(adding)?;
+
+        let mut report = MergeReport::default();
+
+        for (id, mut login) in other
+            .logins
+            .into_iter()
+            .sorted_by(|a, b| (&a.1.name, &a.1.username).cmp(&(&b.1.name, &b.1.username)))
+        {
+            let Some(existing) = self.counterpart(id, &login) else {
+                report.added.push(login.to_string());
+                login.updated_at = Some(now());
+                self.logins.insert(id, login);
+                continue;
+            };
+            if self.logins[&existing].same_as(&login) {
+                report.skipped.push(login.to_string());
+                continue;
+            }
+
+            report.conflicted += 1;
+            match policy {
+                MergePolicy::Skip => report.skipped.push(login.to_string()),
+                MergePolicy::Overwrite => {
+                    report.overwritten.push(login.to_string());
+                    login.updated_at = Some(now());
+                    self.logins.insert(existing, login);
+                }
+                MergePolicy::KeepBoth => {
+                    self.rename_apart(&mut login);
+                    login.updated_at = Some(now());
+                    let description = login.to_string();
+                    if let Entry::Vacant(entry) = self.logins.entry(id) {
+                        entry.insert(login);
+                    } else if let Err(e) = self.add_login(login) {
+                        warn!("Failed to add {description}: {e}");
+                        report.skipped.push(description);
+                        continue;
+                    }
+                    report.added.push(description);
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    // Renames `login` to `name (2)`, `name (3)`, and so on, until it no longer has the same name
+    // and username as a login in the database.
+    fn rename_apart(&self, login: &mut Login) {
+        let clashes = |login: &Login| {
+            self.logins
+                .values()
+                .any(|existing| existing.name == login.name && existing.username == login.username)
+        };
+        let original_name = login.name.clone();
+        let mut copy = 1;
+        while clashes(login) {
+            copy += 1;
+            login.name = format!("{original_name} ({copy})");
+        }
+    }
+
+    /// Removes anything left over in the logins which no longer does anything, such as secret
+    /// fields naming custom fields which have since been removed. Nothing which is shown or used
+    /// anywhere is changed.
+    pub fn compact(&mut self) -> CompactReport {
+        let mut report = CompactReport::default();
+
+        for login in self.logins.values_mut() {
+            let before = login.secret_fields.len();
+            let custom_fields = &login.custom_fields;
+            login
+                .secret_fields
+                .retain(|key| custom_fields.contains_key(key));
+            report.dangling_secret_fields += before - login.secret_fields.len();
+
+            let before = login.tags.len();
+            let mut seen = HashSet::new();
+            login
+                .tags
+                .retain(|tag| !tag.trim().is_empty() && seen.insert(tag.clone()));
+            report.redundant_tags += before - login.tags.len();
+        }
+
+        report
+    }
+
+    // Compacts the database, reporting what was removed and how much smaller the file will be. The
+    // file itself is rewritten when the database is synced afterwards, as with any other change.
+    pub(crate) fn compact_interactive(&mut self, dry_run: bool) -> Result<()> {
+        let before = fs::metadata(&self.path)
+            .wrap_err("Failed to get the size of the database")?
+            .len();
+
+        let (report, after) = if dry_run {
+            let mut preview = Database {
+                logins: self.logins.clone(),
+                version: self.version,
+                ..Database::default()
+            };
+            let report = preview.compact();
+            (report, preview.encoded_len()?)
+        } else {
+            let report = self.compact();
+            (report, self.encoded_len()?)
+        };
+
+        let verb = if dry_run { "Would remove" } else { "Removed" };
+        status!(
+            "{verb} {} secret field(s) without a custom field, and {} empty or repeated tag(s)",
+            report.dangling_secret_fields,
+            report.redundant_tags
+        );
+        status!(
+            "The database {} {after} bytes, {} bytes smaller than before",
+            if dry_run { "would be" } else { "is now" },
+            before.saturating_sub(after)
+        );
+
+        if dry_run {
+            status!("Dry run, the database was not changed");
+        }
+
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> Result<u64> {
+        let doc = rmp_serde::encode::to_vec(self).wrap_err("Failed to serialise the database")?;
+        Ok(doc.len() as u64)
+    }
+
+    // Finds the login in this database which corresponds to `login` from another one, by id, or
+    // failing that by name and username.
+    fn counterpart(&self, id: Uuid, login: &Login) -> Option<Uuid> {
+        if self.logins.contains_key(&id) {
+            return Some(id);
+        }
+        self.logins
+            .iter()
+            .find(|(_, existing)| {
+                existing.name == login.name && existing.username == login.username
+            })
+            .map(|(id, _)| *id)
+    }
+
+    /// Compares this database with `other`, matching logins up the same way as
+    /// [`Database::merge()`]. Neither database is changed. Logins which only differ in when they
+    /// were last accessed are counted as unchanged.
+    #[must_use]
+    pub fn diff(&self, other: &Database) -> DiffReport {
+        let mut report = DiffReport::default();
+        let mut matched = HashSet::new();
+
+        for (id, theirs) in other
+            .logins
+            .iter()
+            .sorted_by(|a, b| (&a.1.name, &a.1.username).cmp(&(&b.1.name, &b.1.username)))
+        {
+            let Some(existing) = self.counterpart(*id, theirs) else {
+                report.only_there.push(theirs.to_string());
+                continue;
+            };
+            matched.insert(existing);
+
+            let fields = self.logins[&existing].changed_fields(theirs);
+            if fields.is_empty() {
+                report.unchanged += 1;
+            } else {
+                report.changed.push(ChangedLogin {
+                    login: self.logins[&existing].to_string(),
+                    fields,
+                });
+            }
+        }
+
+        report.only_here = self
+            .logins
+            .iter()
+            .filter(|(id, _)| !matched.contains(*id))
+            .map(|(_, login)| login)
+            .sorted_by(|a, b| (&a.name, &a.username).cmp(&(&b.name, &b.username)))
+            .map(ToString::to_string)
+            .collect();
+
+        report
+    }
+
+    pub(crate) fn diff_interactive(&self, path: &Path, json: bool) -> Result<()> {
+        let other = Database::open(path)
+            .wrap_err_with(|| format!("Failed to open the database at {}", path.display()))?;
+        let report = self.diff(&other);
+
+        if json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report)
+                    .wrap_err("Failed to serialise the differences as JSON")?
+            );
+            return Ok(());
+        }
+
+        if report.only_here.is_empty() && report.only_there.is_empty() && report.changed.is_empty()
+        {
+            status!("The databases have the same {} logins", report.unchanged);
+            return Ok(());
+        }
+
+        let mut builder = Builder::new();
+        builder.set_header(["", "login", "field", "here", "there"]);
+        for login in &report.only_here {
+            builder.push_record(["only here", login, "", "", ""]);
+        }
+        for login in &report.only_there {
+            builder.push_record(["only there", login, "", "", ""]);
+        }
+        for changed in &report.changed {
+            for change in &changed.fields {
+                builder.push_record([
+                    "changed",
+                    &changed.login,
+                    &change.field,
+                    &change.here,
+                    &change.there,
+                ]);
+            }
+        }
+        pager::print_paged(&builder.build().with(Style::rounded()).to_string());
+        status!(
+            "{} only here, {} only there, {} changed, {} the same",
+            report.only_here.len(),
+            report.only_there.len(),
+            report.changed.len(),
+            report.unchanged
+        );
+
+        Ok(())
+    }
+
+    pub(crate) fn merge_interactive(&mut self, args: &MergeArgs) -> Result<()> {
+        let MergeArgs {
+            ref path,
+            on_conflict: policy,
+            dry_run,
+            allow_references,
+        } = *args;
+        let mut other = Database::open(path)
+            .wrap_err_with(|| format!("Failed to open the database at {}", path.display()))?;
+        if !allow_references {
+            escape_references(other.logins.values_mut());
+        }
+
+        let report = if dry_run {
+            let mut preview = Database {
+                logins: self.logins.clone(),
+                max_logins: self.max_logins,
+                ..Database::default()
+            };
+            preview.merge(other, policy)
+        } else {
+            self.merge(other, policy)
+        }
+        .wrap_err("Failed to merge the databases")?;
+
+        for login in &report.added {
+            status!("{}", style(format!("+ {login}")).green());
+        }
+        for login in &report.overwritten {
+            status!("{}", style(format!("~ {login} (overwritten)")).yellow());
+        }
+        for login in &report.skipped {
+            status!("{}", style(format!("= {login} (skipped)")).dim());
+        }
+        status!(
+            "{} added, {} overwritten, {} skipped, {} conflicted",
+            report.added.len(),
+            report.overwritten.len(),
+            report.skipped.len(),
+            report.conflicted
+        );
+
+        if dry_run {
+            status!("Dry run, the database was not changed");
+        }
+
+        Ok(())
+    }
+
+    /// The whole database as pretty-printed JSON, with every password, secret custom field and
+    /// attachment replaced by `"<redacted>"`, for sharing when reporting a problem. Empty
+    /// passwords are left empty. It can't be imported again, and the real database is never
+    /// written this way.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can't be serialised.
+    pub fn to_redacted_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(&RedactedDatabase::new(self))
+            .wrap_err("Failed to serialise the redacted database")
+    }
+
+    /// The logins which were added or changed at or after `since`, see [`Login::updated_at`].
+    /// Logins from before changes were recorded are always included, since there's no telling
+    /// when they last changed.
+    #[must_use]
+    pub fn changed_since(&self, since: DateTime<Utc>) -> Vec<(&Uuid, &Login)> {
+        // Times are only recorded to the second, so a login changed earlier in the same second as
+        // `since` counts too.
+        let since = since.trunc_subsecs(0);
+        self.logins
+            .iter()
+            .filter(|(_, login)| login.updated_at.is_none_or(|updated| updated >= since))
+            .collect()
+    }
+
+    // Writes every login to `path`, or to standard output if there isn't one, or with `since`, only
+    // the ones which have changed since then. Logins are sorted by name and username so that
+    // exporting the same database twice gives the same file. With `redacted`, the database is
+    // written by `to_redacted_json()` instead. JSON exports keep passwords as they're stored, so
+    // that `import --allow-references` can tell references from escaped passwords again, but
+    // other password managers only understand the passwords themselves, see
+    // `resolve_for_export()`.
+    pub(crate) fn export(
+        &self,
+        path: Option<&Path>,
+        format: ExportFormat,
+        bare: bool,
+        redacted: bool,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        if bare && !matches!(format, ExportFormat::Json) {
+            bail!("`--bare` only applies to JSON exports");
+        }
+        if redacted && !matches!(format, ExportFormat::Json) {
+            bail!("`--redacted` only applies to JSON exports");
+        }
+        let logins: Vec<&Login> = match since {
+            Some(since) => self
+                .changed_since(since)
+                .into_iter()
+                .map(|(_, login)| login)
+                .collect(),
+            None => self.logins.values().collect(),
+        };
+        let logins: Vec<&Login> = logins
+            .into_iter()
+            .sorted_by(|a, b| (&a.name, &a.username).cmp(&(&b.name, &b.username)))
+            .collect();
+
+        let mut exported = logins.len();
+        let mut writer: Box<dyn Write> = match path {
+            Some(path) => Box::new(BufWriter::new(
+                permissions::private(OpenOptions::new().write(true).create(true).truncate(true))
+                    .open(path)
+                    .wrap_err("Failed to create the export file")?,
+            )),
+            None => Box::new(std::io::stdout().lock()),
+        };
+
+        match format {
+            ExportFormat::Json if redacted => {
+                writeln!(writer, "{}", self.to_redacted_json()?)
+                    .and_then(|()| writer.flush())
+                    .wrap_err("Failed to write the redacted database")?;
+            }
+            ExportFormat::Json => export::write_json(&logins, bare, since.is_some(), writer)?,
+            ExportFormat::KeepassCsv => {
+                let resolved = self.resolve_for_export(&logins);
+                export::write_keepass_csv(&resolved.iter().collect::<Vec<_>>(), writer)?;
+                exported = resolved.len();
+            }
+        }
+
+        if let Some(path) = path {
+            status!("Exported {exported} logins to {}", path.display());
+        }
+
+        Ok(())
+    }
+
+    // Favorites are always sorted before other logins. The sort is stable, so the order of the
+    // matches is otherwise preserved.
+    #[must_use]
+    pub fn query(&self, name: Option<&str>) -> Vec<(&Uuid, &Login)> {
+        let mut matches = self.fuzzy_match(name);
+        matches.sort_by_key(|(_, login)| !login.favorite);
+        matches
+    }
+
+    fn fuzzy_match(&self, name: Option<&str>) -> Vec<(&Uuid, &Login)> {
+        use nucleo_matcher::{pattern::Pattern, Matcher};
+
+        if self.logins.is_empty() {
+            return Vec::new();
+        }
+        let Some(name) = name else {
+            return self.logins.iter().collect();
+        };
+        if name.is_empty() {
+            return self.logins.iter().collect();
+        }
+
+        // nucleo strips the accents from the names it's matching against, so that "resume" finds
+        // "Résumé", but not from the pattern, so "résumé" wouldn't find anything unless we strip
+        // them from it ourselves.
+        let mut config = self.fuzzy.matcher_config();
+        config.normalize = !self.exact_search;
+        let name: Cow<str> = if self.exact_search {
+            Cow::Borrowed(name)
+        } else {
+            Cow::Owned(name.chars().map(nucleo_matcher::chars::normalize).collect())
+        };
+        let mut matcher = Matcher::new(config);
+        let logins: Vec<LoginAndId> = self.logins.iter().map_into().collect();
+
+        Pattern::parse(&name, self.fuzzy.case_matching())
+            .match_list(logins, &mut matcher)
+            .into_iter()
+            .map(|(login, _)| login)
+            .map(|login| (login.0, login.1))
+            .collect()
+    }
+
+    // Returns whether anything matched. Only the table says so when nothing did, the other formats
+    // are for scripts, which can check the exit code instead. `--raw` is expected to have already
+    // been turned into `--output plain`. Shown passwords are cleared from the terminal again after
+    // `reveal_timeout`, if it's set.
+    pub(crate) fn query_interactive(
+        &mut self,
+        args: &QueryArgs,
+        reveal_timeout: Option<Duration>,
+    ) -> Result<bool> {
+        let QueryArgs {
+            name,
+            favorites,
+            folder,
+            output,
+            fields,
+            show_passwords,
+            first,
+            sort,
+            ..
+        } = args;
+        let show_passwords = *show_passwords;
+
+        let mut matches: Vec<&Login> = self
+            .query(name.as_deref())
+            .into_iter()
+            .map(|(_, login)| login)
+            .filter(|login| login.favorite || !favorites)
+            .filter(|login| {
+                folder
+                    .as_deref()
+                    .is_none_or(|folder| login.in_folder(folder))
+            })
+            .collect();
+        if let QuerySort::Recent = sort {
+            // Stable, so logins which have never been accessed keep their order, at the end.
+            matches.sort_by_key(|login| Reverse(login.last_accessed));
+        }
+        matches.truncate(if *first { 1 } else { usize::MAX });
+        let found = !matches.is_empty();
+        let fields = if fields.is_empty() {
+            DEFAULT_FIELDS
+        } else {
+            fields
+        };
+
+        // Only resolved when they're going to be shown, since that can mean running commands.
+        let resolved: Vec<Login>;
+        if show_passwords && fields.contains(&LoginField::Password) {
+            resolved = matches
+                .iter()
+                .map(|login| self.with_resolved_password(login))
+                .collect();
+            matches = resolved.iter().collect();
+        }
+
+        let text = match output {
+            OutputFormat::Table => {
+                if matches.is_empty() {
+                    let data = TableValue::Cell(String::from("No records"));
+
+                    println!(
+                        "{table}",
+                        table = PoolTable::from(data).with(Style::rounded())
+                    );
+                    return Ok(false);
+                }
+
+                // The rows are built by hand, rather than deriving `Tabled` for `Login`, so that
+                // only the chosen fields are shown.
+                let mut builder = Builder::new();
+                builder.set_header(fields.iter().map(|field| field.header()));
+                for login in matches {
+                    builder.push_record(
+                        fields
+                            .iter()
+                            .map(|field| field.display(login, show_passwords)),
+                    );
+                }
+                builder.build().with(Style::rounded()).to_string()
+            }
+            OutputFormat::Json => {
+                let rows: Vec<serde_json::Map<String, serde_json::Value>> = matches
+                    .into_iter()
+                    .map(|login| {
+                        fields
+                            .iter()
+                            .map(|field| {
+                                (
+                                    String::from(field.header()),
+                                    field.json(login, show_passwords),
+                                )
+                            })
+                            .collect()
+                    })
+                    .collect();
+                serde_json::to_string_pretty(&rows)
+                    .wrap_err("Failed to serialise the logins as JSON")?
+            }
+            OutputFormat::Plain => matches
+                .into_iter()
+                .map(|login| {
+                    fields
+                        .iter()
+                        .map(|field| field.display(login, show_passwords))
+                        .join("\t")
+                })
+                .join("\n"),
+        };
+
+        match output {
+            _ if show_passwords => reveal::print_revealed(&text, reveal_timeout),
+            OutputFormat::Table => pager::print_paged(&text),
+            OutputFormat::Json | OutputFormat::Plain => println!("{text}"),
+        }
+        Ok(found)
+    }
+
+    /// Every folder which has a login in it, along with the folders it's inside, so that they can
+    /// be shown as a tree. Sorted, so each folder comes straight after the one it's inside.
+    #[must_use]
+    pub fn folders(&self) -> BTreeSet<String> {
+        let mut folders = BTreeSet::new();
+        for folder in self
+            .logins
+            .values()
+            .filter_map(|login| login.folder.as_deref())
+        {
+            for (i, _) in folder.match_indices('/') {
+                folders.insert(String::from(&folder[..i]));
+            }
+            folders.insert(String::from(folder));
+        }
+        folders
+    }
+
+    #[must_use]
+    pub fn get(&self, id: Uuid) -> Option<&Login> {
+        self.logins.get(&id)
+    }
+
+    // Finds the login `name_or_id` refers to. An exact id is used as is, otherwise the user chooses
+    // from the logins that match it as a name, unless there's only one.
+    pub(crate) fn get_one_interactive(
+        &self,
+        name_or_id: Option<&str>,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Uuid>> {
+        if let Some(id) = name_or_id.and_then(|id| Uuid::parse_str(id).ok()) {
+            if self.logins.contains_key(&id) {
+                return Ok(Some(id));
+            }
+        }
+
+        let matches = self.query(name_or_id);
+        if let [(id, _)] = matches.as_slice() {
+            return Ok(Some(**id));
+        }
+
+        self.select_login(Message::ChooseLogin, name_or_id, timeout)
+    }
+
+    // A copy of `login` with its password resolved, for showing it. If it can't be resolved, the
+    // reference is shown instead with a warning, so that one broken reference doesn't stop the
+    // rest from being shown.
+    fn with_resolved_password(&self, login: &Login) -> Login {
+        let mut login = login.clone();
+        match login.resolve_password(self.allow_password_commands) {
+            Ok(password) => login.password = password,
+            Err(e) => eprintln!(
+                "{}",
+                style(format!("! Failed to get the password of {login}: {e:#}")).red()
+            ),
+        }
+        login
+    }
+
+    // Copies of `logins` with their passwords resolved, for exporting them somewhere which doesn't
+    // understand references. A login whose password can't be resolved is left out with a warning,
+    // rather than exporting the reference as if it were the password.
+    fn resolve_for_export(&self, logins: &[&Login]) -> Vec<Login> {
+        logins
+            .iter()
+            .filter_map(
+                |login| match login.resolve_password(self.allow_password_commands) {
+                    Ok(password) => Some(Login {
+                        password,
+                        ..(*login).clone()
+                    }),
+                    Err(e) => {
+                        eprintln!(
+                            "{}",
+                            style(format!("! Left {login} out of the export: {e:#}")).red()
+                        );
+                        None
+                    }
+                },
+            )
+            .collect()
+    }
+
+    pub(crate) fn show_interactive(
+        &mut self,
+        name_or_id: Option<&str>,
+        timeout: Option<Duration>,
+        show_passwords: bool,
+    ) -> Result<()> {
+        let Some(id) = self.get_one_interactive(name_or_id, timeout)? else {
+            eprintln!("No logins match");
+            return Ok(());
+        };
+        self.record_access(id);
+        let resolved;
+        let login = if show_passwords {
+            resolved = self.with_resolved_password(&self.logins[&id]);
+            &resolved
+        } else {
+            &self.logins[&id]
+        };
+
+        let mut builder = Builder::new();
+        builder.push_record([String::from("id"), id.to_string()]);
+        for field in [
+            LoginField::Name,
+            LoginField::Username,
+            LoginField::Password,
+            LoginField::Favorite,
+            LoginField::Url,
+            LoginField::Notes,
+            LoginField::Tags,
+            LoginField::Folder,
+        ] {
+            builder.push_record([
+                String::from(field.header()),
+                field.display(login, show_passwords),
+            ]);
+        }
+        for (key, value) in login.custom_fields.iter().sorted() {
+            let value = if show_passwords || !login.secret_fields.contains(key) {
+                value.clone()
+            } else {
+                String::from(HIDDEN_PASSWORD)
+            };
+            builder.push_record([key.clone(), value]);
+        }
+        for attachment in &login.attachments {
+            builder.push_record([
+                String::from("attachment"),
+                format!("{} ({} bytes)", attachment.name, attachment.bytes.len()),
+            ]);
+        }
+
+        // There's no header, so there's no line under it either.
+        println!(
+            "{}",
+            builder.build().with(Style::rounded().remove_horizontals())
+        );
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: Uuid) -> Option<Login> {
+        self.logins.remove(&id)
+    }
+
+    pub fn remove_many(&mut self, ids: &[Uuid]) -> Vec<Login> {
+        ids.iter().filter_map(|id| self.logins.remove(id)).collect()
+    }
+
+    pub(crate) fn remove_interactive(
+        &mut self,
+        timeout: Option<Duration>,
+        dry_run: bool,
+    ) -> Result<Option<Login>> {
+        let Some(id) = self.select_login(Message::ChooseLoginToRemove, None, timeout)? else {
+            return Ok(None);
+        };
+
+        if dry_run {
+            status!("Would remove {}", self.logins[&id]);
+            return Ok(None);
+        }
+
+        Ok(self.logins.remove(&id))
+    }
+
+    pub(crate) fn remove_many_interactive(
+        &mut self,
+        timeout: Option<Duration>,
+        dry_run: bool,
+        yes: bool,
+    ) -> Result<Vec<Login>> {
+        let options = self.query(None);
+        let items: Vec<String> = options.iter().map(|(_, login)| login.to_string()).collect();
+
+        let chosen = prompt(timeout, move || {
+            MultiSelect::with_theme(&ColorfulTheme::default())
+                .with_prompt(Message::ChooseLoginsToRemove.to_string())
+                .items(&items)
+                .interact_opt()
+        })?
+        .wrap_err("Failed to read choice of logins to be removed from console")?;
+
+        let ids: Vec<Uuid> = match chosen {
+            Some(indices) if !indices.is_empty() => {
+                indices.into_iter().map(|index| *options[index].0).collect()
+            }
+            _ => return Ok(Vec::new()),
+        };
+
+        if dry_run {
+            for id in &ids {
+                status!("Would remove {}", self.logins[id]);
+            }
+            return Ok(Vec::new());
+        }
+
+        if !yes {
+            let count = ids.len();
+            let confirmed = prompt(timeout, move || {
+                Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt(Message::ConfirmRemove { count }.to_string())
+                    .default(false)
+                    .interact()
+            })?
+            .wrap_err("Failed to read confirmation from console")?;
+
+            if !confirmed {
+                return Ok(Vec::new());
+            }
+        }
+
+        Ok(self.remove_many(&ids))
+    }
+
+    /// Returns the login with the given id, recording that it was accessed, see
+    /// [`Login::last_accessed`].
+    pub fn access(&mut self, id: Uuid) -> Option<&Login> {
+        self.record_access(id);
+        self.logins.get(&id)
+    }
+
+    fn record_access(&mut self, id: Uuid) {
+        if let Some(login) = self.logins.get_mut(&id) {
+            login.last_accessed = Some(now());
+            self.accessed = true;
+        }
+    }
+
+    // Copies any access times from `other` which are newer than the ones in this database. Used to
+    // save the access times recorded by a command which didn't hold the lock, into a fresh copy of
+    // the database, so that nothing changed by another instance in the meantime is lost.
+    pub(crate) fn merge_access_times(&mut self, other: &Database) {
+        for (id, accessed) in &other.logins {
+            if let Some(login) = self.logins.get_mut(id) {
+                login.last_accessed = login.last_accessed.max(accessed.last_accessed);
+            }
+        }
+    }
+
+    /// Applies `patch` to the login with the given id, returning it as it is afterwards, or `None`
+    /// if there is no login with that id.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`AddError::Duplicate`] if the patch would give the login the same name and
+    /// username as another one, in which case it isn't changed.
+    pub fn update_login(
+        &mut self,
+        id: Uuid,
+        patch: LoginPatch,
+    ) -> Result<Option<&Login>, AddError> {
+        let Some(updated) = self.patched(id, patch)? else {
+            return Ok(None);
+        };
+        self.logins.insert(id, updated);
+        Ok(self.logins.get(&id))
+    }
+
+    // The login with the given id as `update_login()` would leave it, without changing anything.
+    fn patched(&self, id: Uuid, patch: LoginPatch) -> Result<Option<Login>, AddError> {
+        let Some(current) = self.logins.get(&id) else {
+            return Ok(None);
+        };
+        let mut updated = current.clone();
+        updated.apply_patch(patch);
+        updated.updated_at = Some(now());
+
+        if self.logins.iter().any(|(other_id, other)| {
+            *other_id != id && other.name == updated.name && other.username == updated.username
+        }) {
+            return Err(AddError::Duplicate {
+                name: updated.name,
+                username: updated.username,
+            });
+        }
+
+        Ok(Some(updated))
+    }
+
+    // Asks for the new name if `to` isn't given, starting from the current one so that a typo can
+    // be fixed without typing the whole name again. A dry run makes the same checks, so it fails
+    // where the rename would, but never changes the database.
+    pub(crate) fn rename_interactive(
+        &mut self,
+        name_or_id: Option<&str>,
+        to: Option<String>,
+        dry_run: bool,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        let Some(id) = self.get_one_interactive(name_or_id, timeout)? else {
+            eprintln!("No logins match");
+            return Ok(());
+        };
+        let original = self.logins[&id].clone();
+
+        let to = if let Some(to) = to {
+            to
+        } else {
+            let current = original.name.clone();
+            prompt(timeout, move || {
+                Input::<String>::with_theme(&ColorfulTheme::default())
+                    .with_prompt(Message::NewNamePrompt.to_string())
+                    .with_initial_text(current)
+                    .interact_text()
+            })?
+            .wrap_err("Failed to read the new name from console")?
+        };
+        let to = to.trim();
+        if to.is_empty() {
+            bail!("The new name can't be empty");
+        }
+        if to == original.name {
+            status!("{original} already has that name");
+            return Ok(());
+        }
+
+        let patch = LoginPatch {
+            name: Some(String::from(to)),
+            ..LoginPatch::default()
+        };
+        let renamed = if dry_run {
+            self.patched(id, patch)?
+        } else {
+            self.update_login(id, patch)?.cloned()
+        };
+        let Some(renamed) = renamed else {
+            unreachable!("the login was chosen from the database");
+        };
+        status!(
+            "{} {original} to {renamed}",
+            if dry_run { "Would rename" } else { "Renamed" }
+        );
+
+        Ok(())
+    }
+
+    /// Attaches `attachment` to the login with the given id, replacing any attachment with the
+    /// same name. Returns whether one was replaced.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no login with the id, or the attachment is bigger than
+    /// [`Database::max_attachment_bytes`] allows.
+    pub fn attach(&mut self, id: Uuid, attachment: Attachment) -> Result<bool, AttachError> {
+        let size = attachment.bytes.len() as u64;
+        if self.max_attachment_bytes != 0 && size > self.max_attachment_bytes {
+            return Err(AttachError::TooLarge {
+                size,
+                max: self.max_attachment_bytes,
+            });
+        }
+        let login = self
+            .logins
+            .get_mut(&id)
+            .ok_or(AttachError::NoSuchLogin(id))?;
+
+        login.updated_at = Some(now());
+        if let Some(existing) = login
+            .attachments
+            .iter_mut()
+            .find(|existing| existing.name == attachment.name)
+        {
+            *existing = attachment;
+            return Ok(true);
+        }
+        login.attachments.push(attachment);
+        Ok(false)
+    }
+
+    /// Returns the attachment called `name` on the login with the given id, recording that the
+    /// login was accessed, like [`Database::access()`].
+    pub fn access_attachment(&mut self, id: Uuid, name: &str) -> Option<&Attachment> {
+        self.access(id)?
+            .attachments
+            .iter()
+            .find(|attachment| attachment.name == name)
+    }
+
+    pub(crate) fn attach_interactive(
+        &mut self,
+        login: &str,
+        file: &Path,
+        name: Option<String>,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        let Some(id) = self.get_one_interactive(Some(login), timeout)? else {
+            bail!("No logins match {login:?}");
+        };
+        let Some(name) = name.or_else(|| {
+            file.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        }) else {
+            bail!(
+                "{} has no file name, give one with `--name`",
+                file.display()
+            );
+        };
+
+        let bytes =
+            fs::read(file).wrap_err_with(|| format!("Failed to read {}", file.display()))?;
+        let size = bytes.len();
+        let replaced = self.attach(
+            id,
+            Attachment {
+                name: name.clone(),
+                bytes,
+            },
+        )?;
+        status!(
+            "{} {name} ({size} bytes) {} {}",
+            if replaced { "Replaced" } else { "Attached" },
+            if replaced { "on" } else { "to" },
+            self.logins[&id]
+        );
+
+        let total = self.encoded_len()?;
+        if total > LARGE_DATABASE_BYTES {
+            eprintln!(
+                "{}",
+                style(format!(
+                    "! The database is now {} MiB, which makes every change slower to save. Consider keeping large files somewhere else",
+                    total / 1024 / 1024
+                ))
+                .yellow()
+            );
+        }
+
+        Ok(())
+    }
+
+    // Writes the attachment to `out`, or to standard output if there isn't one. Counts as an
+    // access, like `show`.
+    pub(crate) fn extract_interactive(
+        &mut self,
+        login: &str,
+        name: &str,
+        out: Option<&Path>,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        let Some(id) = self.get_one_interactive(Some(login), timeout)? else {
+            bail!("No logins match {login:?}");
+        };
+        let description = self.logins[&id].to_string();
+        let Some(attachment) = self.access_attachment(id, name) else {
+            bail!("{description} has no attachment called {name:?}");
+        };
+
+        match out {
+            Some(out) => {
+                permissions::write_private(out, &attachment.bytes)
+                    .wrap_err_with(|| format!("Failed to write {}", out.display()))?;
+                status!("Saved {name} to {}", out.display());
+            }
+            None => std::io::stdout()
+                .write_all(&attachment.bytes)
+                .wrap_err("Failed to write the attachment")?,
+        }
+
+        Ok(())
+    }
+
+    // Returns the new value of the flag, or `None` if there is no login with the given id.
+    pub fn toggle_favorite(&mut self, id: Uuid) -> Option<bool> {
+        let login = self.logins.get_mut(&id)?;
+        login.favorite = !login.favorite;
+        login.updated_at = Some(now());
+        Some(login.favorite)
+    }
+
+    pub(crate) fn toggle_favorite_interactive(&mut self, timeout: Option<Duration>) -> Result<()> {
+        let Some(id) = self.select_login(Message::ChooseFavorite, None, timeout)? else {
+            return Ok(());
+        };
+
+        match self.toggle_favorite(id) {
+            Some(true) => status!("Added {} to your favorites", self.logins[&id].name),
+            Some(false) => status!("Removed {} from your favorites", self.logins[&id].name),
+            None => unreachable!("the login was chosen from the database"),
+        }
+
+        Ok(())
+    }
+
+    // Lets the user fuzzy-select one of the logins matching `name`, returning its id, or `None` if
+    // they cancelled or nothing matched.
+    fn select_login(
+        &self,
+        message: Message,
+        name: Option<&str>,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Uuid>> {
+        let options = self.query(name);
+        if options.is_empty() {
+            return Ok(None);
+        }
+        let items: Vec<String> = options.iter().map(|(_, login)| login.to_string()).collect();
+        let message = message.to_string();
+
+        let choice = prompt(timeout, move || {
+            FuzzySelect::with_theme(&ColorfulTheme::default())
+                .with_prompt(message)
+                .items(&items)
+                .interact_opt()
+        })?
+        .wrap_err("Failed to read choice of login from console")?;
+
+        Ok(choice.map(|index| *options[index].0))
+    }
+
+    // Like `open()`, but if the database turns out to be corrupt and there's a backup of it, offers
+    // to restore the backup. The corrupt database is kept beside it, see `restore_backup()`, in
+    // case there's anything in it worth recovering by hand.
+    pub(crate) fn open_interactive(path: &Path, timeout: Option<Duration>) -> Result<Self> {
+        let err = match Self::open(path) {
+            Ok(db) => return Ok(db),
+            Err(err) => err,
+        };
+        let Some(CorruptDatabase {
+            backup: Some(backup),
+            ..
+        }) = err.downcast_ref::<CorruptDatabase>().cloned()
+        else {
+            return Err(err);
+        };
+        if !std::io::stdin().is_terminal() {
+            return Err(err);
+        }
+
+        eprintln!("{}", style(format!("{err}")).red());
+        let restore = prompt(timeout, move || {
+            Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt(Message::ConfirmRestore.to_string())
+                .default(true)
+                .interact()
+        })?
+        .wrap_err("Failed to read whether to restore the database from console")?;
+        if !restore {
+            return Err(err);
+        }
+
+        let corrupt = Self::restore_backup(path, &backup)?;
+        eprintln!(
+            "Restored the database from {}, the corrupt database was kept at {}",
+            backup.display(),
+            corrupt.display()
+        );
+
+        Self::open(path)
+    }
+
+    // Replaces the database at `path` with a copy of `backup`, and returns where the database it
+    // replaced was kept, which is `<db>.corrupt`, or if an earlier restore left one of those, a
+    // name with the time in it, such as `locket.db.1700000000000.corrupt`. Commands which only
+    // read the database don't take the lock, so like `write_file()`, the backup is copied beside
+    // the database and renamed over it under the data lock, and they never see it half-restored.
+    fn restore_backup(path: &Path, backup: &Path) -> Result<PathBuf> {
+        let _write_lock = data_lock(path, true);
+        let with_suffix = |suffix: &str| {
+            let mut name = path.as_os_str().to_owned();
+            name.push(suffix);
+            PathBuf::from(name)
+        };
+
+        let mut corrupt = with_suffix(".corrupt");
+        if corrupt
+            .try_exists()
+            .wrap_err_with(|| format!("Failed to check whether {} exists", corrupt.display()))?
+        {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+            corrupt = with_suffix(&format!(".{timestamp}.corrupt"));
+        }
+        let tmp_path = with_suffix(".tmp");
+
+        fs::copy(backup, &tmp_path).wrap_err("Failed to copy the backup beside the database")?;
+        fs::copy(path, &corrupt).wrap_err("Failed to keep a copy of the corrupt database")?;
+        fs::rename(&tmp_path, path).wrap_err("Failed to replace the database with the backup")?;
+
+        Ok(corrupt)
+    }
+
+    /// Writes the database back to the file it was loaded from, then backs it up if backups are
+    /// turned on and it's changed since it was opened or last written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file couldn't be written, or the backup couldn't be made.
+    pub fn sync(&self) -> Result<()> {
+        self.write(&self.encode()?)
+    }
+
+    /// Like [`Database::sync()`], but only writes the database if it's changed since it was opened
+    /// or last written, and returns whether it was written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file couldn't be written, or the backup couldn't be made.
+    pub fn sync_if_changed(&self) -> Result<bool> {
+        let doc = self.encode()?;
+        if *self.written() == Some(content_hash(&doc)) {
+            return Ok(false);
+        }
+        self.write(&doc)?;
+        Ok(true)
+    }
+
+    // Like `sync()`, but never makes a backup. For writes which only record when logins were last
+    // used, which would otherwise rotate the backups of real changes out after a few `show`s.
+    pub(crate) fn sync_without_backup(&self) -> Result<()> {
+        self.write_file(&self.encode()?)
+    }
+
+    // Only ever held briefly, without calling anything which could panic.
+    fn written(&self) -> MutexGuard<'_, Option<u64>> {
+        self.written.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn encode(&self) -> Result<Vec<u8>> {
+        rmp_serde::encode::to_vec(&self).wrap_err("Failed to serialise the database")
+    }
+
+    // The database as it would be written to disk now, which is also what a backup made now would
+    // contain, along with the name that backup would have. Restoring from it works the same way.
+    #[cfg(feature = "web")]
+    pub(crate) fn snapshot(&self) -> Result<(String, Vec<u8>)> {
+        Ok((Self::backup_name(), self.encode()?))
+    }
+
+    fn backup_name() -> String {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        format!("{BACKUP_PREFIX}{timestamp}{BACKUP_SUFFIX}")
+    }
+
+    // Rewriting the file with what it already held isn't worth a backup, which would only push an
+    // older one out.
+    fn write(&self, doc: &[u8]) -> Result<()> {
+        let changed = *self.written() != Some(content_hash(doc));
+        self.write_file(doc)?;
+        if changed && self.backup_retention > 0 {
+            self.backup().wrap_err("Failed to back up the database")?;
+        }
+
+        Ok(())
+    }
+
+    fn write_file(&self, doc: &[u8]) -> Result<()> {
+        // Written next to the database and then renamed over it, so that commands reading the
+        // database without the lock see either all of the old version or all of the new one. The
+        // data lock is only needed where renaming over a file isn't atomic.
+        let _write_lock = data_lock(&self.path, true);
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let f = permissions::private(OpenOptions::new().write(true).create(true).truncate(true))
+            .open(&tmp_path)
+            .wrap_err("Failed to open the database file for sync")?;
+        let mut writer = BufWriter::new(f);
+        writer
+            .write_all(doc)
+            .wrap_err("Failed to write the database to disk")?;
         writer
-            .write_all(&doc)
+            .flush()
             .wrap_err("Failed to write the database to disk")?;
+        fs::rename(&tmp_path, &self.path).wrap_err("Failed to replace the database file")?;
+        *self.written() = Some(content_hash(doc));
+
+        Ok(())
+    }
+
+    // Backups are kept in a `backups` directory next to the database. They're straight copies of
+    // the file, so they're in whatever format the database itself is.
+    fn backup(&self) -> Result<()> {
+        let dir = Self::backups_dir(&self.path);
+        permissions::create_private_dir_all(&dir)
+            .wrap_err("Failed to create the backups directory")?;
+
+        let backup = dir.join(Self::backup_name());
+        fs::copy(&self.path, &backup)
+            .wrap_err_with(|| format!("Failed to copy the database to {}", backup.display()))?;
+        debug!("Backed up the database to {}", backup.display());
+
+        Self::rotate_backups(&dir, self.backup_retention)
+    }
+
+    // Deletes all but the `keep` most recently modified backups in `dir`. Other files in the
+    // directory are left alone.
+    pub(crate) fn rotate_backups(dir: &Path, keep: usize) -> Result<()> {
+        for path in Self::list_backups(dir)?.into_iter().skip(keep) {
+            fs::remove_file(&path)
+                .wrap_err_with(|| format!("Failed to delete the old backup {}", path.display()))?;
+            debug!("Deleted the old backup {}", path.display());
+        }
 
         Ok(())
     }
+
+    // Where the backups of the database at `path` are kept.
+    pub(crate) fn backups_dir(path: &Path) -> PathBuf {
+        path.parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join("backups")
+    }
+
+    // The backups in `dir`, most recently modified first.
+    pub(crate) fn list_backups(dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut backups = Vec::new();
+        for entry in fs::read_dir(dir).wrap_err("Failed to list the backups")? {
+            let entry = entry.wrap_err("Failed to list the backups")?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if !(name.starts_with(BACKUP_PREFIX) && name.ends_with(BACKUP_SUFFIX)) {
+                continue;
+            }
+
+            let modified = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .wrap_err_with(|| format!("Failed to get the age of the backup {name}"))?;
+            backups.push((modified, entry.path()));
+        }
+
+        backups.sort_unstable_by(|a, b| b.cmp(a));
+        Ok(backups.into_iter().map(|(_, path)| path).collect())
+    }
+
+    // Only used once the database itself has turned out to be corrupt, so any problems reading the
+    // backups just mean there's nothing to offer.
+    fn latest_readable_backup(path: &Path) -> Option<PathBuf> {
+        Self::list_backups(&Self::backups_dir(path))
+            .ok()?
+            .into_iter()
+            .find(|backup| {
+                File::open(backup).is_ok_and(|file| {
+                    rmp_serde::decode::from_read::<_, Self>(BufReader::new(file)).is_ok()
+                })
+            })
+    }
 }
 
 impl Display for Login {
@@ -346,15 +2827,381 @@ impl AsRef<str> for Login {
 }
 
 impl Login {
+    #[must_use]
     pub fn new(name: String, username: String, password: String) -> Self {
         Self {
             name,
             username,
             password,
+            ..Default::default()
+        }
+    }
+
+    /// Checks for anything about the login which is probably a mistake, such as an empty password.
+    /// Nothing is ruled out, since some logins are only kept for their notes, so it's up to the
+    /// caller to pass these on.
+    #[must_use]
+    pub fn validate(&self) -> Vec<LoginWarning> {
+        let mut warnings = Vec::new();
+        if self.password.is_empty() {
+            warnings.push(LoginWarning::EmptyPassword);
+        }
+        warnings
+    }
+
+    fn sorted_attachments(&self) -> Vec<&Attachment> {
+        let mut attachments: Vec<&Attachment> = self.attachments.iter().collect();
+        attachments.sort_by(|a, b| a.name.cmp(&b.name));
+        attachments
+    }
+
+    // Whether this login and `other` only differ in when they were accessed or changed, which
+    // merging and diffing don't count as a difference.
+    fn same_as(&self, other: &Login) -> bool {
+        self.changed_fields(other).is_empty()
+    }
+
+    // The fields which differ between this login and `other`, with passwords and secret fields
+    // masked. Access times are left out, since they change whenever a login is looked at.
+    fn changed_fields(&self, other: &Login) -> Vec<FieldChange> {
+        let mut changes = Vec::new();
+        let mut compare = |field: &str, here: String, there: String| {
+            if here != there {
+                changes.push(FieldChange {
+                    field: String::from(field),
+                    here,
+                    there,
+                });
+            }
+        };
+
+        compare("name", self.name.clone(), other.name.clone());
+        compare("username", self.username.clone(), other.username.clone());
+        if self.password != other.password {
+            compare(
+                "password",
+                String::from(HIDDEN_PASSWORD),
+                // Masked differently, so that the row doesn't look like nothing changed.
+                String::from("******** (different)"),
+            );
+        }
+        compare(
+            "favorite",
+            self.favorite.to_string(),
+            other.favorite.to_string(),
+        );
+        compare("url", self.url.clone(), other.url.clone());
+        compare("notes", self.notes.clone(), other.notes.clone());
+        compare("tags", self.tags.join(", "), other.tags.join(", "));
+        compare(
+            "folder",
+            self.folder.clone().unwrap_or_default(),
+            other.folder.clone().unwrap_or_default(),
+        );
+
+        let keys: BTreeSet<&String> = self
+            .custom_fields
+            .keys()
+            .chain(other.custom_fields.keys())
+            .collect();
+        for key in keys {
+            let value = |login: &Login| match login.custom_fields.get(key) {
+                Some(_) if login.secret_fields.contains(key) => String::from(HIDDEN_PASSWORD),
+                Some(value) => value.clone(),
+                None => String::new(),
+            };
+            let field = format!("custom field {key}");
+            let (here, there) = (value(self), value(other));
+            if self.custom_fields.get(key) != other.custom_fields.get(key) && here == there {
+                // Both are secret, so they'd look the same once masked.
+                compare(&field, here, String::from("******** (different)"));
+            } else {
+                compare(&field, here, there);
+            }
+        }
+        let attachments = |login: &Login| {
+            login
+                .attachments
+                .iter()
+                .map(|attachment| format!("{} ({} bytes)", attachment.name, attachment.bytes.len()))
+                .sorted()
+                .join(", ")
+        };
+        let (here, there) = (attachments(self), attachments(other));
+        if here == there && self.sorted_attachments() != other.sorted_attachments() {
+            // The same names and sizes, so it's the contents which differ.
+            compare("attachments", here, format!("{there} (different)"));
+        } else {
+            compare("attachments", here, there);
+        }
+        compare(
+            "secret fields",
+            self.secret_fields.iter().sorted().join(", "),
+            other.secret_fields.iter().sorted().join(", "),
+        );
+
+        changes
+    }
+
+    // A copy with the password and secret custom fields hidden, for showing the login somewhere
+    // the secrets can be fetched separately if they're wanted.
+    #[cfg(feature = "web")]
+    pub(crate) fn masked(&self) -> Self {
+        let mut masked = self.clone();
+        if !masked.password.is_empty() {
+            masked.password = String::from(HIDDEN_PASSWORD);
+        }
+        for (key, value) in &mut masked.custom_fields {
+            if self.secret_fields.contains(key) {
+                *value = String::from(HIDDEN_PASSWORD);
+            }
+        }
+        masked
+    }
+
+    // A copy with the `\` taken off an escaped password, for sending the login somewhere which
+    // doesn't know about escaping. References are left as they're written, see
+    // `PasswordReference::unescape()`.
+    #[cfg(any(feature = "web", all(unix, feature = "socket")))]
+    pub(crate) fn unescaped(&self) -> Self {
+        Self {
+            password: PasswordReference::unescape(&self.password).to_owned(),
+            ..self.clone()
+        }
+    }
+
+    /// Where the password is kept, if the login only refers to it, as with `file:~/secrets/email`
+    /// or `cmd:pass show email`, rather than storing the password itself.
+    #[must_use]
+    pub fn password_reference(&self) -> Option<PasswordReference<'_>> {
+        PasswordReference::parse(&self.password)
+    }
+
+    /// The login's password, read from wherever it's kept if the login only refers to it. Commands
+    /// are only run if `allow_commands` is true. An escaped password is given without the escape,
+    /// see [`PasswordReference::escape()`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the password is a reference which can't be read, such as a file which
+    /// doesn't exist or a command which fails, or a command when they aren't allowed.
+    pub fn resolve_password(&self, allow_commands: bool) -> Result<String> {
+        match self.password_reference() {
+            Some(reference) => reference.resolve(allow_commands),
+            None => Ok(PasswordReference::unescape(&self.password).to_owned()),
+        }
+    }
+
+    /// Whether the login is in `folder`, or in one of the folders inside it.
+    #[must_use]
+    pub fn in_folder(&self, folder: &str) -> bool {
+        let Some(folder) = normalise_folder(folder) else {
+            return true;
+        };
+        self.folder.as_deref().is_some_and(|own| {
+            own.strip_prefix(&folder)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+
+    /// Changes the fields which are set in `patch`, leaving the rest as they were.
+    pub fn apply_patch(&mut self, patch: LoginPatch) {
+        let LoginPatch {
+            name,
+            username,
+            password,
+            favorite,
+            url,
+            notes,
+            tags,
+            folder,
+            custom_fields,
+            secret_fields,
+        } = patch;
+
+        if let Some(name) = name {
+            self.name = name;
+        }
+        if let Some(username) = username {
+            self.username = username;
+        }
+        if let Some(password) = password {
+            self.password = password;
+        }
+        if let Some(favorite) = favorite {
+            self.favorite = favorite;
+        }
+        if let Some(url) = url {
+            self.url = url;
+        }
+        if let Some(notes) = notes {
+            self.notes = notes;
+        }
+        if let Some(tags) = tags {
+            self.tags = tags;
+        }
+        if let Some(folder) = folder {
+            self.folder = normalise_folder(&folder);
+        }
+        if let Some(custom_fields) = custom_fields {
+            self.custom_fields = custom_fields;
+        }
+        if let Some(secret_fields) = secret_fields {
+            self.secret_fields = secret_fields;
+        }
+    }
+}
+
+/// The fields of a [`Login`] to change, for updating it without having to send the rest. Fields
+/// which are collections are replaced as a whole.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[cfg_attr(feature = "web", derive(schemars::JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct LoginPatch {
+    pub name: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub favorite: Option<bool>,
+    pub url: Option<String>,
+    pub notes: Option<String>,
+    pub tags: Option<Vec<String>>,
+    /// An empty string takes the login out of its folder.
+    pub folder: Option<String>,
+    pub custom_fields: Option<HashMap<String, String>>,
+    pub secret_fields: Option<HashSet<String>>,
+}
+
+impl LoginField {
+    fn header(self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::Username => "username",
+            Self::Password => "password",
+            Self::Favorite => "favorite",
+            Self::Url => "url",
+            Self::Notes => "notes",
+            Self::Tags => "tags",
+            Self::Folder => "folder",
+        }
+    }
+
+    // Favorites are shown with a star so that they stand out, rather than as `true`/`false`.
+    fn display(self, login: &Login, show_passwords: bool) -> String {
+        match self {
+            Self::Name => login.name.clone(),
+            Self::Username => login.username.clone(),
+            Self::Password if show_passwords => login.password.clone(),
+            Self::Password => String::from(HIDDEN_PASSWORD),
+            Self::Favorite => String::from(if login.favorite { "★" } else { "" }),
+            Self::Url => login.url.clone(),
+            Self::Notes => login.notes.clone(),
+            Self::Tags => login.tags.join(", "),
+            Self::Folder => login.folder.clone().unwrap_or_default(),
+        }
+    }
+
+    fn json(self, login: &Login, show_passwords: bool) -> serde_json::Value {
+        match self {
+            Self::Favorite => serde_json::Value::Bool(login.favorite),
+            Self::Tags => serde_json::Value::from(login.tags.clone()),
+            Self::Folder => serde_json::Value::from(login.folder.clone()),
+            _ => serde_json::Value::String(self.display(login, show_passwords)),
+        }
+    }
+}
+
+// The file beside the database at `path` used by `data_lock()`.
+pub(crate) fn data_lock_path(path: &Path) -> PathBuf {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    PathBuf::from(lock_path)
+}
+
+// Locks the file beside the database at `path` which guards reads and writes of the database itself,
+// shared for reading and exclusive for writing, and waits until it can. This is separate from the
+// lockfile held by whichever instance is changing the database, which is held for much longer.
+// Locking is best effort, if the file can't be created, e.g. because the directory is read-only,
+// the database is used without it.
+fn data_lock(path: &Path, exclusive: bool) -> Option<File> {
+    let lock_path = data_lock_path(path);
+
+    let file =
+        match permissions::private(OpenOptions::new().create(true).truncate(false).write(true))
+            .open(&lock_path)
+        {
+            Ok(file) => file,
+            Err(e) => {
+                debug!("Failed to open {}: {e}", lock_path.display());
+                return None;
+            }
+        };
+
+    let locked = if exclusive {
+        file.lock_exclusive()
+    } else {
+        file.lock_shared()
+    };
+    if let Err(e) = locked {
+        debug!("Failed to lock {}: {e}", lock_path.display());
+        return None;
+    }
+
+    Some(file)
+}
+
+// Stores the passwords of logins from somewhere other than the command line as they are, where they
+// would otherwise be references to files or commands, warning about each one. See
+// `PasswordReference`.
+fn escape_references<'a>(logins: impl IntoIterator<Item = &'a mut Login>) {
+    for login in logins {
+        if let Cow::Owned(escaped) = PasswordReference::escape(&login.password) {
+            eprintln!(
+                "{}",
+                style(format!("! {login}: the password looks like a reference to a file or command, so it's stored as it is. Pass `--allow-references` to keep it as a reference")).yellow()
+            );
+            login.password = escaped;
         }
     }
 }
 
+// Prints the warnings from `Login::validate()` for a login which is being added without prompting.
+fn warn_about(login: &Login) {
+    for warning in login.validate() {
+        eprintln!("{}", style(format!("! {login}: {warning}")).yellow());
+    }
+}
+
+// Tidies up a folder path, so that `/Work//Cloud/` and `Work/Cloud` are the same folder. An empty
+// path is no folder at all.
+pub(crate) fn normalise_folder(folder: &str) -> Option<String> {
+    let folder = folder
+        .split('/')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .join("/");
+    (!folder.is_empty()).then_some(folder)
+}
+
+// Only compared with other hashes made by the same process, so it doesn't need to be stable.
+fn content_hash(doc: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    doc.hash(&mut hasher);
+    hasher.finish()
+}
+
+// The time now, to the second, since that's all that's kept of the times in `Login`, so that a
+// login compares the same before and after it's been saved.
+pub(crate) fn now() -> DateTime<Utc> {
+    Utc::now().trunc_subsecs(0)
+}
+
+pub(crate) fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
 // A tuple struct which simply allows us to have custom `Deref` behaviour on a `(&Uuid, &Login)`.
 // We need this because of how nucleo works.
 struct LoginAndId<'a>(&'a Uuid, &'a Login);
@@ -365,7 +3212,7 @@ impl<'a> From<(&'a Uuid, &'a Login)> for LoginAndId<'a> {
     }
 }
 
-impl<'a> AsRef<str> for LoginAndId<'a> {
+impl AsRef<str> for LoginAndId<'_> {
     fn as_ref(&self) -> &str {
         &self.1.name
     }
@@ -1,387 +1,1216 @@
 use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashMap},
+    fmt::Write,
     fs,
-    hint::unreachable_unchecked,
-    io::ErrorKind,
-    path::Path,
+    io::{Cursor, ErrorKind, Read},
+    path::{Component, Path, PathBuf},
     str::FromStr,
     sync::atomic::{AtomicBool, Ordering},
-    sync::Arc,
+    sync::{mpsc::Receiver, Arc, Mutex, MutexGuard, PoisonError, RwLock},
+    time::{Duration, Instant},
 };
 
 use color_eyre::eyre::{bail, Result, WrapErr};
+use itertools::Itertools;
 use log::{debug, error, info, warn};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::de::DeserializeOwned;
 use signal_hook::consts::SIGINT;
-use tiny_http::{Header, Request, Response, StatusCode};
+use tiny_http::{Header, Method, Request, Response, StatusCode};
 use url::Url;
 use uuid::Uuid;
 
-use crate::models::{Database, Login};
+use crate::errors::{AddError, ApiError, AttachError, LoginWarning};
+use crate::models::{Attachment, Config, Database, Login, LoginPatch};
+use crate::openapi;
+use crate::reference::{self, PasswordReference};
+use crate::router::{Params, RouteError, Router};
+use crate::threadpool::Threadpool;
+use crate::watch;
 
-pub fn serve(db: &mut Database, port: u16, lck_path: &Path) -> Result<()> {
+// The number of threads used to handle requests, unless `Server::with_workers()` says otherwise.
+const WORKERS: usize = 4;
+// How many requests can wait for a worker, for each worker. Any more are turned away with a 503,
+// rather than being queued without limit.
+const QUEUE_PER_WORKER: usize = 32;
+// The most threads `web_workers` can ask for. More than this would only use memory, since every
+// request has to take the database lock anyway.
+const MAX_WORKERS: usize = 64;
+// The names the server can be reached by in the `Host` header, see `check_host()`.
+const LOCAL_HOSTS: [&str; 3] = ["127.0.0.1", "localhost", "[::1]"];
+// How long requests still being handled are given to finish once the server is told to stop.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+// The largest request body that will be read, anything bigger is rejected with a 413.
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+// How often the server checks whether it's been asked to shut down while it's waiting for requests.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(250);
+// The most idempotency keys remembered at once. Once there are this many, the oldest is forgotten
+// to make room for each new one.
+const MAX_IDEMPOTENCY_KEYS: usize = 1024;
+
+// Requests are handled concurrently on a threadpool, so the database is shared between the workers
+// behind a mutex. See the comment on `Database` for how it must be locked. Returns once the server
+// has been asked to shut down with SIGINT and the requests already in progress have finished; it's
+// up to the caller to sync the database afterwards. With `watch`, changes to the configuration file
+// are applied as it runs.
+pub(crate) fn serve(db: &Arc<Mutex<Database>>, config: &Config, watch: bool) -> Result<()> {
     let should_shutdown = Arc::new(AtomicBool::new(false));
     signal_hook::flag::register(SIGINT, Arc::clone(&should_shutdown))
         .wrap_err("Failed to register the shutdown bool")?;
-    let ip = format!("127.0.0.1:{port}");
-    let server = tiny_http::Server::http(&ip)
-        .map_err(|e| color_eyre::eyre::eyre!(e))
-        .wrap_err_with(|| format!("Failed to start server at {ip}"))?;
-
-    info!("Serving webpage at {ip}");
-    for request in server.incoming_requests() {
-        use tiny_http::Method as M;
-        let url = match Url::from_str(&format!("https://{ip}"))
-            .expect("Please don't put any rubbish in this url")
-            .join(request.url())
-        {
-            Ok(url) => url,
-            Err(e) => {
-                error!(
-                    "Failed to parse a url: `{}`, with err: {}",
-                    request.url(),
-                    e
-                );
-                std::process::exit(1)
+
+    if config.web_workers == 0 {
+        bail!("`web_workers` must be at least 1");
+    }
+    let workers = if config.web_workers > MAX_WORKERS {
+        warn!(
+            "`web_workers` is {}, using the maximum of {MAX_WORKERS} instead",
+            config.web_workers
+        );
+        MAX_WORKERS
+    } else {
+        config.web_workers
+    };
+
+    let mut server = Server::bind(config.port)?
+        .with_assets_dir(config.web_assets_dir.clone())
+        .with_idempotency_ttl(Duration::from_secs(config.idempotency_ttl_secs))
+        .with_snapshots(config.allow_snapshot_download)
+        .with_workers(workers);
+    // Kept until the server stops, since the file is only watched while it's alive.
+    let _watcher = if watch {
+        let (watcher, reloads) = watch::watch_config(config)?;
+        server = server.with_config_reloads(reloads);
+        info!("Watching the configuration file for changes");
+        Some(watcher)
+    } else {
+        None
+    };
+    info!(
+        "Serving webpage at 127.0.0.1:{} with {workers} workers",
+        server.port()
+    );
+    server.run(db, &should_shutdown)?;
+
+    info!("Shutting down, waiting for requests in progress to finish");
+    Ok(())
+}
+
+/// The web server, bound to a port on localhost but not yet handling requests.
+pub struct Server {
+    inner: tiny_http::Server,
+    port: u16,
+    workers: usize,
+    site: Site,
+    reloads: Option<Receiver<Config>>,
+}
+
+// What the handlers need to know about the server, besides the database.
+struct Site {
+    base: Url,
+    // Behind a lock, like the idempotency keys, so that `serve --watch` can change it.
+    assets_dir: RwLock<Option<PathBuf>>,
+    idempotency_keys: Mutex<IdempotencyKeys>,
+    // Whether `/api/v1/snapshot` is allowed, see `Config::allow_snapshot_download`.
+    snapshots: AtomicBool,
+    router: Router<Handler>,
+}
+
+impl Site {
+    // Applies the settings the server itself uses from a configuration file which has changed.
+    fn reconfigure(&self, config: &Config) {
+        self.assets_dir
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone_from(&config.web_assets_dir);
+        self.idempotency_keys
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .ttl = Duration::from_secs(config.idempotency_ttl_secs);
+        self.snapshots
+            .store(config.allow_snapshot_download, Ordering::Relaxed);
+    }
+}
+
+// The responses already sent to requests to `/api/v1/new` with an `Idempotency-Key` header, so that
+// a client which retries a request it didn't see the response to gets the same response again,
+// instead of the logins being added twice. These only last as long as the server does.
+struct IdempotencyKeys {
+    ttl: Duration,
+    responses: HashMap<String, (Instant, SavedResponse)>,
+}
+
+#[derive(Clone)]
+struct SavedResponse {
+    status: u16,
+    body: String,
+    location: Option<String>,
+}
+
+impl IdempotencyKeys {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            responses: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<SavedResponse> {
+        self.forget_expired();
+        self.responses
+            .get(key)
+            .map(|(_, response)| response.clone())
+    }
+
+    fn insert(&mut self, key: String, response: SavedResponse) {
+        if self.ttl.is_zero() {
+            return;
+        }
+
+        self.forget_expired();
+        if self.responses.len() >= MAX_IDEMPOTENCY_KEYS {
+            if let Some(oldest) = self
+                .responses
+                .iter()
+                .min_by_key(|(_, (saved_at, _))| *saved_at)
+                .map(|(key, _)| key.clone())
+            {
+                self.responses.remove(&oldest);
             }
-        };
-        // TODO: Go through all of these functions, and check that they follow the proper behaviour, returning correct status codes, etc.
-        match (request.method(), url.path()) {
-            (
-                M::Get,
-                "/" | "/new" | "/index.css" | "/query.js" | "/query.js.map" | "/form.js"
-                | "/form.js.map",
-            ) => serve_static(request),
-            (M::Get, "/query") => serve_query_page(
-                request,
-                url.query_pairs()
-                    .find(|query| &query.0 == "query")
-                    .map(|query| query.1)
-                    .as_deref(),
-                db,
-            ),
-            (M::Get, "/api/v1/query") => serve_query(
-                request,
-                url.query_pairs()
-                    .find(|query| &query.0 == "query")
-                    .map(|query| query.1)
-                    .as_deref(),
-                db,
-            ),
-            (M::Get, "/api/v1/sync") => {
-                db.sync()
-                    .wrap_err("Failed to sync database after it was requested via API")?;
-                let Err(err) = request.respond(
-                    Response::from_string(StatusCode(204).default_reason_phrase())
-                        .with_status_code(204),
-                ) else {
+        }
+        self.responses.insert(key, (Instant::now(), response));
+    }
+
+    fn forget_expired(&mut self) {
+        let ttl = self.ttl;
+        self.responses
+            .retain(|_, (saved_at, _)| saved_at.elapsed() < ttl);
+    }
+}
+
+impl SavedResponse {
+    fn to_response(&self) -> ApiResponse {
+        let mut response = with_content_type(
+            Response::from_string(self.body.as_str()).with_status_code(self.status),
+            "application/json",
+        );
+        if let Some(location) = &self.location {
+            let location = Header::from_bytes("Location", location.as_str())
+                .expect("A path containing a UUID is a valid header value");
+            response.add_header(location);
+        }
+
+        response
+    }
+}
+
+impl Server {
+    /// Binds to `port` on localhost. If `port` is 0, the OS picks a free one, which can be found
+    /// with [`Server::port()`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the port couldn't be bound.
+    pub fn bind(port: u16) -> Result<Self> {
+        let ip = format!("127.0.0.1:{port}");
+        let inner = tiny_http::Server::http(&ip)
+            .map_err(|e| color_eyre::eyre::eyre!(e))
+            .wrap_err_with(|| format!("Failed to start server at {ip}"))?;
+        let port = inner.server_addr().to_ip().map_or(port, |addr| addr.port());
+        let base = Url::from_str(&format!("https://127.0.0.1:{port}"))
+            .wrap_err("Failed to build the base url")?;
+
+        Ok(Self {
+            inner,
+            port,
+            workers: WORKERS,
+            site: Site {
+                base,
+                assets_dir: RwLock::new(None),
+                idempotency_keys: Mutex::new(IdempotencyKeys::new(Duration::ZERO)),
+                snapshots: AtomicBool::new(false),
+                router: routes(),
+            },
+            reloads: None,
+        })
+    }
+
+    /// Serves the web UI's static files from `dir` where they exist there, rather than the ones
+    /// built into Locket.
+    #[must_use]
+    pub fn with_assets_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.site.assets_dir = RwLock::new(dir);
+        self
+    }
+
+    /// Remembers the response to each request to `/api/v1/new` with an `Idempotency-Key` header
+    /// for `ttl`, and sends it again for any request with the same key, rather than adding the
+    /// logins again. A `ttl` of zero turns this off.
+    #[must_use]
+    pub fn with_idempotency_ttl(mut self, ttl: Duration) -> Self {
+        self.site.idempotency_keys = Mutex::new(IdempotencyKeys::new(ttl));
+        self
+    }
+
+    /// Lets `/api/v1/snapshot` download the whole database if `allow` is true. Otherwise, as it is
+    /// by default, the endpoint is forbidden.
+    #[must_use]
+    pub fn with_snapshots(self, allow: bool) -> Self {
+        self.site.snapshots.store(allow, Ordering::Relaxed);
+        self
+    }
+
+    /// Handles up to `workers` requests at once.
+    ///
+    /// # Panics
+    ///
+    /// [`Server::run()`] panics if `workers` is 0.
+    #[must_use]
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Applies each configuration sent through `reloads` while the server runs. The settings for
+    /// the server which can change without binding it again, and the settings for the database,
+    /// are taken from it, and the rest are ignored.
+    #[must_use]
+    pub fn with_config_reloads(mut self, reloads: Receiver<Config>) -> Self {
+        self.reloads = Some(reloads);
+        self
+    }
+
+    /// The port the server is actually bound to.
+    #[must_use]
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Handles requests until `should_shutdown` is set, then waits for the requests already in
+    /// progress to finish.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the threads to handle requests on couldn't be started.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the server was given 0 workers, see [`Server::with_workers()`].
+    pub fn run(self, db: &Arc<Mutex<Database>>, should_shutdown: &AtomicBool) -> Result<()> {
+        let pool = Threadpool::with_capacity(self.workers, self.workers * QUEUE_PER_WORKER)
+            .wrap_err("Failed to start the threads for requests")?;
+        let site = Arc::new(self.site);
+
+        while !should_shutdown.load(Ordering::Relaxed) {
+            if let Some(reloads) = &self.reloads {
+                for config in reloads.try_iter() {
+                    site.reconfigure(&config);
+                    lock(db).configure(&config);
+                }
+            }
+
+            let request = match self.inner.recv_timeout(SHUTDOWN_POLL_INTERVAL) {
+                Ok(Some(request)) => request,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("Failed to receive a request: {e}");
                     continue;
-                };
+                }
+            };
 
-                warn!("Failed to respond to a request: {err:#?}");
+            // This is the only thread which gives the pool jobs, so if there's room in the queue
+            // now, there still will be when the job is added. `try_exec()` can't be used instead,
+            // since the request would be lost inside the job it gives back.
+            if pool.is_full() {
+                debug!(
+                    "Turned away {} {}, too busy",
+                    request.method(),
+                    request.url()
+                );
+                let busy = ApiError::ServiceUnavailable(String::from(
+                    "The server is too busy, please try again shortly",
+                ));
+                if let Err(e) = request.respond(busy.into_response()) {
+                    warn!("Failed to respond to a request: {e:#?}");
+                }
+                continue;
             }
-            (M::Post, "/api/v1/new") => add_new(request, db),
-            (M::Delete, "/api/v1/remove") => remove_login(
-                request,
-                url.query_pairs()
-                    .find(|query| &query.0 == "id")
-                    .map(|query| query.1)
-                    .as_deref(),
-                db,
-            ),
-            _ => {
-                info!("404 served: {}", url.path());
-                serve_404(request);
+
+            let site = Arc::clone(&site);
+            let db = Arc::clone(db);
+            pool.exec(move || handle(request, &site, &db));
+        }
+
+        // A request which never finishes shouldn't stop the server from shutting down, so the
+        // workers are only given so long to finish what they're doing.
+        let running = pool.shutdown_timeout(SHUTDOWN_TIMEOUT);
+        if !running.is_empty() {
+            warn!(
+                "{} worker(s) were still handling requests after {} seconds, shutting down anyway",
+                running.len(),
+                SHUTDOWN_TIMEOUT.as_secs()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+type ApiResponse = Response<Cursor<Vec<u8>>>;
+type ApiResult = Result<ApiResponse, ApiError>;
+
+// Responds to a single request. Handlers return an `ApiError` rather than responding themselves
+// when something goes wrong, and it's turned into a response here.
+fn handle(mut request: Request, site: &Site, db: &Mutex<Database>) {
+    let response = match route(&mut request, site, db) {
+        Ok(response) => response,
+        Err(e) => {
+            if let ApiError::Internal(_) = e {
+                error!("{} {}: {e}", request.method(), request.url());
+            } else {
+                debug!("{} {}: {e}", request.method(), request.url());
             }
+            e.into_response()
         }
+    };
 
-        if should_shutdown.load(Ordering::Relaxed) {
-            db.sync().wrap_err("Failed to sync database to disk")?;
-            if let Err(err) = fs::remove_file(lck_path) {
-                match err.kind() {
-                    ErrorKind::NotFound => {
-                        eprintln!("Tried to remove the lockfile, but it wasn't present");
-                        std::process::exit(1);
-                    }
-                    _ => bail!("Failed to remove the lockfile: {}", err),
-                }
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to respond to a request: {e:#?}");
+    }
+}
+
+fn route(request: &mut Request, site: &Site, db: &Mutex<Database>) -> ApiResult {
+    check_host(request, site)?;
+    let url = site.base.join(request.url()).map_err(|e| {
+        ApiError::BadRequest(format!("Failed to parse the url `{}`: {e}", request.url()))
+    })?;
+
+    match site.router.find(request.method(), url.path()) {
+        Ok((handler, params)) => {
+            let cx = Context {
+                url,
+                params,
+                site,
+                db,
             };
+            handler(request, &cx)
+        }
+        // Anything else which is fetched is one of the web UI's files.
+        Err(RouteError::NotFound) if *request.method() == Method::Get => serve_static(
+            url.path(),
+            site.assets_dir
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .as_deref(),
+        ),
+        Err(RouteError::NotFound) => {
+            info!("404 served: {}", url.path());
+            Err(ApiError::NotFound(format!(
+                "There is nothing at {}",
+                url.path()
+            )))
         }
+        Err(RouteError::MethodNotAllowed(allowed)) => Err(ApiError::MethodNotAllowed {
+            message: format!("{} doesn't accept {}", url.path(), request.method()),
+            allow: allowed.iter().join(", "),
+        }),
     }
-    Ok(())
 }
 
-// In debug mode, we can do a sort of "hot-reloading", by just reopening the same files
-// over and over again. Therefore, we can use `unwrap()`, as in my opinion, if someone
-// is editing this project's code, and doesn't have these files in the right places, it's
-// their fault, and it's my project so I can do what I like :^).
-#[cfg(debug_assertions)]
-fn serve_static(request: Request) {
-    match request.url() {
-        "/" => serve_bytes(
-            request,
-            &fs::read("src/web/index.html").expect("Failed to open index.html")[..],
-            "text/html; charset=utf8",
-        ),
-        "/new" => serve_bytes(
-            request,
-            &fs::read("src/web/form.html").expect("Failed to open form.html")[..],
-            "text/html; charset=utf8",
-        ),
-        "/index.css" => serve_bytes(
-            request,
-            &fs::read("dist/index.css").expect("Failed to open index.css")[..],
-            "text/css; charset=utf8",
-        ),
-        "/query.js" => serve_bytes(
-            request,
-            &fs::read("dist/query.js").expect("Failed to open query.js")[..],
-            "application/javascript; charset=utf8",
-        ),
-        "/query.js.map" => serve_bytes(
-            request,
-            &fs::read("dist/query.js.map").expect("Failed to open query.js.map")[..],
-            "application/javascript; charset=utf8",
-        ),
-        "/form.js" => serve_bytes(
-            request,
-            &fs::read("dist/form.js").expect("Failed to open form.js")[..],
-            "application/javascript; charset=utf8",
-        ),
-        "/form.js.map" => serve_bytes(
-            request,
-            &fs::read("dist/form.js.map").expect("Failed to open form.js.map")[..],
-            "application/javascript; charset=utf8",
-        ),
-        _ => unsafe { unreachable_unchecked() },
+// Browsers send the name they looked up in `Host`, so only accepting the names of the loopback
+// address stops a page on another site from reaching the server by pointing its own name at
+// 127.0.0.1, which is known as DNS rebinding.
+fn check_host(request: &Request, site: &Site) -> Result<(), ApiError> {
+    let Some(host) = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Host"))
+    else {
+        return Err(ApiError::BadRequest(String::from(
+            "The request has no `Host` header",
+        )));
     };
+
+    let host = host.value.as_str();
+    let (name, port) = host
+        .rsplit_once(':')
+        .filter(|(_, port)| !port.contains(']'))
+        .map_or((host, None), |(name, port)| (name, Some(port)));
+    let port_matches =
+        port.is_none_or(|port| port.parse().ok() == site.base.port_or_known_default());
+    if LOCAL_HOSTS.contains(&name.to_ascii_lowercase().as_str()) && port_matches {
+        Ok(())
+    } else {
+        Err(ApiError::Forbidden(format!(
+            "`{host}` isn't this server, which only answers to localhost"
+        )))
+    }
 }
 
-// Release mode version of the previous function. Here, it uses `include_bytes!()` to
-// pack the content of the files into the binary.
-#[cfg(not(debug_assertions))]
-fn serve_static(request: Request) {
-    match request.url() {
-        "/" => serve_bytes(
-            request,
-            &include_bytes!("web/index.html")[..],
-            "text/html; charset=utf8",
-        ),
-        "/new" => serve_bytes(
-            request,
-            &include_bytes!("web/form.html")[..],
-            "text/html; charset=utf8",
-        ),
-        "/index.css" => serve_bytes(
-            request,
-            &include_bytes!("../dist/index.css")[..],
-            "text/css; charset=utf8",
-        ),
-        "/query.js" => serve_bytes(
-            request,
-            &include_bytes!("../dist/query.js")[..],
-            "application/javascript; charset=utf8",
-        ),
-        "/query.js.map" => serve_bytes(
-            request,
-            &include_bytes!("../dist/query.js.map")[..],
-            "application/javascript; charset=utf8",
-        ),
-        "/form.js" => serve_bytes(
-            request,
-            &include_bytes!("../dist/form.js")[..],
-            "application/javascript; charset=utf8",
-        ),
-        "/form.js.map" => serve_bytes(
+// What a handler is given besides the request, which it reads the body from if it needs one.
+struct Context<'a> {
+    url: Url,
+    params: Params,
+    site: &'a Site,
+    db: &'a Mutex<Database>,
+}
+
+impl Context<'_> {
+    // A parameter from the path, if the route has one called `name`, or otherwise from the query
+    // string.
+    fn param(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.params
+            .get(name)
+            .map(Cow::Borrowed)
+            .or_else(|| query_param(&self.url, name))
+    }
+}
+
+type Handler = fn(&mut Request, &Context<'_>) -> ApiResult;
+
+// Every route the server has, besides the web UI's static files. The routes for a single login and
+// its attachments take the id and the attachment's name in the path, and the older routes which
+// take them in the query string are kept working alongside them, with the same handlers.
+// TODO: Go through all of these functions, and check that they follow the proper behaviour, returning correct status codes, etc.
+fn routes() -> Router<Handler> {
+    use tiny_http::Method as M;
+
+    let login: Handler = |_, cx| get_login(cx.param("id").as_deref(), &mut lock(cx.db));
+    let update: Handler = |request, cx| update_login(request, cx.param("id").as_deref(), cx.db);
+    let remove: Handler = |_, cx| {
+        remove_login(
+            cx.param("id").as_deref(),
+            cx.param("echo").as_deref() == Some("true"),
+            &mut lock(cx.db),
+        )
+    };
+    let download: Handler = |_, cx| {
+        get_attachment(
+            cx.param("id").as_deref(),
+            cx.param("name").as_deref(),
+            &mut lock(cx.db),
+        )
+    };
+    let upload: Handler = |request, cx| {
+        add_attachment(
             request,
-            &include_bytes!("../dist/form.js.map")[..],
-            "application/javascript; charset=utf8",
-        ),
-        _ => unsafe { unreachable_unchecked() },
+            cx.param("id").as_deref(),
+            cx.param("name").as_deref(),
+            cx.db,
+        )
     };
+
+    Router::<Handler>::new()
+        .route(M::Get, "/query", |_, cx| {
+            Ok(serve_query_page(
+                cx.param("query").as_deref(),
+                cx.param("folder").as_deref(),
+                &lock(cx.db),
+            ))
+        })
+        .route(M::Get, "/edit", |_, cx| {
+            serve_edit_page(cx.param("id").as_deref(), &lock(cx.db))
+        })
+        .route(M::Get, "/api/v1/query", |_, cx| {
+            serve_query(
+                cx.param("query").as_deref(),
+                cx.param("folder").as_deref(),
+                cx.param("masked").as_deref() == Some("true"),
+                &lock(cx.db),
+            )
+        })
+        .route(M::Get, "/api/v1/folders", |_, cx| {
+            serve_folders(&lock(cx.db))
+        })
+        .route(M::Get, "/api/v1/sync", |_, cx| sync(&lock(cx.db)))
+        .route(M::Get, "/api/v1/snapshot", |_, cx| {
+            if cx.site.snapshots.load(Ordering::Relaxed) {
+                snapshot(&lock(cx.db))
+            } else {
+                Err(ApiError::Forbidden(String::from(
+                    "Downloading the database is off, set `allow_snapshot_download` in the configuration file to allow it",
+                )))
+            }
+        })
+        .route(M::Get, "/api/v1/openapi.json", |_, _| Ok(serve_openapi()))
+        .route(M::Post, "/api/v1/new", |request, cx| {
+            add_new(request, cx.db, &cx.site.idempotency_keys)
+        })
+        .route(M::Get, "/api/v1/login/:id", login)
+        .route(M::Patch, "/api/v1/login/:id", update)
+        .route(M::Delete, "/api/v1/login/:id", remove)
+        .route(M::Get, "/api/v1/login/:id/attachments/:name", download)
+        .route(M::Post, "/api/v1/login/:id/attachments/:name", upload)
+        .route(M::Get, "/api/v1/login", login)
+        .route(M::Patch, "/api/v1/update", update)
+        .route(M::Delete, "/api/v1/remove", remove)
+        .route(M::Get, "/api/v1/attachment", download)
+        .route(M::Post, "/api/v1/attachment", upload)
+}
+
+fn query_param<'a>(url: &'a Url, key: &str) -> Option<Cow<'a, str>> {
+    url.query_pairs()
+        .find(|query| query.0 == key)
+        .map(|query| query.1)
+}
+
+// A handler panicking while it holds the lock poisons the mutex. The handlers only ever make
+// single, complete changes to the database, so it's still consistent and we carry on with it
+// rather than taking every later request down too.
+fn lock(db: &Mutex<Database>) -> MutexGuard<'_, Database> {
+    db.lock().unwrap_or_else(|poisoned| {
+        warn!("A thread panicked while holding the database lock");
+        poisoned.into_inner()
+    })
+}
+
+fn status(status: u16) -> ApiResponse {
+    Response::from_string(StatusCode(status).default_reason_phrase()).with_status_code(status)
 }
 
-fn serve_bytes(request: Request, content: &[u8], content_type: &str) {
-    let content_type_header = Header::from_bytes("Content-Type", content_type)
+fn with_content_type(response: ApiResponse, content_type: &str) -> ApiResponse {
+    let header = Header::from_bytes("Content-Type", content_type)
         .expect("Please don't put rubbish inside `content_type`");
-    let response = Response::from_data(content).with_header(content_type_header);
+    response.with_header(header)
+}
 
-    if let Err(e) = request.respond(response) {
-        warn!("Failed to respond to a request: {e:#?}");
+// The web UI's static files, by the path each is served at. Nothing else is served, whatever else
+// is in the assets directory, or in `dist` in debug mode.
+const ASSETS: [(&str, &str); 8] = [
+    ("/", "index.html"),
+    ("/new", "form.html"),
+    ("/logins", "logins.html"),
+    ("/index.css", "index.css"),
+    ("/query.js", "query.js"),
+    ("/query.js.map", "query.js.map"),
+    ("/form.js", "form.js"),
+    ("/form.js.map", "form.js.map"),
+];
+
+// Serves one of the web UI's static files in `ASSETS`, from the assets directory if one is
+// configured and it has the file, or otherwise from the ones built into Locket. Every path goes
+// through `sanitize_asset_path()` as well, so nothing outside of the assets can be served, even by
+// accident, such as through a symlink in the assets directory.
+fn serve_static(path: &str, assets_dir: Option<&Path>) -> ApiResult {
+    // Paths which try to climb out are refused as such, rather than just not being found.
+    asset_relative_path(&percent_decode_str(path.trim_start_matches('/')).decode_utf8_lossy())?;
+    let Some((_, name)) = ASSETS.iter().find(|(served_at, _)| *served_at == path) else {
+        return Err(ApiError::NotFound(format!("There is nothing at {path}")));
+    };
+    let relative = Path::new(name);
+
+    let content = match assets_dir {
+        Some(dir) => match read_asset(&sanitize_asset_path(dir, relative)?)? {
+            Some(content) => Some(content),
+            None => builtin_asset(relative)?,
+        },
+        None => builtin_asset(relative)?,
+    };
+    let content =
+        content.ok_or_else(|| ApiError::NotFound(format!("There is nothing at {path}")))?;
+
+    Ok(with_content_type(
+        Response::from_data(content),
+        content_type(relative),
+    ))
+}
+
+// Checks that `requested` is made up only of plain names, so it can't climb out of whichever
+// directory it's joined onto, or replace it with an absolute path.
+fn asset_relative_path(requested: &str) -> Result<&Path, ApiError> {
+    let relative = Path::new(requested);
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(ApiError::Forbidden(format!(
+            "`{requested}` is outside of the web assets"
+        )));
+    }
+
+    Ok(relative)
+}
+
+// Resolves `requested` against `root`. Besides the checks done by `asset_relative_path()`, the
+// path is canonicalised when it exists, so that a symlink can't lead outside of `root` either.
+fn sanitize_asset_path(root: &Path, requested: &Path) -> Result<PathBuf, ApiError> {
+    let path = root.join(asset_relative_path(&requested.to_string_lossy())?);
+    let canonical = match path.canonicalize() {
+        Ok(canonical) => canonical,
+        // Doesn't exist, so reading it will just fail.
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(path),
+        Err(e) => {
+            return Err(ApiError::Internal(format!(
+                "Failed to resolve {}: {e}",
+                path.display()
+            )))
+        }
+    };
+    let root = root
+        .canonicalize()
+        .map_err(|e| ApiError::Internal(format!("Failed to resolve {}: {e}", root.display())))?;
+
+    if !canonical.starts_with(&root) {
+        return Err(ApiError::Forbidden(format!(
+            "`{}` is outside of the web assets",
+            requested.display()
+        )));
+    }
+
+    Ok(canonical)
+}
+
+// Returns `None` if there's no such file, so the built in one can be used instead.
+fn read_asset(path: &Path) -> Result<Option<Vec<u8>>, ApiError> {
+    if path.is_dir() {
+        return Ok(None);
+    }
+
+    match fs::read(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(ApiError::Internal(format!(
+            "Failed to open {}: {e}",
+            path.display()
+        ))),
     }
 }
 
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("html") => "text/html; charset=utf8",
+        Some("css") => "text/css; charset=utf8",
+        Some("js" | "map") => "application/javascript; charset=utf8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("ico") => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+// In debug mode, we can do a sort of "hot-reloading", by just reopening the same files
+// over and over again. If someone is editing this project's code, and doesn't have these files in
+// the right places, they get a 500 and the reason in the log.
+#[cfg(debug_assertions)]
+fn builtin_asset(name: &Path) -> Result<Option<Vec<u8>>, ApiError> {
+    let root = if matches!(
+        name.to_str(),
+        Some("index.html" | "form.html" | "logins.html")
+    ) {
+        Path::new("src/web")
+    } else {
+        Path::new("dist")
+    };
+
+    read_asset(&sanitize_asset_path(root, name)?)
+}
+
+// Release mode version of the previous function. Here, it uses `include_bytes!()` to
+// pack the content of the files into the binary.
+#[cfg(not(debug_assertions))]
+#[allow(clippy::unnecessary_wraps)]
+fn builtin_asset(name: &Path) -> Result<Option<Vec<u8>>, ApiError> {
+    let content: &[u8] = match name.to_str() {
+        Some("index.html") => include_bytes!("web/index.html"),
+        Some("form.html") => include_bytes!("web/form.html"),
+        Some("logins.html") => include_bytes!("web/logins.html"),
+        Some("index.css") => include_bytes!("../dist/index.css"),
+        Some("query.js") => include_bytes!("../dist/query.js"),
+        Some("query.js.map") => include_bytes!("../dist/query.js.map"),
+        Some("form.js") => include_bytes!("../dist/form.js"),
+        Some("form.js.map") => include_bytes!("../dist/form.js.map"),
+        _ => return Ok(None),
+    };
+
+    Ok(Some(content.to_vec()))
+}
+
 // We should probably allow multiple mime types to be put in the response, by looking at the `Accept` header.
 // However, for now there's probably not much point since we're the only ones consuming this API. Therefore
 // we just ignore all headers, and send back `application/json`.
 // TODO: Maybe look at checking the header to at least see if JSON was requested, and if not return 415 with `Accept-Post` set.
-fn serve_query(request: Request, query: Option<&str>, db: &Database) {
-    let matches = db.query(query);
-    let body = serde_json::ser::to_string(&matches);
-
-    if let Err(e) = body {
-        warn!("Failed to serialise query matches into JSON: {e}");
-        if let Err(e) = request.respond(
-            Response::from_string(StatusCode(500).default_reason_phrase()).with_status_code(500),
-        ) {
-            warn!("Failed to respond to a request: {e:#?}");
-        }
 
-        return;
+// With `masked`, passwords and secret fields are hidden, as the web UI asks for them, fetching a
+// login's secrets from `/api/v1/login` only when they're shown.
+fn serve_query(
+    query: Option<&str>,
+    folder: Option<&str>,
+    masked: bool,
+    db: &Database,
+) -> ApiResult {
+    let mut matches = db.query(query);
+    if let Some(folder) = folder {
+        matches.retain(|(_, login)| login.in_folder(folder));
     }
+    let matches: Vec<(&Uuid, Login)> = matches
+        .into_iter()
+        .map(|(id, login)| {
+            let login = if masked {
+                login.masked()
+            } else {
+                login.unescaped()
+            };
+            (id, login)
+        })
+        .collect();
+    let body = serde_json::ser::to_string(&matches).map_err(|e| {
+        ApiError::Internal(format!("Failed to serialise query matches into JSON: {e}"))
+    })?;
 
-    // *Should* be fine.
-    let body = unsafe { body.unwrap_unchecked() };
-    let header = Header::from_bytes("Content-Type", "application/json")
-        .expect("Don't put rubbish in here please");
-    let response = Response::from_string(body)
-        .with_header(header)
-        .with_status_code(200);
+    Ok(with_content_type(
+        Response::from_string(body),
+        "application/json",
+    ))
+}
 
-    if let Err(e) = request.respond(response) {
-        warn!("Failed to respond to a request: {e:#?}");
-    };
+fn serve_folders(db: &Database) -> ApiResult {
+    let body = serde_json::ser::to_string(&db.folders()).map_err(|e| {
+        ApiError::Internal(format!("Failed to serialise the folders into JSON: {e}"))
+    })?;
+
+    Ok(with_content_type(
+        Response::from_string(body),
+        "application/json",
+    ))
 }
 
-// This function currently doesn't support the "hot-reloading" that the other static files do. This
-// is due to not using a proper templating library, and instead just formatting the text.
-fn serve_query_page(request: Request, query: Option<&str>, db: &Database) {
-    let logins = db.query(query);
+// The page at `/logins` is rendered by the browser from the JSON API. This server-rendered version
+// is kept for browsers without JavaScript, so everything from the database is escaped. Passwords
+// and secret fields are masked, as they are on that page, and the password is on the edit page.
+// It doesn't support the "hot-reloading" that the static files do, since it's formatted in here.
+fn serve_query_page(query: Option<&str>, folder: Option<&str>, db: &Database) -> ApiResponse {
+    let mut logins = db.query(query);
+    if let Some(folder) = folder {
+        logins.retain(|(_, login)| login.in_folder(folder));
+    }
 
     let mut grids = String::new();
-    for login in logins {
+    for (id, login) in logins {
+        let login = (id, login.masked());
+        let mut custom_fields = String::new();
+        for (key, value) in login.1.custom_fields.iter().sorted() {
+            let _ = write!(
+                custom_fields,
+                include_str!("web/field.html"),
+                key = escape_html(key),
+                value = escape_html(value)
+            );
+        }
+
         let card = format!(
             include_str!("web/card.html"),
-            name = login.1.name,
-            username = login.1.username,
-            password = login.1.password,
+            favorite = if login.1.favorite { "★ " } else { "" },
+            name = escape_html(&login.1.name),
+            username = escape_html(&login.1.username),
+            password = escape_html(&login.1.password),
+            custom_fields = custom_fields,
             id = login.0.simple()
         );
         grids.push_str(&card);
     }
 
-    let header =
-        Header::from_bytes("Content-Type", "text/html").expect("Don't put rubbish in here please");
-    let response = Response::from_string(format!(include_str!("web/query.html"), grid = grids))
-        .with_header(header)
-        .with_status_code(200);
+    with_content_type(
+        Response::from_string(format!(
+            include_str!("web/query.html"),
+            folders = folder_tree(&db.folders(), query),
+            grid = grids
+        )),
+        "text/html",
+    )
+}
 
-    if let Err(e) = request.respond(response) {
-        warn!("Failed to respond to a request: {e:#?}");
+// Like the `/new` page, but filled in with the login's current values, which are saved with
+// `/api/v1/update`. The values come from whoever added the login, so they're escaped.
+fn serve_edit_page(id: Option<&str>, db: &Database) -> ApiResult {
+    let id = parse_id(id)?;
+    let login = db
+        .get(id)
+        .ok_or_else(|| ApiError::NotFound(format!("There is no login with id {id}")))?;
+
+    Ok(with_content_type(
+        Response::from_string(format!(
+            include_str!("web/edit.html"),
+            name = escape_html(&login.name),
+            username = escape_html(&login.username),
+            password = escape_html(PasswordReference::unescape(&login.password)),
+            id = id.simple()
+        )),
+        "text/html",
+    ))
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Links to each folder, indented by how deep it is, which keep the current search. The folders are
+// sorted, so each one is listed straight after the folder it's inside.
+fn folder_tree(folders: &BTreeSet<String>, query: Option<&str>) -> String {
+    if folders.is_empty() {
+        return String::new();
+    }
+
+    let query = utf8_percent_encode(query.unwrap_or_default(), NON_ALPHANUMERIC);
+    let mut tree = format!(
+        r#"<li><a class="hover:underline" href="/query?query={query}">All logins</a></li>"#
+    );
+    for folder in folders {
+        let depth = folder.matches('/').count() + 1;
+        let name = escape_html(folder.rsplit('/').next().unwrap_or(folder));
+        let _ = write!(
+            tree,
+            r#"<li style="padding-left: {depth}rem"><a class="hover:underline" href="/query?query={query}&folder={}">{name}</a></li>"#,
+            utf8_percent_encode(folder, NON_ALPHANUMERIC)
+        );
+    }
+    tree
+}
+
+// The body is read and parsed before the database is locked, so a slow client doesn't hold up
+// every other request.
+fn add_new(
+    request: &mut Request,
+    db: &Mutex<Database>,
+    idempotency_keys: &Mutex<IdempotencyKeys>,
+) -> ApiResult {
+    let logins: Vec<Login> = read_json_body(request)?;
+
+    let Some(key) = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Idempotency-Key"))
+        .map(|header| header.value.to_string())
+    else {
+        return Ok(add_logins(logins, db)?.to_response());
     };
+
+    // Held until the response has been saved, so that a retry arriving while the first request is
+    // still being handled waits for it, rather than adding the logins a second time.
+    let mut idempotency_keys = idempotency_keys
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if let Some(saved) = idempotency_keys.get(&key) {
+        debug!("Replaying the response for the idempotency key {key:?}");
+        return Ok(saved.to_response());
+    }
+
+    let response = add_logins(logins, db)?;
+    idempotency_keys.insert(key, response.clone());
+    Ok(response.to_response())
 }
 
-fn add_new(mut request: Request, db: &mut Database) {
-    let body_length = request.body_length().unwrap_or(0);
-    let mut buf: Vec<u8> = Vec::with_capacity(body_length);
+// Reads a body of at most `limit` bytes.
+fn read_body(request: &mut Request, limit: u64) -> Result<Vec<u8>, ApiError> {
+    let too_large = || {
+        ApiError::PayloadTooLarge(format!(
+            "The body of the request must be at most {limit} bytes"
+        ))
+    };
+    if request
+        .body_length()
+        .is_some_and(|length| length as u64 > limit)
+    {
+        return Err(too_large());
+    }
+
+    let mut buf: Vec<u8> = Vec::with_capacity(request.body_length().unwrap_or(0));
+    // Read one byte more than we allow, so we can tell whether there was more without a
+    // `Content-Length` to go by.
+    request
+        .as_reader()
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut buf)
+        .map_err(|e| {
+            ApiError::BadRequest(format!("Could not read the body of the request: {e}"))
+        })?;
+    if buf.len() as u64 > limit {
+        return Err(too_large());
+    }
+
+    Ok(buf)
+}
+
+// Reads a JSON body of at most `MAX_BODY_SIZE` bytes, and parses it as a `T`.
+fn read_json_body<T: DeserializeOwned>(request: &mut Request) -> Result<T, ApiError> {
     let Some(content_type_header) = request
         .headers()
         .iter()
         .find(|header| header.field.as_str() == "Content-Type")
     else {
-        debug!("A request was made to `/api/v1/new` without a `Content-Type` header");
-        let response =
-            Response::from_string(StatusCode(415).default_reason_phrase()).with_status_code(415);
-        if let Err(e) = request.respond(response) {
-            warn!("Failed to respond to a request: {e:#?}");
-        }
-        return;
+        return Err(ApiError::UnsupportedMediaType(String::from(
+            "The request has no `Content-Type` header",
+        )));
     };
 
     if content_type_header.value != "application/json" {
-        debug!("A request was made to `/api/v1/new` without a valid `Content-Type` of `application/json`");
-        let response =
-            Response::from_string(StatusCode(415).default_reason_phrase()).with_status_code(415);
-        if let Err(e) = request.respond(response) {
-            warn!("Failed to respond to a request: {e:#?}");
-        }
-        return;
+        return Err(ApiError::UnsupportedMediaType(String::from(
+            "The `Content-Type` of the request must be `application/json`",
+        )));
     }
 
-    if let Err(e) = request.as_reader().read_to_end(&mut buf) {
-        info!("Could not read the body of the request: {e:#?}");
-        let response =
-            Response::from_string(StatusCode(415).default_reason_phrase()).with_status_code(415);
-        if let Err(e) = request.respond(response) {
-            warn!("Failed to respond to a request: {e:#?}");
-        }
-        return;
-    }
+    let buf = read_body(request, MAX_BODY_SIZE as u64)?;
 
-    let content = match String::from_utf8(buf) {
-        Ok(content) => content,
-        Err(e) => {
-            debug!("The body of a request could not be interpreted as UTF-8: {e:#?}");
-            return;
-        }
-    };
+    // The `Content-Type` has already been checked, so from here on a body we can't use is
+    // malformed, rather than of the wrong type.
+    let content = String::from_utf8(buf)
+        .map_err(|e| ApiError::BadRequest(format!("The body of the request isn't UTF-8: {e}")))?;
 
-    let logins = match serde_json::de::from_str::<Vec<Login>>(&content) {
-        Ok(logins) => logins,
-        Err(e) => {
-            info!("Failed to parse login from request: {e}");
-            let response = Response::from_string(StatusCode(415).default_reason_phrase())
-                .with_status_code(415);
-            if let Err(e) = request.respond(response) {
-                warn!("Failed to respond to a request: {e:#?}");
-            }
-            return;
-        }
-    };
+    serde_json::de::from_str(&content)
+        .map_err(|e| ApiError::BadRequest(format!("Failed to parse the request: {e}")))
+}
 
-    db.append_logins(logins);
-    if let Err(e) = request.respond(
-        Response::from_string(StatusCode(201).default_reason_phrase()).with_status_code(201),
-    ) {
-        warn!("Failed to respond to a request: {e:#?}");
-    };
+// A batch which wouldn't fit in the database is refused as a whole, rather than in the results for
+// each login. Nothing has been added, so it isn't saved for the idempotency key either, and can be
+// retried once there's room.
+fn add_logins(logins: Vec<Login>, db: &Mutex<Database>) -> Result<SavedResponse, ApiError> {
+    if let Some(login) = logins
+        .iter()
+        .find(|login| login.password_reference().is_some())
+    {
+        return Err(ApiError::BadRequest(reference::refused(login)));
+    }
+    let warnings: Vec<Vec<LoginWarning>> = logins.iter().map(Login::validate).collect();
+    let outcomes = lock(db).append_logins(logins);
+    if let Some(e) = outcomes
+        .iter()
+        .filter_map(|outcome| outcome.as_ref().err())
+        .find(|e| matches!(e, AddError::QuotaExceeded { .. }))
+    {
+        return Err(ApiError::InsufficientStorage(e.to_string()));
+    }
+    let ids: Vec<Uuid> = outcomes
+        .iter()
+        .filter_map(|outcome| outcome.clone().ok())
+        .collect();
+    let results: Vec<serde_json::Value> = outcomes
+        .iter()
+        .zip(warnings)
+        .map(|outcome| match outcome {
+            (Ok(id), warnings) if warnings.is_empty() => serde_json::json!({ "id": id }),
+            (Ok(id), warnings) => serde_json::json!({
+                "id": id,
+                "warnings": warnings.iter().map(|warning| warning.kind()).collect::<Vec<_>>()
+            }),
+            (Err(e), _) => serde_json::json!({ "error": e.kind(), "message": e.to_string() }),
+        })
+        .collect();
+
+    // Some of a batch can be added while others aren't, so the results for each login are always
+    // in the body. The status only says whether anything was created at all. `Location` can only
+    // point at one resource, so when several logins are created it points at the first of them.
+    Ok(SavedResponse {
+        status: if ids.is_empty() { 200 } else { 201 },
+        location: ids.first().map(|id| format!("/api/v1/login?id={id}")),
+        body: serde_json::json!({ "ids": ids, "results": results }).to_string(),
+    })
 }
 
 // Now idempotent. Returns 204 on successful deletion, and 404 otherwise. Due to idempotency, a request can be sent multiple times by the client
 // legally. Only the first successful deletion will return 204, other would-be-successful requests get a 404. This is OK according to
 // https://stackoverflow.com/questions/24713945/does-idempotency-include-response-codes.8
-fn remove_login(request: Request, id: Option<&str>, db: &mut Database) {
-    let Some(id) = id else {
-        debug!("A DELETE request contained no ID");
-        // I assume that this should be a 404, looking at https://www.rfc-editor.org/rfc/rfc9110.html#name-client-error-4xx a 404 seems to be most accurate.
-        let response =
-            Response::from_string(StatusCode(404).default_reason_phrase()).with_status_code(404);
+// With `echo`, the removed login is sent back with a 200 instead, so that clients can update what
+// they're showing without querying again.
+fn remove_login(id: Option<&str>, echo: bool, db: &mut Database) -> ApiResult {
+    let id = parse_id(id)?;
 
-        if let Err(e) = request.respond(response) {
-            warn!("Failed to respond to a request: {e:#?}");
-        }
-        return;
+    let Some(removed) = db.remove(id) else {
+        return Err(ApiError::NotFound(format!(
+            "There is no login with id {id}"
+        )));
     };
 
-    let id = match Uuid::parse_str(id) {
-        Ok(id) => id,
-        Err(e) => {
-            debug!("A DELETE request contained an invalid ID: {}", e);
-            let response = Response::from_string(StatusCode(404).default_reason_phrase())
-                .with_status_code(404);
-            if let Err(e) = request.respond(response) {
-                warn!("Failed to respond to a request: {e:#?}");
-            }
-            return;
+    if echo {
+        login_response(&removed)
+    } else {
+        Ok(status(204))
+    }
+}
+
+// Only the fields given in the body are changed. Responds with the whole login as it is afterwards.
+fn update_login(request: &mut Request, id: Option<&str>, db: &Mutex<Database>) -> ApiResult {
+    let id = parse_id(id)?;
+    let mut patch: LoginPatch = read_json_body(request)?;
+
+    let mut db = lock(db);
+    if let Some(login) = db.get(id) {
+        // A password which is sent back as it was given out, as the edit page does, is kept as it
+        // is stored, whether it's a reference or escaped.
+        if patch.password.as_deref() == Some(PasswordReference::unescape(&login.password)) {
+            patch.password = None;
         }
+        if patch
+            .password
+            .as_deref()
+            .is_some_and(|password| PasswordReference::parse(password).is_some())
+        {
+            return Err(ApiError::BadRequest(reference::refused(login)));
+        }
+    }
+
+    match db.update_login(id, patch) {
+        Ok(Some(login)) => login_response(login),
+        Ok(None) => Err(ApiError::NotFound(format!(
+            "There is no login with id {id}"
+        ))),
+        Err(e) => Err(ApiError::Conflict(e.to_string())),
+    }
+}
+
+// Counts as an access, see `Login::last_accessed`. The new access time is saved with everything
+// else when the database is next synced.
+// A password kept elsewhere is sent as the reference, rather than what it refers to, which is only
+// ever read for the command line.
+fn get_login(id: Option<&str>, db: &mut Database) -> ApiResult {
+    let id = parse_id(id)?;
+    let login = db
+        .access(id)
+        .ok_or_else(|| ApiError::NotFound(format!("There is no login with id {id}")))?;
+
+    login_response(login)
+}
+
+// Attachments are sent and received as they are, rather than as JSON, so that large files don't
+// have to be encoded. They're always served as a download, whatever their name, since an HTML file
+// shown by the browser would run with access to the rest of the API.
+fn get_attachment(id: Option<&str>, name: Option<&str>, db: &mut Database) -> ApiResult {
+    let id = parse_id(id)?;
+    let name = attachment_name(name)?;
+    if db.get(id).is_none() {
+        return Err(ApiError::NotFound(format!(
+            "There is no login with id {id}"
+        )));
+    }
+    let attachment = db.access_attachment(id, name).ok_or_else(|| {
+        ApiError::NotFound(format!("The login has no attachment called {name:?}"))
+    })?;
+
+    let response = with_content_type(
+        Response::from_data(attachment.bytes.clone()),
+        "application/octet-stream",
+    );
+    Ok(response
+        .with_header(
+            Header::from_bytes("Content-Disposition", "attachment")
+                .expect("A constant header is valid"),
+        )
+        .with_header(
+            Header::from_bytes("X-Content-Type-Options", "nosniff")
+                .expect("A constant header is valid"),
+        ))
+}
+
+// Like `add_new()`, the body is read before the database is locked for the change. The size limit
+// is only read from the database, so that it's known before the body is.
+fn add_attachment(
+    request: &mut Request,
+    id: Option<&str>,
+    name: Option<&str>,
+    db: &Mutex<Database>,
+) -> ApiResult {
+    let id = parse_id(id)?;
+    let name = attachment_name(name)?;
+    let limit = match lock(db).max_attachment_bytes {
+        0 => u64::MAX,
+        max => max,
     };
+    let bytes = read_body(request, limit)?;
 
-    if db.remove(id).is_none() {
-        let response =
-            Response::from_string(StatusCode(404).default_reason_phrase()).with_status_code(404);
-        if let Err(e) = request.respond(response) {
-            warn!("Failed to respond to a request: {e:#?}");
-        }
-        return;
+    let attachment = Attachment {
+        name: String::from(name),
+        bytes,
+    };
+    match lock(db).attach(id, attachment) {
+        Ok(true) => Ok(Response::from_data(Vec::new()).with_status_code(200)),
+        Ok(false) => Ok(Response::from_data(Vec::new()).with_status_code(201)),
+        Err(e @ AttachError::NoSuchLogin(_)) => Err(ApiError::NotFound(e.to_string())),
+        Err(e @ AttachError::TooLarge { .. }) => Err(ApiError::PayloadTooLarge(e.to_string())),
     }
+}
 
-    if let Err(e) = request.respond(
-        Response::from_string(StatusCode(204).default_reason_phrase()).with_status_code(204),
-    ) {
-        warn!("Failed to respond to a request: {e:#?}");
+fn attachment_name(name: Option<&str>) -> Result<&str, ApiError> {
+    name.filter(|name| !name.is_empty()).ok_or_else(|| {
+        ApiError::BadRequest(String::from(
+            "The request didn't contain the `name` of the attachment",
+        ))
+    })
+}
+
+// Escaped passwords are sent as they stand, without the `\` in front of them.
+fn login_response(login: &Login) -> ApiResult {
+    let body = serde_json::ser::to_string(&login.unescaped())
+        .map_err(|e| ApiError::Internal(format!("Failed to serialise a login into JSON: {e}")))?;
+
+    Ok(with_content_type(
+        Response::from_string(body),
+        "application/json",
+    ))
+}
+
+// Parses the `id` query parameter of a request about a single login. A missing or invalid id can't
+// refer to any login, so they're both treated as not found.
+fn parse_id(id: Option<&str>) -> Result<Uuid, ApiError> {
+    // I assume that this should be a 404, looking at https://www.rfc-editor.org/rfc/rfc9110.html#name-client-error-4xx a 404 seems to be most accurate.
+    let Some(id) = id else {
+        return Err(ApiError::NotFound(String::from(
+            "The request didn't contain an `id`",
+        )));
     };
+
+    Uuid::parse_str(id).map_err(|e| ApiError::NotFound(format!("`{id}` isn't a valid id: {e}")))
 }
 
-fn serve_404(request: Request) {
-    if let Err(e) = request.respond(Response::from_string("404").with_status_code(404)) {
-        warn!("Failed to respond to a request: {e:#?}");
-    }
+// Doesn't touch the database, so it's safe to serve to anyone.
+fn serve_openapi() -> ApiResponse {
+    with_content_type(
+        Response::from_string(openapi::document().to_string()),
+        "application/json",
+    )
+}
+
+// The whole database, serialised while the lock is held so that it's a consistent copy, in the same
+// form as a backup and named like one, so that it can be put in the backups directory as it is.
+fn snapshot(db: &Database) -> ApiResult {
+    let (name, bytes) = db
+        .snapshot()
+        .map_err(|e| ApiError::Internal(format!("Failed to take a snapshot: {e:#}")))?;
+
+    let disposition = Header::from_bytes(
+        "Content-Disposition",
+        format!("attachment; filename=\"{name}\""),
+    )
+    .expect("A backup's name is a valid header value");
+    Ok(
+        with_content_type(Response::from_data(bytes), "application/octet-stream")
+            .with_header(disposition)
+            .with_header(
+                Header::from_bytes("X-Content-Type-Options", "nosniff")
+                    .expect("A constant header is valid"),
+            ),
+    )
+}
+
+// Only writes the database if it's changed since it was last written, so that clients can poll
+// this cheaply. `X-Synced` says whether it was written, and the status is 204 either way, as it
+// always has been.
+fn sync(db: &Database) -> ApiResult {
+    let synced = db.sync_if_changed().map_err(|e| {
+        ApiError::Internal(format!(
+            "Failed to sync database after it was requested via API: {e:#}"
+        ))
+    })?;
+
+    let header = Header::from_bytes("X-Synced", if synced { "true" } else { "false" })
+        .expect("A constant header is valid");
+    Ok(status(204).with_header(header))
 }
@@ -0,0 +1,324 @@
+use schemars::gen::SchemaSettings;
+use serde_json::{json, Value};
+
+use crate::models::{Login, LoginPatch};
+
+// Describes the JSON API as an OpenAPI 3 document, so clients for it can be generated. The schema
+// for `Login` is derived from the struct itself, so it can't fall out of date, but the paths are
+// written out by hand and have to be kept in step with `net::routes()`.
+pub(crate) fn document() -> Value {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    let login = generator.subschema_for::<Login>();
+    let patch = generator.subschema_for::<LoginPatch>();
+    let mut schemas: serde_json::Map<String, Value> = generator
+        .take_definitions()
+        .into_iter()
+        .map(|(name, schema)| (name, json!(schema)))
+        .collect();
+    schemas.insert(String::from("NewResponse"), new_response_schema());
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Locket",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Every request must have a `Host` header naming localhost, 127.0.0.1 or [::1], and the server's port if it has one. Any other host is refused with a 403. Errors are sent as a JSON object, with a short, stable `error` naming the kind of error, such as `not_found`, and a `message` for people.",
+        },
+        "paths": with_path_routes(paths(&json!(login), &json!(patch))),
+        "components": { "schemas": schemas }
+    })
+}
+
+// `login` is the schema for a single login, and `patch` for the changes to one. This is one big
+// literal, so its length isn't a problem.
+#[allow(clippy::too_many_lines)]
+fn paths(login: &Value, patch: &Value) -> Value {
+    let id_param = json!({
+        "name": "id",
+        "in": "query",
+        "required": true,
+        "description": "The id of the login.",
+        "schema": { "type": "string", "format": "uuid" }
+    });
+    let not_found = json!({ "description": "The id is missing, invalid, or no login has it." });
+    let name_param = json!({
+        "name": "name",
+        "in": "query",
+        "required": true,
+        "description": "The name of the attachment.",
+        "schema": { "type": "string" }
+    });
+
+    json!({
+            "/api/v1/query": {
+                "get": {
+                    "summary": "Fuzzy search the logins by name.",
+                    "parameters": [{
+                        "name": "query",
+                        "in": "query",
+                        "required": false,
+                        "description": "What to search for. Every login is returned if it's missing.",
+                        "schema": { "type": "string" }
+                    }, {
+                        "name": "folder",
+                        "in": "query",
+                        "required": false,
+                        "description": "Only return logins in this folder, such as `Work/Cloud`, or in the folders inside it.",
+                        "schema": { "type": "string" }
+                    }, {
+                        "name": "masked",
+                        "in": "query",
+                        "required": false,
+                        "description": "If `true`, passwords and secret custom fields are replaced with `********`. `/api/v1/login` returns them unmasked.",
+                        "schema": { "type": "boolean", "default": false }
+                    }],
+                    "responses": {
+                        "200": {
+                            "description": "The matching logins, best match first, each as an `[id, login]` pair.",
+                            "content": { "application/json": { "schema": {
+                                "type": "array",
+                                "items": {
+                                    "type": "array",
+                                    "minItems": 2,
+                                    "maxItems": 2,
+                                    "items": {
+                                        "oneOf": [{ "type": "string", "format": "uuid" }, login]
+                                    }
+                                }
+                            } } }
+                        }
+                    }
+                }
+            },
+            "/api/v1/folders": {
+                "get": {
+                    "summary": "List the folders, including those which only have folders in them.",
+                    "responses": {
+                        "200": {
+                            "description": "The folders, such as `Work` and `Work/Cloud`, sorted so that each folder comes straight after the one it's in.",
+                            "content": { "application/json": { "schema": {
+                                "type": "array",
+                                "items": { "type": "string" }
+                            } } }
+                        }
+                    }
+                }
+            },
+            "/api/v1/login": {
+                "get": {
+                    "summary": "Get a single login.",
+                    "description": "A password which refers to a file (`file:<path>`) or a command (`cmd:<command>`) is sent as it's written, rather than what it refers to, which is only ever read by the command line. A password which starts with `\\` followed by `file:` or `cmd:` is stored as it is, without the first `\\`.",
+                    "parameters": [id_param],
+                    "responses": {
+                        "200": {
+                            "description": "The login.",
+                            "content": { "application/json": { "schema": login } }
+                        },
+                        "404": not_found
+                    }
+                }
+            },
+            "/api/v1/new": {
+                "post": {
+                    "summary": "Add logins, skipping any with the same name and username as one that already exists.",
+                    "parameters": [{
+                        "name": "Idempotency-Key",
+                        "in": "header",
+                        "required": false,
+                        "description": "Any string. A request with the same key as an earlier one gets the earlier response again, without anything being added. Keys are forgotten after `idempotency_ttl_secs`, or when the server restarts.",
+                        "schema": { "type": "string" }
+                    }],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": {
+                            "type": "array",
+                            "items": login
+                        } } }
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "None of the logins were added, see `results` for why.",
+                            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/NewResponse" } } }
+                        },
+                        "201": {
+                            "description": "At least one of the logins was added. `Location` points at the first of them.",
+                            "headers": { "Location": { "schema": { "type": "string" } } },
+                            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/NewResponse" } } }
+                        },
+                        "400": { "description": "The body couldn't be read, or isn't a JSON array of logins, or one of the passwords refers to a file or command. Those can only be added from the command line, and start with `\\` to be stored as they are." },
+                        "413": { "description": "The body is larger than 1 MiB." },
+                        "415": { "description": "The `Content-Type` isn't `application/json`." },
+                        "507": { "description": "Adding the logins would take the database past `max_logins`, so none of them were added." }
+                    }
+                }
+            },
+            "/api/v1/update": {
+                "patch": {
+                    "summary": "Change some of the fields of a login, leaving the rest as they are.",
+                    "parameters": [id_param],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": patch } }
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "The login, after the changes.",
+                            "content": { "application/json": { "schema": login } }
+                        },
+                        "400": { "description": "The body couldn't be read, or isn't a valid patch, or it changes the password to one which refers to a file or command." },
+                        "404": not_found,
+                        "409": { "description": "Another login already has the new name and username." },
+                        "413": { "description": "The body is larger than 1 MiB." },
+                        "415": { "description": "The `Content-Type` isn't `application/json`." }
+                    }
+                }
+            },
+            "/api/v1/remove": {
+                "delete": {
+                    "summary": "Remove a single login.",
+                    "parameters": [id_param, {
+                        "name": "echo",
+                        "in": "query",
+                        "required": false,
+                        "description": "If `true`, the removed login is sent back.",
+                        "schema": { "type": "boolean" }
+                    }],
+                    "responses": {
+                        "200": {
+                            "description": "The login was removed, and `echo` was `true`.",
+                            "content": { "application/json": { "schema": login } }
+                        },
+                        "204": { "description": "The login was removed." },
+                        "404": not_found
+                    }
+                }
+            },
+            "/api/v1/attachment": {
+                "get": {
+                    "summary": "Download a file attached to a login.",
+                    "parameters": [id_param, name_param],
+                    "responses": {
+                        "200": {
+                            "description": "The contents of the attachment, as they are.",
+                            "content": { "application/octet-stream": {
+                                "schema": { "type": "string", "format": "binary" }
+                            } }
+                        },
+                        "400": { "description": "There was no `name`." },
+                        "404": { "description": "The id is missing, invalid, or no login has it, or the login has no attachment with that name." }
+                    }
+                },
+                "post": {
+                    "summary": "Attach a file to a login, replacing any attachment with the same name.",
+                    "parameters": [id_param, name_param],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/octet-stream": {
+                            "schema": { "type": "string", "format": "binary" }
+                        } }
+                    },
+                    "responses": {
+                        "200": { "description": "An attachment with the same name was replaced." },
+                        "201": { "description": "The file was attached." },
+                        "400": { "description": "There was no `name`." },
+                        "404": not_found,
+                        "413": { "description": "The file is larger than `max_attachment_bytes` allows." }
+                    }
+                }
+            },
+            "/api/v1/snapshot": {
+                "get": {
+                    "summary": "Download a copy of the whole database, taken at a single point in time, in the same format as a backup.",
+                    "description": "Only allowed if `allow_snapshot_download` is set in the configuration file.",
+                    "responses": {
+                        "200": {
+                            "description": "The database, named like a backup in `Content-Disposition`, so that it can be put in the backups directory and restored from. It contains every password.",
+                            "content": { "application/octet-stream": {
+                                "schema": { "type": "string", "format": "binary" }
+                            } }
+                        },
+                        "403": { "description": "`allow_snapshot_download` isn't set." },
+                        "500": { "description": "The database couldn't be serialised." }
+                    }
+                }
+            },
+            "/api/v1/sync": {
+                "get": {
+                    "summary": "Write the database to disk, if it's changed since it was last written.",
+                    "responses": {
+                        "204": {
+                            "description": "The database was written, or didn't need to be.",
+                            "headers": {
+                                "X-Synced": {
+                                    "description": "`true` if the database was written, `false` if nothing had changed.",
+                                    "schema": { "type": "boolean" }
+                                }
+                            }
+                        },
+                        "500": { "description": "The database couldn't be written." }
+                    }
+                }
+            }
+    })
+}
+
+// Adds the routes which take the id, and the attachment's name, in the path. They behave the same
+// as the routes which take them in the query string, so they're described by copies of those.
+fn with_path_routes(mut paths: Value) -> Value {
+    let operation = |path: &str, method: &str| {
+        let mut operation = paths[path][method].clone();
+        if let Some(params) = operation["parameters"].as_array_mut() {
+            for param in params {
+                if param["name"] == "id" || param["name"] == "name" {
+                    param["in"] = json!("path");
+                }
+            }
+        }
+        operation
+    };
+
+    let login = json!({
+        "get": operation("/api/v1/login", "get"),
+        "patch": operation("/api/v1/update", "patch"),
+        "delete": operation("/api/v1/remove", "delete"),
+    });
+    let attachment = json!({
+        "get": operation("/api/v1/attachment", "get"),
+        "post": operation("/api/v1/attachment", "post"),
+    });
+    paths["/api/v1/login/{id}"] = login;
+    paths["/api/v1/login/{id}/attachments/{name}"] = attachment;
+    paths
+}
+
+// There's no struct for the body `/api/v1/new` responds with to derive this from.
+fn new_response_schema() -> Value {
+    json!({
+        "type": "object",
+        "required": ["ids", "results"],
+        "properties": {
+            "ids": {
+                "description": "The ids of the logins which were added, in the order they were given.",
+                "type": "array",
+                "items": { "type": "string", "format": "uuid" }
+            },
+            "results": {
+                "description": "What happened to each login, in the order they were given.",
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "string", "format": "uuid" },
+                        "warnings": {
+                            "description": "Anything about an added login which is probably a mistake. Left out if there's nothing.",
+                            "type": "array",
+                            "items": { "type": "string", "enum": ["empty_password"] }
+                        },
+                        "error": { "type": "string", "enum": ["duplicate", "no_free_id"] },
+                        "message": { "type": "string" }
+                    }
+                }
+            }
+        }
+    })
+}
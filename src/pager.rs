@@ -0,0 +1,63 @@
+use std::{
+    env,
+    io::{IsTerminal, Write},
+    process::Stdio,
+};
+
+use console::Term;
+use log::debug;
+
+use crate::program::shell_command;
+
+// Used when `$PAGER` isn't set. `-R` lets colours through, and `-F` quits straight away if the
+// text fits on one screen anyway.
+const DEFAULT_PAGER: &str = "less -RF";
+// The exit code the shell uses when it can't find the command it was asked to run.
+const COMMAND_NOT_FOUND: i32 = 127;
+
+// Prints `text`, through the user's pager if it's too tall to fit in the terminal. If standard
+// output isn't a terminal, or the pager can't be started, it's printed directly instead.
+pub(crate) fn print_paged(text: &str) {
+    let stdout = Term::stdout();
+    let (rows, _) = stdout.size();
+    if !std::io::stdout().is_terminal() || text.lines().count() < usize::from(rows) {
+        println!("{text}");
+        return;
+    }
+
+    let pager = env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or_else(|| String::from(DEFAULT_PAGER));
+    if !page(&pager, text) {
+        println!("{text}");
+    }
+}
+
+// Returns whether the pager was started. Once it has been, it's up to the user what they see, so
+// errors writing to it (such as the user quitting before reading everything) are ignored.
+fn page(pager: &str, text: &str) -> bool {
+    let mut child = match shell_command(pager).stdin(Stdio::piped()).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            debug!("Failed to start the pager `{pager}`: {e}");
+            return false;
+        }
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = writeln!(stdin, "{text}");
+    }
+    match child.wait() {
+        // The shell couldn't find the pager, so nothing was shown.
+        Ok(status) if status.code() == Some(COMMAND_NOT_FOUND) => {
+            debug!("The pager `{pager}` couldn't be found");
+            false
+        }
+        Ok(_) => true,
+        Err(e) => {
+            debug!("Failed to wait for the pager to exit: {e}");
+            true
+        }
+    }
+}
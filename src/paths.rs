@@ -0,0 +1,73 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{Context, Result};
+use serde_derive::Serialize;
+
+use crate::{
+    models::Config,
+    program::{lockfile_path, Program},
+};
+
+#[derive(Serialize)]
+struct Paths<'a> {
+    config: &'a Path,
+    database: &'a Path,
+    lockfile: &'a Path,
+    data_dir: &'a Path,
+    initialised: bool,
+}
+
+// Prints where Locket keeps its files, as this run would use them, so after `LOCKET_DATA_DIR`, the
+// `path` in the configuration file and `--set` have been applied. Nothing is created or locked, and
+// before `init` the paths it would use are printed instead.
+pub(crate) fn paths(program: &Program, overrides: &[(String, String)], json: bool) -> Result<()> {
+    let initialised = program.conf_path.try_exists().wrap_err_with(|| {
+        format!(
+            "Failed to check whether {} exists",
+            program.conf_path.display()
+        )
+    })?;
+    let config = if initialised {
+        Some(
+            Config::read(&program.conf_path)
+                .wrap_err_with(|| format!("Failed to read {}", program.conf_path.display()))?
+                .with_overrides(overrides)?,
+        )
+    } else {
+        None
+    };
+    let database = config.as_ref().map_or(program.db_path.as_path(), |config| {
+        program.database_path(config)
+    });
+    let data_dir = database.parent().map_or_else(PathBuf::new, PathBuf::from);
+
+    let paths = Paths {
+        config: &program.conf_path,
+        database,
+        lockfile: &lockfile_path(database),
+        data_dir: &data_dir,
+        initialised,
+    };
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&paths)
+                .wrap_err("Failed to serialise the paths as JSON")?
+        );
+        return Ok(());
+    }
+
+    for (label, path) in [
+        ("config", paths.config),
+        ("database", paths.database),
+        ("lockfile", paths.lockfile),
+        ("data dir", paths.data_dir),
+    ] {
+        if path.exists() {
+            println!("{label:<8}  {}", path.display());
+        } else {
+            println!("{label:<8}  {} (doesn't exist yet)", path.display());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,179 @@
+#[cfg(unix)]
+use std::{
+    collections::BTreeSet,
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+    sync::{atomic::Ordering, Mutex, PoisonError},
+};
+use std::{
+    fs::{DirBuilder, OpenOptions},
+    io,
+    path::Path,
+    sync::atomic::AtomicBool,
+};
+
+use color_eyre::eyre::Result;
+#[cfg(unix)]
+use color_eyre::eyre::{bail, Context};
+#[cfg(unix)]
+use console::style;
+
+#[cfg(unix)]
+use crate::{
+    models::{data_lock_path, Config, Database},
+    program::{bin_name, lockfile_path, Program},
+};
+
+// On Unix, the files Locket creates are only readable and writable by their owner, and the
+// directories it creates are only accessible by them, whatever the umask is. Everything in them is
+// either a password or says where to find them. Elsewhere these do nothing, and files get whatever
+// access the directory they're created in gives them, which on Windows is normally just the user's.
+#[cfg(unix)]
+const FILE_MODE: u32 = 0o600;
+#[cfg(unix)]
+const DIR_MODE: u32 = 0o700;
+// The bits which give the group or anyone else any access.
+#[cfg(unix)]
+const EXPOSED_BITS: u32 = 0o077;
+
+// Makes files created by `options` private to their owner. Files which already exist keep their
+// permissions.
+pub(crate) fn private(options: &mut OpenOptions) -> &mut OpenOptions {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(FILE_MODE);
+    }
+    options
+}
+
+// Like `fs::write()`, but a file created by it is private to its owner.
+pub(crate) fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+
+    private(OpenOptions::new().write(true).create(true).truncate(true))
+        .open(path)?
+        .write_all(contents)
+}
+
+// Like `fs::create_dir_all()`, but the directories it creates are private to their owner.
+// Directories which already exist, such as `~/.config`, keep their permissions.
+pub(crate) fn create_private_dir_all(dir: &Path) -> io::Result<()> {
+    let mut builder = DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(DIR_MODE);
+    }
+    builder.create(dir)
+}
+
+// Set from `--strict-permissions` before anything is opened, to refuse files which `check()` would
+// otherwise only warn about.
+pub(crate) static STRICT: AtomicBool = AtomicBool::new(false);
+
+// The files which have been warned about already, so that opening one more than once, as `run()`
+// does with the configuration file, only warns once.
+#[cfg(unix)]
+static WARNED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+// Checks that nobody but the owner can access the file at `path`, such as a database created before
+// Locket restricted its permissions, or copied from a backup which didn't keep them. If anyone can,
+// this warns, or with `--strict-permissions`, fails. Files which can't be checked, e.g. because
+// they don't exist, are left for whatever opens them to report.
+pub(crate) fn check(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        let Some(mode) = exposed_mode(path) else {
+            return Ok(());
+        };
+        if STRICT.load(Ordering::Relaxed) {
+            bail!(
+                "Other users can read {} (its mode is {mode:o}), run `{} fix-permissions` to make it private, or leave out `--strict-permissions`",
+                path.display(),
+                bin_name()
+            );
+        }
+
+        let first = WARNED
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(path.to_path_buf());
+        if first {
+            eprintln!(
+                "{}",
+                style(format!(
+                    "! Other users can read {} (its mode is {mode:o}), run `{} fix-permissions` to make it private",
+                    path.display(),
+                    bin_name()
+                ))
+                .yellow()
+            );
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+
+    Ok(())
+}
+
+// The permissions of the file at `path`, if they give anyone but its owner access.
+#[cfg(unix)]
+fn exposed_mode(path: &Path) -> Option<u32> {
+    let mode = fs::metadata(path).ok()?.permissions().mode() & 0o777;
+    (mode & EXPOSED_BITS != 0).then_some(mode)
+}
+
+// Makes Locket's configuration file, database, and the files kept beside it private to their owner,
+// for files created before Locket did so itself. The directories holding them are left alone, since
+// they may have been chosen with `path` or `LOCKET_DATA_DIR` to be shared.
+#[cfg(unix)]
+pub(crate) fn fix_permissions(program: &Program) -> Result<()> {
+    let mut fixed = 0;
+    fixed += usize::from(make_private(&program.conf_path)?);
+    // Read after its own permissions are fixed, so that `--strict-permissions` doesn't refuse it.
+    let config =
+        Config::open(&program.conf_path).wrap_err("Failed to open the configuration file")?;
+    let db_path = program.database_path(&config);
+
+    let backups_dir = Database::backups_dir(db_path);
+    let backups = if backups_dir.is_dir() {
+        Database::list_backups(&backups_dir)?
+    } else {
+        Vec::new()
+    };
+    for path in [
+        db_path.to_path_buf(),
+        data_lock_path(db_path),
+        lockfile_path(db_path),
+    ]
+    .into_iter()
+    .chain(backups)
+    {
+        fixed += usize::from(make_private(&path)?);
+    }
+
+    if fixed == 0 {
+        status!("Every file was private already");
+    }
+    Ok(())
+}
+
+// Removes any access to the file at `path` from anyone but its owner, returning whether anything
+// changed. Files which don't exist are skipped, since they'll be private when they're created.
+#[cfg(unix)]
+fn make_private(path: &Path) -> Result<bool> {
+    let Some(mode) = exposed_mode(path) else {
+        return Ok(false);
+    };
+    let private = mode & !EXPOSED_BITS;
+    fs::set_permissions(path, fs::Permissions::from_mode(private))
+        .wrap_err_with(|| format!("Failed to change the permissions of {}", path.display()))?;
+    status!(
+        "Changed the mode of {} from {mode:o} to {private:o}",
+        path.display()
+    );
+    Ok(true)
+}
@@ -0,0 +1,413 @@
+use std::{
+    env, fs,
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    process::{self, Command},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::eyre::{bail, Context, Result};
+use fs2::{lock_contended_error, FileExt};
+use log::{debug, warn};
+
+use crate::{
+    errors::LocketError,
+    models::{Config, Database},
+    permissions,
+};
+
+static DATABASE_FILE_NAME: &str = "locket.db";
+static CONFIG_FILE_NAME: &str = "locket.toml";
+pub(crate) static DATA_DIR_VAR: &str = "LOCKET_DATA_DIR";
+// How often to check whether the lock has been released while waiting for it.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// The name Locket was run as, for telling the user which commands to run, so that the advice is
+// still right if the binary has been renamed.
+pub(crate) fn bin_name() -> String {
+    env::args_os()
+        .next()
+        .and_then(|arg0| {
+            Path::new(&arg0)
+                .file_stem()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| String::from("locket"))
+}
+
+// Runs `command` with the shell where there is one, so that it can contain quoted arguments and
+// pipes, as `$PAGER` and password commands can. Like git, it's split on whitespace elsewhere.
+#[cfg(unix)]
+pub(crate) fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+pub(crate) fn shell_command(command: &str) -> Command {
+    let mut words = command.split_whitespace();
+    let mut shell = Command::new(words.next().unwrap_or_default());
+    shell.args(words);
+    shell
+}
+
+// Quotes `word` for a POSIX shell, if it needs it.
+fn shell_quote(word: &str) -> String {
+    if !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/._-+:@%,".contains(c))
+    {
+        return String::from(word);
+    }
+    format!("'{}'", word.replace('\'', "'\\''"))
+}
+
+// Responsible for locating, creating, and cleaning up the files that Locket uses, i.e. the
+// configuration file and the database.
+pub(crate) struct Program {
+    pub conf_path: PathBuf,
+    pub db_path: PathBuf,
+    // Whether `db_path` comes from `LOCKET_DATA_DIR`, in which case it takes precedence over the
+    // path in the configuration file.
+    data_dir_overridden: bool,
+}
+
+impl Program {
+    // Only works out where the files should be, it doesn't create anything, so that read-only
+    // commands like `doctor` can use it safely.
+    pub fn new() -> Result<Self> {
+        let Some(proj_dirs) =
+            directories::ProjectDirs::from("com.github", "needlesslygrim", "Locket")
+        else {
+            bail!("Failed to get project directories")
+        };
+
+        let data_dir_override = env::var_os(DATA_DIR_VAR).filter(|dir| !dir.is_empty());
+        let data_dir_overridden = data_dir_override.is_some();
+        let data_dir =
+            data_dir_override.map_or_else(|| proj_dirs.data_dir().to_path_buf(), PathBuf::from);
+
+        Ok(Self {
+            conf_path: proj_dirs.config_dir().join(CONFIG_FILE_NAME),
+            db_path: data_dir.join(DATABASE_FILE_NAME),
+            data_dir_overridden,
+        })
+    }
+
+    pub fn data_dir_overridden(&self) -> bool {
+        self.data_dir_overridden
+    }
+
+    pub fn database_path<'a>(&'a self, config: &'a Config) -> &'a Path {
+        if self.data_dir_overridden {
+            &self.db_path
+        } else {
+            &config.path
+        }
+    }
+
+    // Opens the configuration file. If there isn't one, this tells the user the command which
+    // would create it and exits, rather than failing with an error.
+    pub fn open_config(&self) -> Result<Config> {
+        match Config::open_initialised(&self.conf_path) {
+            Err(e) if matches!(e.downcast_ref(), Some(LocketError::NotInitialised)) => {
+                eprintln!(
+                    "You have not initialised Locket yet, please run `{}` to initialise, then run this command again.",
+                    self.init_command()
+                );
+                process::exit(0);
+            }
+            result => result.wrap_err("Failed to open the configuration file"),
+        }
+    }
+
+    // The command to initialise Locket where this run would look for it. `LOCKET_DATA_DIR` is
+    // included if it's set, since running `init` from a shell without it would put the database
+    // somewhere else.
+    fn init_command(&self) -> String {
+        let init = format!("{} init", bin_name());
+        let Some(dir) = self
+            .data_dir_overridden
+            .then(|| self.db_path.parent())
+            .flatten()
+        else {
+            return init;
+        };
+        if cfg!(unix) {
+            format!(
+                "{DATA_DIR_VAR}={} {init}",
+                shell_quote(&dir.display().to_string())
+            )
+        } else {
+            format!("{init}` with `{DATA_DIR_VAR}` set to `{}", dir.display())
+        }
+    }
+
+    // Makes sure that the directory given by `LOCKET_DATA_DIR` exists and that we can create
+    // files in it, so that a typo in the variable gives a clear error up front.
+    pub fn prepare_data_dir(&self) -> Result<()> {
+        if !self.data_dir_overridden {
+            return Ok(());
+        }
+
+        let Some(dir) = self.db_path.parent() else {
+            return Ok(());
+        };
+        permissions::create_private_dir_all(dir).wrap_err_with(|| {
+            format!("Failed to create {}, set by {DATA_DIR_VAR}", dir.display())
+        })?;
+
+        let probe = dir.join(".locket-write-test");
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&probe)
+            .wrap_err_with(|| {
+                format!("{}, set by {DATA_DIR_VAR}, isn't writable", dir.display())
+            })?;
+        fs::remove_file(&probe).wrap_err("Failed to remove the file used to test writability")?;
+
+        Ok(())
+    }
+
+    fn create_dirs(&self) -> Result<()> {
+        for path in [&self.conf_path, &self.db_path] {
+            let Some(dir) = path.parent() else {
+                continue;
+            };
+
+            if !dir
+                .try_exists()
+                .wrap_err_with(|| format!("Failed to check if {} exists", dir.display()))?
+            {
+                permissions::create_private_dir_all(dir)
+                    .wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Creates the configuration file and then the database. If anything fails part of the way
+    // through, the files which were created by this call are removed again, so that we never end
+    // up with a configuration file pointing at a database which doesn't exist (or vice versa), and
+    // running `locket init` again starts from a clean slate.
+    //
+    // With `force`, any existing files are moved aside before anything is created, and moved back
+    // if it fails. The lock is held throughout, so that the database isn't swapped out from under
+    // another instance of Locket which is using it.
+    pub fn init(&self, port: Option<u16>, force: bool) -> Result<()> {
+        self.create_dirs()?;
+        let mut guard = InitGuard::default();
+
+        let mut existing = Vec::new();
+        for path in [&self.conf_path, &self.db_path] {
+            if path
+                .try_exists()
+                .wrap_err_with(|| format!("Failed to check whether {} exists", path.display()))?
+            {
+                existing.push(path);
+            }
+        }
+        let lock = if force && !existing.is_empty() {
+            let lock = lock(&self.db_path, Duration::ZERO)?;
+            for path in existing {
+                let backup = guard.move_aside(path)?;
+                status!("Moved {} to {}", path.display(), backup.display());
+            }
+            Some(lock)
+        } else {
+            if let Some(path) = existing.first() {
+                bail!(
+                    "Locket has already been initialised, {} exists. Run `{} init --force` to start over, which moves the existing files aside first",
+                    path.display(),
+                    bin_name()
+                );
+            }
+            None
+        };
+
+        guard.track(&self.conf_path)?;
+        Config::init_interactive(&self.conf_path, &self.db_path, port)
+            .wrap_err("Failed to initialise configuration file")?;
+
+        guard.track(&self.db_path)?;
+        Database::init(&self.db_path).wrap_err("Failed to initialise database")?;
+
+        guard.commit();
+        lock.map_or(Ok(()), Lock::release)
+    }
+}
+
+// The lockfile beside the database at `db_path`, such as `locket.db.lck`. Each database has its
+// own, so instances using different databases don't wait for each other. It's separate from the
+// file locked around each read and write of the database, see `data_lock_path()`, since that's only
+// held for a moment, while this is held for as long as the instance runs.
+pub(crate) fn lockfile_path(db_path: &Path) -> PathBuf {
+    let mut path = db_path.as_os_str().to_owned();
+    path.push(".lck");
+    PathBuf::from(path)
+}
+
+// Takes an exclusive advisory lock on the lockfile of the database at `db_path`, so that only one
+// instance of Locket changes it at a time. Commands which only read the database don't take it;
+// see `Database::open()` and `Database::sync()` for how they're kept from reading a half-written
+// file. If another instance has the lock, this waits up to `timeout` for it to be released, then
+// gives up. The lock is released when the returned guard is dropped, including if the process
+// dies, so it can't be left behind.
+//
+// The lockfile contains the PID of the process holding the lock, so that `doctor` can say which
+// process it is.
+pub(crate) fn lock(db_path: &Path, timeout: Duration) -> Result<Lock> {
+    let mut file = permissions::private(
+        OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true),
+    )
+    .open(lockfile_path(db_path))
+    .wrap_err("Failed to open the lockfile")?;
+
+    let started = Instant::now();
+    loop {
+        match file.try_lock_exclusive() {
+            Ok(()) => break,
+            Err(err) if err.kind() == lock_contended_error().kind() => {
+                if started.elapsed() >= timeout {
+                    bail!(
+                        "Another instance of Locket is changing the database, please wait for it to quit before trying again, or use `--lock-timeout` to wait for it"
+                    );
+                }
+                thread::sleep(LOCK_POLL_INTERVAL);
+            }
+            Err(err) => return Err(err).wrap_err("Failed to lock the lockfile"),
+        }
+    }
+
+    file.set_len(0)
+        .and_then(|()| write!(file, "{}", process::id()))
+        .wrap_err("Failed to write to the lockfile")?;
+    Ok(Lock { file })
+}
+
+pub(crate) fn lock_status(db_path: &Path) -> Result<LockStatus> {
+    let mut file = match File::open(lockfile_path(db_path)) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(LockStatus::Free),
+        Err(err) => return Err(err).wrap_err("Failed to open the lockfile"),
+    };
+
+    // The PID left in the file by the last instance to hold the lock means nothing once it's
+    // been released.
+    if file.try_lock_shared().is_ok() {
+        return Ok(LockStatus::Free);
+    }
+
+    let mut content = String::new();
+    file.read_to_string(&mut content)
+        .wrap_err("Failed to read the lockfile")?;
+    Ok(content
+        .trim()
+        .parse()
+        .map_or(LockStatus::HeldByUnknown, LockStatus::HeldBy))
+}
+
+pub(crate) struct Lock {
+    file: File,
+}
+
+impl Lock {
+    // Dropping the guard releases the lock too, but this reports any failure to do so.
+    pub fn release(self) -> Result<()> {
+        self.file.unlock().wrap_err("Failed to unlock the lockfile")
+    }
+}
+
+pub(crate) enum LockStatus {
+    Free,
+    HeldBy(u32),
+    // The PID hasn't been written yet, or the lock is held by an older version of Locket.
+    HeldByUnknown,
+}
+
+// A sort-of transaction for the files created by `Program::init()`. Paths are tracked before the
+// step which creates them, and are removed when the guard is dropped unless `commit()` has been
+// called. Paths which already existed aren't tracked, so we never delete anything we didn't create.
+// Files moved aside by `move_aside()` are moved back afterwards.
+#[derive(Default)]
+struct InitGuard {
+    created: Vec<PathBuf>,
+    // Each original path, and where it was moved to.
+    moved: Vec<(PathBuf, PathBuf)>,
+    committed: bool,
+}
+
+impl InitGuard {
+    // Renames the file at `path` to a timestamped backup beside it, such as
+    // `locket.db.1700000000000.bak`. Nothing is copied or deleted, so the original is never lost.
+    fn move_aside(&mut self, path: &Path) -> Result<PathBuf> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".{timestamp}.bak"));
+        let backup = path.with_file_name(name);
+
+        fs::rename(path, &backup).wrap_err_with(|| {
+            format!("Failed to move {} to {}", path.display(), backup.display())
+        })?;
+        self.moved.push((PathBuf::from(path), backup.clone()));
+        Ok(backup)
+    }
+
+    fn track(&mut self, path: &Path) -> Result<()> {
+        if !path
+            .try_exists()
+            .wrap_err_with(|| format!("Failed to check whether {} exists", path.display()))?
+        {
+            self.created.push(PathBuf::from(path));
+        }
+
+        Ok(())
+    }
+
+    fn commit(&mut self) {
+        self.committed = true;
+    }
+}
+
+impl Drop for InitGuard {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+
+        for path in self.created.iter().rev() {
+            match fs::remove_file(path) {
+                Ok(()) => debug!("Rolled back the creation of {}", path.display()),
+                Err(err) if err.kind() == ErrorKind::NotFound => (),
+                Err(err) => warn!(
+                    "Failed to remove {} after initialisation failed: {err}",
+                    path.display()
+                ),
+            }
+        }
+        for (original, backup) in self.moved.iter().rev() {
+            match fs::rename(backup, original) {
+                Ok(()) => debug!("Moved {} back to {}", backup.display(), original.display()),
+                Err(err) => warn!(
+                    "Failed to move {} back to {} after initialisation failed: {err}",
+                    backup.display(),
+                    original.display()
+                ),
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::{io::IsTerminal, time::Instant};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use log::info;
+
+use crate::import::Progress;
+
+// How many entries to read between each log line when standard output isn't a terminal.
+const LOG_EVERY: u64 = 1000;
+
+// Shows the progress of an import with a progress bar when standard output is a terminal, and
+// with the occasional log line otherwise, so that something is seen either way.
+pub(crate) struct ImportProgress {
+    bar: Option<ProgressBar>,
+    done: u64,
+    total: Option<u64>,
+    started: Instant,
+}
+
+impl ImportProgress {
+    pub fn new() -> Self {
+        Self {
+            bar: None,
+            done: 0,
+            total: None,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Progress for ImportProgress {
+    fn start(&mut self, total: Option<u64>) {
+        self.total = total;
+        self.started = Instant::now();
+        if !std::io::stdout().is_terminal() {
+            return;
+        }
+
+        self.bar = Some(match total {
+            Some(total) => ProgressBar::new(total).with_style(
+                ProgressStyle::with_template(
+                    "{bar:40} {pos}/{len} entries ({per_sec}, {eta} left)",
+                )
+                .expect("The progress bar template is valid"),
+            ),
+            None => ProgressBar::new_spinner().with_style(
+                ProgressStyle::with_template("{spinner} {pos} entries ({per_sec})")
+                    .expect("The spinner template is valid"),
+            ),
+        });
+    }
+
+    fn advance(&mut self) {
+        self.done += 1;
+        if let Some(bar) = &self.bar {
+            bar.inc(1);
+        } else if self.done.is_multiple_of(LOG_EVERY) {
+            let total = self
+                .total
+                .map(|total| format!("/{total}"))
+                .unwrap_or_default();
+            info!("Read {}{total} entries", self.done);
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(bar) = self.bar.take() {
+            bar.finish_and_clear();
+        }
+        info!(
+            "Read {} entries in {:.1?}",
+            self.done,
+            self.started.elapsed()
+        );
+    }
+}
@@ -0,0 +1,126 @@
+#[cfg(unix)]
+use std::sync::OnceLock;
+use std::{
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use color_eyre::eyre::{bail, Context, Result};
+use log::debug;
+
+use crate::errors::LocketError;
+
+// Runs a `dialoguer` prompt, giving up after `timeout` if one is set. `dialoguer` doesn't support
+// timeouts itself, so the prompt is run on its own thread and we wait for its answer with
+// `recv_timeout()`. If it times out, the thread is left blocked reading from the terminal, which
+// is fine since the process exits shortly afterwards.
+pub(crate) fn prompt<T, F>(timeout: Option<Duration>, f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let Some(timeout) = timeout else {
+        return Ok(f());
+    };
+
+    let terminal = TerminalState::save();
+    let (sender, receiver) = mpsc::channel();
+    thread::Builder::new()
+        .name(String::from("Prompt"))
+        .spawn(move || {
+            // If this fails then we've already timed out, and nobody cares about the answer.
+            let _ = sender.send(f());
+        })
+        .wrap_err("Failed to spawn the prompt thread")?;
+
+    match receiver.recv_timeout(timeout) {
+        Ok(answer) => Ok(answer),
+        Err(RecvTimeoutError::Timeout) => {
+            debug!("A prompt timed out after {timeout:?}");
+            terminal.restore();
+            bail!(LocketError::PromptTimedOut)
+        }
+        Err(RecvTimeoutError::Disconnected) => bail!("The prompt thread exited without answering"),
+    }
+}
+
+// Pressing Ctrl-C during a prompt kills the process with whatever state the prompt left the
+// terminal in, which for a password prompt means not echoing anything typed into it afterwards.
+// This makes SIGINT put the terminal back the way it was when this was called, and then exit.
+// Nothing has been written to the database before the interactive commands finish, and the locks
+// are released by the OS, so there's nothing else to clean up.
+#[cfg(unix)]
+pub(crate) fn restore_terminal_on_interrupt() {
+    static SAVED: OnceLock<TerminalState> = OnceLock::new();
+
+    extern "C" fn handle_sigint(_: libc::c_int) {
+        const SHOW_CURSOR: &[u8] = b"\x1b[?25h\n";
+        // SAFETY: Only async-signal-safe functions are called, and `SAVED` is always set before
+        // this handler is installed.
+        unsafe {
+            if let Some(Some(termios)) = SAVED.get().map(|terminal| &terminal.termios) {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios);
+            }
+            if libc::isatty(libc::STDERR_FILENO) == 1 {
+                libc::write(
+                    libc::STDERR_FILENO,
+                    SHOW_CURSOR.as_ptr().cast(),
+                    SHOW_CURSOR.len(),
+                );
+            }
+            // The conventional exit code for being interrupted by SIGINT.
+            libc::_exit(130);
+        }
+    }
+
+    SAVED.get_or_init(TerminalState::save);
+    // SAFETY: `handle_sigint` only does things which are safe in a signal handler.
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            handle_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+// Some prompts switch the terminal into raw mode or turn off echoing while they read, and put it
+// back afterwards. If we give up on a prompt part of the way through that never happens, so we
+// save the terminal's settings beforehand and restore them ourselves.
+struct TerminalState {
+    #[cfg(unix)]
+    termios: Option<libc::termios>,
+}
+
+impl TerminalState {
+    #[cfg(unix)]
+    fn save() -> Self {
+        let mut termios = std::mem::MaybeUninit::uninit();
+        // SAFETY: `tcgetattr()` initialises `termios` when it succeeds, and we only read it then.
+        let termios = unsafe {
+            (libc::isatty(libc::STDIN_FILENO) == 1
+                && libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) == 0)
+                .then(|| termios.assume_init())
+        };
+
+        Self { termios }
+    }
+
+    #[cfg(not(unix))]
+    fn save() -> Self {
+        Self {}
+    }
+
+    fn restore(&self) {
+        #[cfg(unix)]
+        if let Some(termios) = &self.termios {
+            // SAFETY: `termios` was filled in by `tcgetattr()` for the same file descriptor.
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios);
+            }
+        }
+
+        let _ = console::Term::stderr().show_cursor();
+        eprintln!();
+    }
+}
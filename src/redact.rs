@@ -0,0 +1,121 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Utc};
+use serde_derive::Serialize;
+use uuid::Uuid;
+
+use crate::models::{Attachment, Database, Login};
+
+// Written in place of each secret.
+const REDACTED: &str = "<redacted>";
+
+// These mirror `Database` and `Login` with the secrets left out, for diagnostic dumps which can be
+// shared, such as with a bug report. They're kept apart from the real types, so that nothing done
+// here can change how the database itself is written. Maps are sorted, so that dumping the same
+// database twice gives the same output.
+#[derive(Serialize)]
+pub(crate) struct RedactedDatabase<'a> {
+    version: u32,
+    logins: BTreeMap<&'a Uuid, RedactedLogin<'a>>,
+}
+
+#[derive(Serialize)]
+struct RedactedLogin<'a> {
+    name: &'a str,
+    username: &'a str,
+    password: &'a str,
+    favorite: bool,
+    url: &'a str,
+    notes: &'a str,
+    tags: &'a [String],
+    custom_fields: BTreeMap<&'a str, &'a str>,
+    secret_fields: BTreeSet<&'a str>,
+    #[serde(with = "chrono::serde::ts_seconds_option")]
+    last_accessed: Option<DateTime<Utc>>,
+    folder: Option<&'a str>,
+    attachments: Vec<RedactedAttachment<'a>>,
+    #[serde(with = "chrono::serde::ts_seconds_option")]
+    updated_at: Option<DateTime<Utc>>,
+}
+
+// Attachments are usually things like recovery keys, so only their names and sizes are kept.
+#[derive(Serialize)]
+struct RedactedAttachment<'a> {
+    name: &'a str,
+    size: usize,
+    bytes: &'static str,
+}
+
+impl<'a> RedactedDatabase<'a> {
+    pub(crate) fn new(db: &'a Database) -> Self {
+        Self {
+            version: db.version,
+            logins: db
+                .logins
+                .iter()
+                .map(|(id, login)| (id, RedactedLogin::new(login)))
+                .collect(),
+        }
+    }
+}
+
+impl<'a> RedactedLogin<'a> {
+    // `Login` is destructured rather than its fields read one by one, so that a field added to it
+    // can't be left out of dumps, or worse, copied into them unredacted, without this failing to
+    // compile.
+    fn new(login: &'a Login) -> Self {
+        let Login {
+            name,
+            username,
+            password,
+            favorite,
+            url,
+            notes,
+            tags,
+            custom_fields,
+            secret_fields,
+            last_accessed,
+            folder,
+            attachments,
+            updated_at,
+        } = login;
+
+        Self {
+            name,
+            username,
+            // An empty password is left as it is, since knowing that it's empty can be what
+            // explains the problem.
+            password: if password.is_empty() { "" } else { REDACTED },
+            favorite: *favorite,
+            url,
+            notes,
+            tags,
+            custom_fields: custom_fields
+                .iter()
+                .map(|(key, value)| {
+                    let value = if secret_fields.contains(key) {
+                        REDACTED
+                    } else {
+                        value.as_str()
+                    };
+                    (key.as_str(), value)
+                })
+                .collect(),
+            secret_fields: secret_fields.iter().map(String::as_str).collect(),
+            last_accessed: *last_accessed,
+            folder: folder.as_deref(),
+            attachments: attachments.iter().map(RedactedAttachment::new).collect(),
+            updated_at: *updated_at,
+        }
+    }
+}
+
+impl<'a> RedactedAttachment<'a> {
+    fn new(attachment: &'a Attachment) -> Self {
+        Self {
+            name: &attachment.name,
+            size: attachment.bytes.len(),
+            bytes: REDACTED,
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::{borrow::Cow, fs, path::PathBuf, process::Stdio};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+
+use crate::program::shell_command;
+
+const FILE_PREFIX: &str = "file:";
+const COMMAND_PREFIX: &str = "cmd:";
+const ESCAPE: char = '\\';
+
+/// Somewhere a login's password is kept instead of in the database, written in place of the
+/// password as `file:` or `cmd:` followed by where to find it. See [`crate::Login::resolve_password()`].
+///
+/// A password which really does start with `file:` or `cmd:` is stored with a `\` in front of it,
+/// which is taken off again when it's read, see [`PasswordReference::escape()`]. References can
+/// only be added from the command line, since anything else which can add logins, such as the web
+/// API, could otherwise read any file the user can, by adding a reference to it and then fetching
+/// the login.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordReference<'a> {
+    /// The password is the contents of the file at this path, without a trailing newline. `~` and
+    /// environment variables in the path are expanded.
+    File(&'a str),
+    /// The password is the first line printed by this command, which is run by the shell. Commands
+    /// are only run if `allow_password_commands` is set in the configuration file.
+    Command(&'a str),
+}
+
+impl<'a> PasswordReference<'a> {
+    /// The reference `password` is written as, if it is one. Escaped passwords aren't references.
+    #[must_use]
+    pub fn parse(password: &'a str) -> Option<Self> {
+        if let Some(path) = password.strip_prefix(FILE_PREFIX) {
+            Some(Self::File(path.trim()))
+        } else {
+            password
+                .strip_prefix(COMMAND_PREFIX)
+                .map(|command| Self::Command(command.trim()))
+        }
+    }
+
+    /// `password` as it should be stored so that it's taken as it is, rather than as a reference.
+    /// Only references are changed, by putting a `\` in front of them.
+    #[must_use]
+    pub fn escape(password: &str) -> Cow<'_, str> {
+        if PasswordReference::parse(password).is_some() {
+            Cow::Owned(format!("{ESCAPE}{password}"))
+        } else {
+            Cow::Borrowed(password)
+        }
+    }
+
+    /// The password a stored password stands for, if it isn't a reference. One `\` is taken off
+    /// the front of anything which would be a reference without its backslashes, so `\\file:` can
+    /// be used for a password which starts with `\file:`.
+    #[must_use]
+    pub fn unescape(password: &str) -> &str {
+        match password.strip_prefix(ESCAPE) {
+            Some(rest) if PasswordReference::parse(rest.trim_start_matches(ESCAPE)).is_some() => {
+                rest
+            }
+            _ => password,
+        }
+    }
+
+    pub(crate) fn resolve(self, allow_commands: bool) -> Result<String> {
+        match self {
+            Self::File(path) => {
+                let path = shellexpand::full(path)
+                    .map(|expanded| PathBuf::from(expanded.as_ref()))
+                    .map_err(|e| {
+                        eyre!(
+                            "The environment variable `{}` in `{path}` isn't set",
+                            e.var_name
+                        )
+                    })?;
+                let mut password = fs::read_to_string(&path)
+                    .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+                // Files written by editors and `echo` end with a newline which isn't part of the
+                // password.
+                if password.ends_with('\n') {
+                    password.pop();
+                    if password.ends_with('\r') {
+                        password.pop();
+                    }
+                }
+                Ok(password)
+            }
+            Self::Command(command) => {
+                if !allow_commands {
+                    bail!("The password comes from a command, which is only run if `allow_password_commands` is set in the configuration file");
+                }
+                // Standard input and error are passed through, so that the command can ask for a
+                // passphrase, as `pass` does through GPG.
+                let output = shell_command(command)
+                    .stdin(Stdio::inherit())
+                    .stderr(Stdio::inherit())
+                    .output()
+                    .wrap_err_with(|| format!("Failed to run `{command}`"))?;
+                if !output.status.success() {
+                    bail!("`{command}` failed with {}", output.status);
+                }
+                // Commands like `pass show` print other details about the login after the
+                // password, on lines of their own.
+                String::from_utf8(output.stdout)
+                    .wrap_err_with(|| format!("`{command}` didn't print UTF-8"))?
+                    .lines()
+                    .next()
+                    .map(String::from)
+                    .ok_or_else(|| eyre!("`{command}` didn't print anything"))
+            }
+        }
+    }
+}
+
+// Why a login from somewhere other than the command line was refused.
+#[cfg(any(feature = "web", all(unix, feature = "socket")))]
+pub(crate) fn refused(login: &impl std::fmt::Display) -> String {
+    format!("The password of {login} refers to a file or command, which can only be done from the command line. Start it with `{ESCAPE}` to store it as it is")
+}
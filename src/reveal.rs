@@ -0,0 +1,115 @@
+use std::{
+    io::{self, IsTerminal, Write},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use console::{measure_text_width, Term};
+use log::debug;
+
+use crate::pager;
+
+// Prints `text`, which has passwords in it, and clears it from the terminal again after `timeout`,
+// or sooner if Enter is pressed. Without a timeout, or if standard output isn't a terminal (so the
+// passwords are going somewhere they were asked to be kept), it's printed as usual instead.
+pub(crate) fn print_revealed(text: &str, timeout: Option<Duration>) {
+    let stdout = io::stdout();
+    let Some(timeout) = timeout.filter(|_| stdout.is_terminal()) else {
+        pager::print_paged(text);
+        return;
+    };
+
+    // Never paged, since the pager would be showing the passwords for as long as it liked.
+    println!("{text}");
+    let rows = rows_used(text);
+    #[cfg(unix)]
+    clear_on_interrupt(rows);
+
+    // Reading the line is left to its own thread, so that it can be given up on when the timeout
+    // comes first. It's left blocked if so, which is fine since the process exits soon afterwards.
+    // `sender` is kept here, so that the channel stays open if the thread gives up early.
+    let (sender, receiver) = mpsc::channel();
+    if io::stdin().is_terminal() {
+        let sender = sender.clone();
+        let spawned = thread::Builder::new()
+            .name(String::from("Reveal"))
+            .spawn(move || {
+                // Reaching the end of the input (from Ctrl-D) doesn't count.
+                if io::stdin()
+                    .read_line(&mut String::new())
+                    .is_ok_and(|read| read > 0)
+                {
+                    let _ = sender.send(());
+                }
+            });
+        if let Err(e) = spawned {
+            debug!("Failed to spawn the thread waiting for Enter: {e}");
+        }
+    }
+
+    // Pressing Enter moves the cursor down a line, which needs clearing too.
+    let rows = match receiver.recv_timeout(timeout) {
+        Ok(()) => rows + 1,
+        Err(_) => rows,
+    };
+    drop(sender);
+    let mut stdout = stdout.lock();
+    let _ = stdout
+        .write_all(clear_sequence(rows).as_bytes())
+        .and_then(|()| stdout.flush());
+}
+
+// How many rows of the terminal printing `text` took up, counting lines which wrapped. Only what's
+// still on the screen can be cleared, so it's no more than the terminal's height.
+fn rows_used(text: &str) -> usize {
+    let (height, width) = Term::stdout().size();
+    let width = usize::from(width).max(1);
+    let rows: usize = text
+        .lines()
+        .map(|line| measure_text_width(line).div_ceil(width).max(1))
+        .sum();
+    rows.min(usize::from(height))
+}
+
+// Moves the cursor up to the start of the first of the last `rows` rows and erases everything from
+// there down, leaving the cursor where the text began.
+fn clear_sequence(rows: usize) -> String {
+    format!("\x1b[{rows}A\r\x1b[J")
+}
+
+// Like `prompt::restore_terminal_on_interrupt()`, but clears the text before exiting, so that
+// pressing Ctrl-C doesn't leave the passwords on the screen. The sequence is built beforehand,
+// since allocating isn't safe in a signal handler.
+#[cfg(unix)]
+fn clear_on_interrupt(rows: usize) {
+    use std::sync::OnceLock;
+
+    static SEQUENCE: OnceLock<String> = OnceLock::new();
+
+    extern "C" fn handle_sigint(_: libc::c_int) {
+        // SAFETY: Only async-signal-safe functions are called, and `SEQUENCE` is always set before
+        // this handler is installed.
+        unsafe {
+            if let Some(sequence) = SEQUENCE.get() {
+                libc::write(
+                    libc::STDOUT_FILENO,
+                    sequence.as_ptr().cast(),
+                    sequence.len(),
+                );
+            }
+            // The conventional exit code for being interrupted by SIGINT.
+            libc::_exit(130);
+        }
+    }
+
+    // Ctrl-C is echoed as `^C` without moving to a new line, so the rows to clear are the same.
+    SEQUENCE.get_or_init(|| clear_sequence(rows));
+    // SAFETY: `handle_sigint` only does things which are safe in a signal handler.
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            handle_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use std::{collections::HashMap, fmt::Display};
+
+use console::style;
+use tabled::{builder::Builder, settings::Style};
+use uuid::Uuid;
+use zxcvbn::{zxcvbn, Score};
+
+use crate::{
+    errors::LoginWarning,
+    models::{Database, Login},
+    reference::PasswordReference,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Priority {
+    High,
+    Medium,
+}
+
+// Not coloured, since tabled would count the escape codes towards the width of the column.
+impl Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::High => f.write_str("high"),
+            Self::Medium => f.write_str("medium"),
+        }
+    }
+}
+
+struct Finding<'a> {
+    priority: Priority,
+    login: &'a Login,
+    problem: String,
+}
+
+// Lists the logins which need attention, most urgent first, so that they can be worked through from
+// the top. This only reads the database.
+pub(crate) fn review(db: &Database) {
+    let mut findings = Vec::new();
+
+    for login in db.logins.values() {
+        // The same checks that are made when a login is added, so that the two can't disagree.
+        for warning in login.validate() {
+            let priority = match warning {
+                LoginWarning::EmptyPassword => Priority::High,
+            };
+            findings.push(Finding {
+                priority,
+                login,
+                problem: warning.to_string(),
+            });
+        }
+
+        if is_weak(&login.password) {
+            findings.push(Finding {
+                priority: Priority::Medium,
+                login,
+                problem: String::from("The password is short or simple enough to be guessed"),
+            });
+        }
+    }
+    find_reused(db, &mut findings);
+
+    if findings.is_empty() {
+        println!("{} No logins need attention", style("✔").green());
+        return;
+    }
+
+    findings.sort_by(|a, b| {
+        (a.priority, &a.login.name, &a.login.username).cmp(&(
+            b.priority,
+            &b.login.name,
+            &b.login.username,
+        ))
+    });
+
+    let mut builder = Builder::new();
+    builder.set_header(["priority", "name", "username", "problem"]);
+    for finding in &findings {
+        builder.push_record([
+            finding.priority.to_string(),
+            finding.login.name.clone(),
+            finding.login.username.clone(),
+            finding.problem.clone(),
+        ]);
+    }
+    println!("{}", builder.build().with(Style::rounded()));
+    println!("{} problem(s) found", findings.len());
+}
+
+// A password used by more than one login is reported once for each of them, naming the others, so
+// that every login which needs changing shows up in the table.
+fn find_reused<'a>(db: &'a Database, findings: &mut Vec<Finding<'a>>) {
+    for sharing in sharing_passwords(db) {
+        for &(id, login) in &sharing {
+            let mut others: Vec<&str> = sharing
+                .iter()
+                .filter(|(other, _)| *other != id)
+                .map(|(_, other)| other.name.as_str())
+                .collect();
+            others.sort_unstable();
+            findings.push(Finding {
+                priority: Priority::High,
+                login,
+                problem: format!("The password is also used by {}", others.join(", ")),
+            });
+        }
+    }
+}
+
+// The logins which share their password with another, grouped by password. Empty passwords are
+// left out, since they're reported on their own.
+pub(crate) fn sharing_passwords(db: &Database) -> Vec<Vec<(&Uuid, &Login)>> {
+    let mut by_password: HashMap<&str, Vec<(&Uuid, &Login)>> = HashMap::new();
+    for (id, login) in &db.logins {
+        if !login.password.is_empty() {
+            by_password
+                .entry(&login.password)
+                .or_default()
+                .push((id, login));
+        }
+    }
+
+    by_password
+        .into_values()
+        .filter(|sharing| sharing.len() > 1)
+        .collect()
+}
+
+// Passwords which zxcvbn scores below 2 out of 4 are weak, which it describes as guessable by an
+// attacker who can make a few thousand guesses a second. It knows about common passwords, words
+// and names, dates, keyboard patterns and substitutions like `p4ssw0rd`. References to passwords
+// kept elsewhere say nothing about how strong the password is.
+pub(crate) fn is_weak(password: &str) -> bool {
+    !password.is_empty()
+        && PasswordReference::parse(password).is_none()
+        && zxcvbn(PasswordReference::unescape(password), &[]).score() < Score::Two
+}
@@ -0,0 +1,144 @@
+use percent_encoding::percent_decode_str;
+use tiny_http::Method;
+
+/// Finds the handler for a request from its method and path, out of the routes added with
+/// [`Router::route()`]. A route's pattern is a path whose segments are either matched as they are,
+/// or, if they start with `:`, match any single segment, which is passed on as a parameter of that
+/// name. For example, `/api/v1/login/:id` matches `/api/v1/login/1234` with `id` set to `1234`.
+///
+/// Routes are tried in the order they were added, so a literal route added before a pattern which
+/// would also match it takes precedence.
+pub struct Router<H> {
+    routes: Vec<Route<H>>,
+}
+
+struct Route<H> {
+    method: Method,
+    segments: Vec<Segment>,
+    handler: H,
+}
+
+enum Segment {
+    Literal(&'static str),
+    Param(&'static str),
+}
+
+/// The parameters taken from the path of a request by the route it matched.
+#[derive(Debug, Default)]
+pub struct Params {
+    values: Vec<(&'static str, String)>,
+}
+
+/// Why [`Router::find()`] found no handler.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RouteError {
+    /// No route has a pattern which matches the path.
+    NotFound,
+    /// At least one route's pattern matches the path, but none of them are for the method. These
+    /// are the methods which would have been, in the order their routes were added.
+    MethodNotAllowed(Vec<Method>),
+}
+
+impl<H> Default for Router<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H> Router<H> {
+    /// A router with no routes.
+    #[must_use]
+    pub fn new() -> Self {
+        Self { routes: Vec::new() }
+    }
+
+    /// Sends requests with `method` whose path matches `pattern` to `handler`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pattern` doesn't start with `/`, or has a parameter without a name.
+    #[must_use]
+    pub fn route(mut self, method: Method, pattern: &'static str, handler: H) -> Self {
+        let Some(pattern) = pattern.strip_prefix('/') else {
+            panic!("The pattern `{pattern}` doesn't start with `/`");
+        };
+        let segments = pattern
+            .split('/')
+            .map(|segment| match segment.strip_prefix(':') {
+                Some("") => panic!("The pattern `/{pattern}` has a parameter without a name"),
+                Some(name) => Segment::Param(name),
+                None => Segment::Literal(segment),
+            })
+            .collect();
+
+        self.routes.push(Route {
+            method,
+            segments,
+            handler,
+        });
+        self
+    }
+
+    /// The handler for a request with `method` to `path`, and the parameters taken from the path.
+    /// `path` is as it appears in the URL, and the parameters are percent-decoded.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RouteError::NotFound`] if no route matches `path`, or
+    /// [`RouteError::MethodNotAllowed`] if routes match it, but only for other methods.
+    pub fn find(&self, method: &Method, path: &str) -> Result<(&H, Params), RouteError> {
+        let segments: Vec<&str> = path.strip_prefix('/').unwrap_or(path).split('/').collect();
+        let mut allowed = Vec::new();
+        for route in &self.routes {
+            let Some(params) = route.matches(&segments) else {
+                continue;
+            };
+            if route.method == *method {
+                return Ok((&route.handler, params));
+            }
+            if !allowed.contains(&route.method) {
+                allowed.push(route.method.clone());
+            }
+        }
+
+        if allowed.is_empty() {
+            Err(RouteError::NotFound)
+        } else {
+            Err(RouteError::MethodNotAllowed(allowed))
+        }
+    }
+}
+
+impl<H> Route<H> {
+    // Parameters must be given a segment which isn't empty, so `/login/` doesn't match
+    // `/login/:id`.
+    fn matches(&self, segments: &[&str]) -> Option<Params> {
+        if segments.len() != self.segments.len() {
+            return None;
+        }
+
+        let mut params = Params::default();
+        for (pattern, segment) in self.segments.iter().zip(segments) {
+            match pattern {
+                Segment::Literal(literal) if literal == segment => (),
+                Segment::Param(name) if !segment.is_empty() => {
+                    let value = percent_decode_str(segment).decode_utf8_lossy();
+                    params.values.push((name, value.into_owned()));
+                }
+                _ => return None,
+            }
+        }
+        Some(params)
+    }
+}
+
+impl Params {
+    /// The value of the parameter called `name`, if the route has one.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
@@ -0,0 +1,275 @@
+use std::{
+    fs,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread,
+    time::Duration,
+};
+
+use color_eyre::eyre::{bail, Result, WrapErr};
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use signal_hook::consts::SIGINT;
+use uuid::Uuid;
+
+use crate::errors::LoginWarning;
+use crate::models::{Database, Login};
+use crate::reference;
+
+// How often the listener and each connection check whether they've been asked to shut down.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// The error codes defined by JSON-RPC 2.0, and one of our own for a login which doesn't exist.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+const NOT_FOUND: i64 = -32001;
+
+type RpcResult = Result<Value, (i64, String)>;
+
+// Serves newline-delimited JSON-RPC 2.0 on a Unix domain socket at `path`, for launchers and
+// editors which want to talk to Locket without going through HTTP. Each line sent is one request,
+// and each gets one line back. The methods are:
+//
+// - `query`, with an optional `query` string, and an optional `folder` to search within, returns
+//   the matches as `[id, login]` pairs, like `/api/v1/query`.
+// - `get`, with an `id`, returns that login.
+// - `add`, with `logins`, an array of logins, returns `{"ids", "results"}`, like `/api/v1/new`.
+// - `remove`, with an `id`, returns `null`.
+//
+// The database is written to disk after each change. Returns once the listener has been asked to
+// shut down with SIGINT and every connection has closed.
+pub(crate) fn serve(db: &Arc<Mutex<Database>>, path: &Path) -> Result<()> {
+    let should_shutdown = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGINT, Arc::clone(&should_shutdown))
+        .wrap_err("Failed to register the shutdown bool")?;
+
+    // A socket left behind by an instance which didn't exit cleanly would stop us binding. The
+    // lock only keeps other instances off this database, and `--path` can put the socket anywhere,
+    // so one which still accepts connections belongs to something else and is left alone.
+    if UnixStream::connect(path).is_ok() {
+        bail!(
+            "Something is already listening on {}, use `--path` to put the socket somewhere else",
+            path.display()
+        );
+    }
+    match fs::remove_file(path) {
+        Ok(()) => debug!("Removed the old socket at {}", path.display()),
+        Err(e) if e.kind() == ErrorKind::NotFound => (),
+        Err(e) => return Err(e).wrap_err("Failed to remove the old socket"),
+    }
+    // Anyone who can connect can read every password, so only the owner may. The socket is created
+    // with those permissions, rather than having them set afterwards, so that there's no moment
+    // where anyone else could connect. Nothing else is running yet to be affected by the umask.
+    let old_umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(old_umask) };
+    let listener = listener.wrap_err_with(|| format!("Failed to listen on {}", path.display()))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+        .wrap_err("Failed to restrict the permissions of the socket")?;
+    listener
+        .set_nonblocking(true)
+        .wrap_err("Failed to make the socket non-blocking")?;
+    info!("Listening on {}", path.display());
+
+    let mut connections = Vec::new();
+    while !should_shutdown.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let db = Arc::clone(db);
+                let should_shutdown = Arc::clone(&should_shutdown);
+                connections.push(thread::spawn(move || {
+                    if let Err(e) = handle_connection(stream, &db, &should_shutdown) {
+                        warn!("A socket connection failed: {e:#}");
+                    }
+                }));
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(SHUTDOWN_POLL_INTERVAL),
+            Err(e) => warn!("Failed to accept a connection: {e}"),
+        }
+        connections.retain(|connection| !connection.is_finished());
+    }
+
+    info!("Shutting down, waiting for connections to close");
+    for connection in connections {
+        let _ = connection.join();
+    }
+    if let Err(e) = fs::remove_file(path) {
+        warn!("Failed to remove the socket: {e}");
+    }
+
+    Ok(())
+}
+
+fn handle_connection(
+    stream: UnixStream,
+    db: &Mutex<Database>,
+    should_shutdown: &AtomicBool,
+) -> Result<()> {
+    stream
+        .set_nonblocking(false)
+        .wrap_err("Failed to make the connection blocking")?;
+    stream
+        .set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL))
+        .wrap_err("Failed to set the connection's read timeout")?;
+    let mut writer = stream
+        .try_clone()
+        .wrap_err("Failed to clone the connection")?;
+    let mut reader = BufReader::new(stream);
+
+    // Bytes, rather than a `String`, so that a line split across read timeouts isn't thrown away.
+    let mut line = Vec::new();
+    while !should_shutdown.load(Ordering::Relaxed) {
+        match reader.read_until(b'\n', &mut line) {
+            // The client hung up.
+            Ok(0) => break,
+            Ok(_) if line.ends_with(b"\n") => {
+                if !line.iter().all(u8::is_ascii_whitespace) {
+                    let response = respond(&line, db);
+                    writeln!(writer, "{response}").wrap_err("Failed to send a response")?;
+                }
+                line.clear();
+            }
+            // The client hung up part of the way through a line.
+            Ok(_) => break,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => (),
+            Err(e) => return Err(e).wrap_err("Failed to read a request"),
+        }
+    }
+
+    Ok(())
+}
+
+fn respond(line: &[u8], db: &Mutex<Database>) -> Value {
+    let request: Value = match serde_json::from_slice(line) {
+        Ok(request) => request,
+        Err(e) => return error_response(&Value::Null, PARSE_ERROR, &e.to_string()),
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let Some(method) = request.get("method").and_then(Value::as_str) else {
+        return error_response(&id, INVALID_REQUEST, "The request has no `method`");
+    };
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+    let result = match method {
+        "query" => Ok(query(&params, &lock(db))),
+        "get" => get(&params, &mut lock(db)),
+        "add" => add(&params, &mut lock(db)),
+        "remove" => remove(&params, &mut lock(db)),
+        _ => Err((METHOD_NOT_FOUND, format!("There is no method `{method}`"))),
+    };
+
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => error_response(&id, code, &message),
+    }
+}
+
+fn error_response(id: &Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+// See `net::lock()`, the same reasoning applies here.
+fn lock(db: &Mutex<Database>) -> MutexGuard<'_, Database> {
+    db.lock().unwrap_or_else(|poisoned| {
+        warn!("A thread panicked while holding the database lock");
+        PoisonError::into_inner(poisoned)
+    })
+}
+
+fn query(params: &Value, db: &Database) -> Value {
+    let query = params.get("query").and_then(Value::as_str);
+    let mut matches = db.query(query);
+    if let Some(folder) = params.get("folder").and_then(Value::as_str) {
+        matches.retain(|(_, login)| login.in_folder(folder));
+    }
+    let matches: Vec<(&Uuid, Login)> = matches
+        .into_iter()
+        .map(|(id, login)| (id, login.unescaped()))
+        .collect();
+    json!(matches)
+}
+
+// As with `/api/v1/login`, a password kept elsewhere is sent as the reference, rather than what it
+// refers to, and an escaped password without its `\`.
+fn get(params: &Value, db: &mut Database) -> RpcResult {
+    let id = parse_id(params)?;
+    db.access(id)
+        .map(|login| json!(login.unescaped()))
+        .ok_or_else(|| (NOT_FOUND, format!("There is no login with id {id}")))
+}
+
+fn add(params: &Value, db: &mut Database) -> RpcResult {
+    let Some(logins) = params.get("logins").cloned() else {
+        return Err((INVALID_PARAMS, String::from("`logins` is missing")));
+    };
+    let logins: Vec<Login> = serde_json::from_value(logins).map_err(|e| {
+        (
+            INVALID_PARAMS,
+            format!("`logins` isn't an array of logins: {e}"),
+        )
+    })?;
+    if let Some(login) = logins
+        .iter()
+        .find(|login| login.password_reference().is_some())
+    {
+        return Err((INVALID_PARAMS, reference::refused(login)));
+    }
+
+    let warnings: Vec<Vec<LoginWarning>> = logins.iter().map(Login::validate).collect();
+    let outcomes = db.append_logins(logins);
+    sync(db)?;
+
+    let ids: Vec<Uuid> = outcomes
+        .iter()
+        .filter_map(|outcome| outcome.clone().ok())
+        .collect();
+    let results: Vec<Value> = outcomes
+        .iter()
+        .zip(warnings)
+        .map(|outcome| match outcome {
+            (Ok(id), warnings) if warnings.is_empty() => json!({ "id": id }),
+            (Ok(id), warnings) => json!({
+                "id": id,
+                "warnings": warnings.iter().map(|warning| warning.kind()).collect::<Vec<_>>()
+            }),
+            (Err(e), _) => json!({ "error": e.kind(), "message": e.to_string() }),
+        })
+        .collect();
+    Ok(json!({ "ids": ids, "results": results }))
+}
+
+fn remove(params: &Value, db: &mut Database) -> RpcResult {
+    let id = parse_id(params)?;
+    if db.remove(id).is_none() {
+        return Err((NOT_FOUND, format!("There is no login with id {id}")));
+    }
+    sync(db)?;
+
+    Ok(Value::Null)
+}
+
+fn parse_id(params: &Value) -> Result<Uuid, (i64, String)> {
+    let Some(id) = params.get("id").and_then(Value::as_str) else {
+        return Err((INVALID_PARAMS, String::from("`id` is missing")));
+    };
+
+    Uuid::parse_str(id).map_err(|e| (INVALID_PARAMS, format!("`{id}` isn't a valid id: {e}")))
+}
+
+fn sync(db: &Database) -> Result<(), (i64, String)> {
+    db.sync().map_err(|e| {
+        warn!("Failed to sync the database: {e:#}");
+        (INTERNAL_ERROR, String::from("Failed to write the database"))
+    })
+}
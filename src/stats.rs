@@ -0,0 +1,117 @@
+use std::fmt::Write;
+
+use color_eyre::eyre::{Context, Result};
+use serde_derive::Serialize;
+use tabled::{builder::Builder, settings::Style};
+
+use crate::{args::StatsFormat, models::Database, review};
+
+// Counts describing the whole database, worked out once and then printed in whichever format was
+// asked for, so that the formats can't disagree. The weak and reused passwords are found the same
+// way as by `review`.
+#[derive(Debug, Serialize)]
+struct DatabaseStats {
+    logins: usize,
+    favorites: usize,
+    folders: usize,
+    attachments: usize,
+    attachment_bytes: usize,
+    empty_passwords: usize,
+    weak_passwords: usize,
+    reused_passwords: usize,
+}
+
+impl DatabaseStats {
+    fn compute(db: &Database) -> Self {
+        let logins = db.logins.values();
+        Self {
+            logins: db.logins.len(),
+            favorites: logins.clone().filter(|login| login.favorite).count(),
+            folders: db.folders().len(),
+            attachments: logins.clone().map(|login| login.attachments.len()).sum(),
+            attachment_bytes: logins
+                .clone()
+                .flat_map(|login| &login.attachments)
+                .map(|attachment| attachment.bytes.len())
+                .sum(),
+            empty_passwords: logins
+                .clone()
+                .filter(|login| login.password.is_empty())
+                .count(),
+            weak_passwords: logins
+                .filter(|login| review::is_weak(&login.password))
+                .count(),
+            reused_passwords: review::sharing_passwords(db).iter().map(Vec::len).sum(),
+        }
+    }
+
+    // Each statistic's name, which is also its name in JSON and (prefixed) in Prometheus, along
+    // with a description for Prometheus' `HELP` line.
+    fn metrics(&self) -> [(&'static str, &'static str, usize); 8] {
+        [
+            ("logins", "Logins in the database.", self.logins),
+            ("favorites", "Logins marked as favorites.", self.favorites),
+            (
+                "folders",
+                "Folders with logins in them, counting parent folders.",
+                self.folders,
+            ),
+            ("attachments", "Files attached to logins.", self.attachments),
+            (
+                "attachment_bytes",
+                "Total size of the files attached to logins, in bytes.",
+                self.attachment_bytes,
+            ),
+            (
+                "empty_passwords",
+                "Logins with an empty password.",
+                self.empty_passwords,
+            ),
+            (
+                "weak_passwords",
+                "Logins with a password short or simple enough to be guessed.",
+                self.weak_passwords,
+            ),
+            (
+                "reused_passwords",
+                "Logins whose password is also used by another login.",
+                self.reused_passwords,
+            ),
+        ]
+    }
+}
+
+// Only reads the database, and doesn't record any access, so it's cheap enough to run from a
+// monitoring system's scrape or a cron job.
+pub(crate) fn stats(db: &Database, format: StatsFormat) -> Result<()> {
+    let stats = DatabaseStats::compute(db);
+    match format {
+        StatsFormat::Table => {
+            let mut builder = Builder::new();
+            for (name, _, value) in stats.metrics() {
+                builder.push_record([name.replace('_', " "), value.to_string()]);
+            }
+            println!("{}", builder.build().with(Style::rounded()));
+        }
+        StatsFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&stats).wrap_err("Failed to serialise the statistics")?
+        ),
+        StatsFormat::Prometheus => print!("{}", prometheus(&stats)),
+    }
+    Ok(())
+}
+
+// The text exposition format, see https://prometheus.io/docs/instrumenting/exposition_formats/.
+// Everything is a gauge, since any of them can go down as well as up.
+fn prometheus(stats: &DatabaseStats) -> String {
+    let mut text = String::new();
+    for (name, help, value) in stats.metrics() {
+        // Writing to a `String` can't fail.
+        let _ = write!(
+            text,
+            "# HELP locket_{name} {help}\n# TYPE locket_{name} gauge\nlocket_{name} {value}\n"
+        );
+    }
+    text
+}
@@ -1,57 +1,292 @@
-use dialoguer::theme::Theme;
-use log::{debug, trace, warn};
-use std::sync::mpsc::Sender;
-use std::sync::{mpsc, Arc, Mutex};
+use log::{debug, error, trace, warn};
+use std::fmt::{self, Debug, Display};
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, Sender, SyncSender};
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
+/// A fixed number of threads which run the jobs given to [`Threadpool::exec()`], in the order
+/// they're given. A job which panics doesn't take its thread down with it, so the pool keeps the
+/// same number of threads for as long as it exists. Dropping the pool waits for the jobs already
+/// given to it to finish, while [`Threadpool::shutdown_timeout()`] only waits so long.
+///
+/// A pool made with [`Threadpool::new()`] queues as many jobs as it's given. One made with
+/// [`Threadpool::with_capacity()`] only queues so many, after which [`Threadpool::exec()`] waits
+/// for room, and [`Threadpool::try_exec()`] gives the job straight back, so the caller can shed
+/// the load instead.
 pub struct Threadpool {
     workers: Vec<Worker>,
-    sender: Option<Sender<Job>>,
+    sender: Option<JobSender>,
+    // The number of jobs given to the pool which no worker has picked up yet.
+    queued: Arc<AtomicUsize>,
+    capacity: Option<usize>,
 }
 
+// How often `Threadpool::shutdown_timeout()` checks whether a worker has finished.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
 type Job = Box<dyn FnOnce() + Send + 'static>;
+
+enum JobSender {
+    Unbounded(Sender<Job>),
+    Bounded(SyncSender<Job>),
+}
+
+impl JobSender {
+    fn send(&self, job: Job) -> Result<(), mpsc::SendError<Job>> {
+        match self {
+            Self::Unbounded(sender) => sender.send(job),
+            Self::Bounded(sender) => sender.send(job),
+        }
+    }
+}
+
+/// Why [`Threadpool::try_exec()`] didn't take a job. Each variant gives the job back.
+pub enum TryExecError<F> {
+    /// The queue already holds as many jobs as the pool's capacity.
+    Full(F),
+    /// Every worker has stopped, so nothing would ever run the job.
+    Stopped(F),
+}
+
+impl<F> TryExecError<F> {
+    /// Gives back the job which wasn't run.
+    pub fn into_inner(self) -> F {
+        match self {
+            Self::Full(f) | Self::Stopped(f) => f,
+        }
+    }
+}
+
+impl<F> Debug for TryExecError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full(_) => f.write_str("Full(..)"),
+            Self::Stopped(_) => f.write_str("Stopped(..)"),
+        }
+    }
+}
+
+impl<F> Display for TryExecError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full(_) => f.write_str("The threadpool's queue is full"),
+            Self::Stopped(_) => f.write_str("Every worker in the threadpool has stopped"),
+        }
+    }
+}
+
+impl<F> std::error::Error for TryExecError<F> {}
+
 impl Threadpool {
-    pub fn new(size: usize) -> Self {
+    /// Starts a pool of `size` threads, which queues as many jobs as it's given.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a thread couldn't be spawned. The threads which were spawned before it
+    /// are shut down again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
+    pub fn new(size: usize) -> io::Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        Self::start(size, JobSender::Unbounded(sender), receiver, None)
+    }
+
+    /// Starts a pool of `size` threads, which queues at most `queue_capacity` jobs that haven't
+    /// been picked up by a worker yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a thread couldn't be spawned. The threads which were spawned before it
+    /// are shut down again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` or `queue_capacity` is 0.
+    pub fn with_capacity(size: usize, queue_capacity: usize) -> io::Result<Self> {
+        assert!(
+            queue_capacity > 0,
+            "capacity of queue must be greater than 0"
+        );
+        let (sender, receiver) = mpsc::sync_channel(queue_capacity);
+        Self::start(
+            size,
+            JobSender::Bounded(sender),
+            receiver,
+            Some(queue_capacity),
+        )
+    }
+
+    fn start(
+        size: usize,
+        sender: JobSender,
+        receiver: mpsc::Receiver<Job>,
+        capacity: Option<usize>,
+    ) -> io::Result<Self> {
         trace!("Initialising threadpool");
         assert!(size > 0, "size of thread pool must be greater than 0");
 
-        let mut workers = Vec::with_capacity(size);
-        let (sender, reciever) = mpsc::channel();
+        let mut pool = Threadpool {
+            workers: Vec::with_capacity(size),
+            sender: Some(sender),
+            queued: Arc::new(AtomicUsize::new(0)),
+            capacity,
+        };
 
-        let receiver = Arc::new(Mutex::new(reciever));
+        let receiver = Arc::new(Mutex::new(receiver));
 
         for i in 0..size {
-            workers.push(Worker::new(i, Arc::clone(&receiver)));
-            trace!("Initialised thread {} of {size}", i + 1)
+            // If this fails, dropping `pool` shuts down the workers which have already started.
+            pool.workers.push(Worker::new(
+                i,
+                Arc::clone(&receiver),
+                Arc::clone(&pool.queued),
+            )?);
+            trace!("Initialised thread {} of {size}", i + 1);
         }
 
         debug!("Threadpool initialised");
 
-        Threadpool {
-            workers,
-            sender: Some(sender),
-        }
+        Ok(pool)
+    }
+
+    /// Whether the queue is at capacity, so that [`Threadpool::exec()`] would wait, and
+    /// [`Threadpool::try_exec()`] would fail. Always false for a pool without a capacity.
+    #[must_use]
+    pub fn is_full(&self) -> bool {
+        self.capacity
+            .is_some_and(|capacity| self.queued.load(Ordering::Acquire) >= capacity)
     }
 
+    /// Runs `f` on the first thread to become free. If the pool has a capacity and its queue is
+    /// full, this waits until there's room, which applies backpressure to the caller.
     pub fn exec<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        let job = Box::new(f);
+        self.queued.fetch_add(1, Ordering::AcqRel);
 
-        self.sender.as_ref().unwrap().send(job).unwrap();
+        // The workers only stop once the sender has been dropped, which doesn't happen until the
+        // pool is dropped, so this can't fail in practice.
+        if let Some(Err(_)) = self.sender.as_ref().map(|sender| sender.send(Box::new(f))) {
+            self.queued.fetch_sub(1, Ordering::AcqRel);
+            error!("Every worker has stopped, so a job was dropped");
+        }
+    }
+
+    /// Like [`Threadpool::exec()`], but gives back a channel which receives whatever `f` returns
+    /// once it has run. If `f` panics, or the pool is dropped before running it, the channel is
+    /// closed without receiving anything.
+    pub fn exec_with_result<F, T>(&self, f: F) -> Receiver<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        self.exec(move || {
+            // The caller may not care about the result after all, and have dropped the receiver.
+            let _ = sender.send(f());
+        });
+        receiver
+    }
+
+    /// Runs `f` on the first thread to become free, unless the queue is full, in which case it's
+    /// given straight back rather than waiting, so the caller can shed the load instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TryExecError::Full`] if the queue is full, or [`TryExecError::Stopped`] if every
+    /// worker has stopped.
+    pub fn try_exec<F>(&self, f: F) -> Result<(), TryExecError<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let Some(sender) = &self.sender else {
+            return Err(TryExecError::Stopped(f));
+        };
+
+        // A place in the queue is reserved before sending, since a job which has been boxed can't
+        // be given back. The count only goes down once a worker has taken a job off the queue, so
+        // with a place reserved, sending can't wait, unless `exec()` is being called at the same
+        // time.
+        if let Some(capacity) = self.capacity {
+            if self
+                .queued
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |queued| {
+                    (queued < capacity).then_some(queued + 1)
+                })
+                .is_err()
+            {
+                return Err(TryExecError::Full(f));
+            }
+        } else {
+            self.queued.fetch_add(1, Ordering::AcqRel);
+        }
+
+        if sender.send(Box::new(f)).is_err() {
+            self.queued.fetch_sub(1, Ordering::AcqRel);
+            error!("Every worker has stopped, so a job was dropped");
+        }
+        Ok(())
+    }
+
+    /// Stops taking jobs and waits up to `timeout` for the workers to finish the jobs already
+    /// given to the pool, unlike dropping it, which waits for as long as that takes.
+    ///
+    /// Returns the ids of the workers which were still running when the time ran out. Their
+    /// threads are left to finish in the background, and the jobs still queued behind them will
+    /// be run too, unless the process exits first.
+    #[must_use = "workers which didn't stop in time should be reported"]
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Vec<usize> {
+        drop(self.sender.take());
+        let deadline = Instant::now() + timeout;
+
+        let mut running = Vec::new();
+        for worker in &mut self.workers {
+            let Some(handle) = worker.handle.take() else {
+                continue;
+            };
+
+            // `JoinHandle` can't be joined with a timeout, so it's polled until it finishes.
+            while !handle.is_finished() && Instant::now() < deadline {
+                thread::sleep(SHUTDOWN_POLL_INTERVAL);
+            }
+
+            if handle.is_finished() {
+                if handle.join().is_err() {
+                    warn!("Worker {} panicked", worker.id);
+                }
+                trace!("Shut down worker {}", worker.id);
+            } else {
+                // Dropping the handle detaches the thread, rather than waiting for it.
+                debug!("Worker {} didn't stop in time", worker.id);
+                running.push(worker.id);
+            }
+        }
+
+        running
     }
 }
 
 impl Drop for Threadpool {
     fn drop(&mut self) {
+        // Every worker shares the one receiver, so the sender has to be dropped before any of
+        // them are joined, otherwise the first join would wait forever for a disconnection.
+        drop(self.sender.take());
+
         for worker in &mut self.workers {
-            drop(self.sender.take());
             debug!("Shutting down worker {}", worker.id);
 
             if let Some(handle) = worker.handle.take() {
-                handle.join().unwrap();
+                if handle.join().is_err() {
+                    warn!("Worker {} panicked", worker.id);
+                }
             }
 
             trace!("Shut down worker {}", worker.id);
@@ -65,29 +300,39 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Self {
+    fn new(
+        id: usize,
+        receiver: Arc<Mutex<mpsc::Receiver<Job>>>,
+        queued: Arc<AtomicUsize>,
+    ) -> io::Result<Self> {
         let handle = thread::Builder::new()
             .name(format!("Worker {id}"))
             .spawn(move || loop {
-                let message = receiver.lock().unwrap().recv();
-
-                match message {
-                    Ok(job) => {
-                        trace!("Worker {id} got a job; executing.");
-
-                        job();
-                    }
-                    Err(_) => {
-                        debug!("Worker {id} disconnected; shutting down.");
-                        break;
-                    }
+                // Jobs are never run while the lock is held, so it can only be poisoned by a panic
+                // in `recv()` itself, which leaves nothing half done.
+                let message = receiver
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .recv();
+
+                let Ok(job) = message else {
+                    debug!("Worker {id} disconnected; shutting down.");
+                    break;
+                };
+                queued.fetch_sub(1, Ordering::AcqRel);
+
+                trace!("Worker {id} got a job; executing.");
+                // The panic message has already been printed by the panic hook. Anything the job
+                // shared with other threads is behind a mutex, which is poisoned for the next user
+                // to deal with, so it's safe to carry on with the next job.
+                if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                    warn!("A job panicked in worker {id}");
                 }
-            })
-            .unwrap();
+            })?;
 
-        Self {
+        Ok(Self {
             id,
             handle: Some(handle),
-        }
+        })
     }
 }
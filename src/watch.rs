@@ -0,0 +1,145 @@
+use std::{
+    path::Path,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use color_eyre::eyre::{eyre, Context, Result};
+use log::{debug, info, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::{
+    config_show::lookup,
+    models::{Config, CONFIG_KEYS},
+};
+
+// Editors often save a file in several steps, such as truncating it and then writing it, so it's
+// only read again once it's been left alone for this long.
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+// The settings which `serve` only reads when it starts. The logger is set up before anything else,
+// so `log_level` is one of them.
+const RESTART_KEYS: &[&str] = &["path", "port", "web_workers", "log_level"];
+// The settings which `Site::reconfigure()` and `Database::configure()` apply while `serve` runs.
+// The rest only affect other commands.
+const LIVE_KEYS: &[&str] = &[
+    "web_assets_dir",
+    "idempotency_ttl_secs",
+    "allow_snapshot_download",
+    "backup_retention",
+    "max_attachment_bytes",
+    "fuzzy.prefer_prefix",
+    "fuzzy.match_paths",
+    "fuzzy.case",
+    "allow_password_commands",
+    "max_logins",
+];
+
+// The configuration file is watched for as long as this is kept.
+pub(crate) struct ConfigWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+// Watches the file `config` was read from, and sends the configuration through the returned
+// channel each time it changes. Changes to the settings which can't be applied while the server is
+// running are logged instead, as is a file which can't be read, in which case the server carries on
+// with what it had.
+pub(crate) fn watch_config(config: &Config) -> Result<(ConfigWatcher, Receiver<Config>)> {
+    let file = config
+        .file()
+        .ok_or_else(|| eyre!("The configuration wasn't read from a file, so it can't be watched"))?
+        .to_path_buf();
+    // The directory is watched rather than the file, since editors which save by writing a new
+    // file and renaming it over the old one would otherwise leave nothing to watch.
+    let dir = file.parent().unwrap_or_else(|| Path::new("."));
+    let name = file.file_name().map(ToOwned::to_owned);
+
+    let (writes_tx, writes) = mpsc::channel();
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+            Ok(event)
+                if !matches!(event.kind, EventKind::Access(_))
+                    && event
+                        .paths
+                        .iter()
+                        .any(|path| path.file_name() == name.as_deref()) =>
+            {
+                let _ = writes_tx.send(());
+            }
+            Ok(_) => (),
+            Err(e) => warn!("Failed to watch the configuration file: {e}"),
+        })
+        .wrap_err("Failed to start watching the configuration file")?;
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .wrap_err_with(|| format!("Failed to watch {}", dir.display()))?;
+
+    let (reloads_tx, reloads) = mpsc::channel();
+    let mut current = config.clone();
+    thread::spawn(move || {
+        while writes.recv().is_ok() {
+            loop {
+                match writes.recv_timeout(SETTLE_TIME) {
+                    Ok(()) => (),
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+
+            let config = match current.reload() {
+                Ok(config) => config,
+                Err(e) => {
+                    warn!(
+                        "Ignoring the changes to {}, it couldn't be read: {e:#}",
+                        file.display()
+                    );
+                    continue;
+                }
+            };
+            let changed = changed_keys(&current, &config);
+            if changed.is_empty() {
+                debug!("{} was written, but no settings changed", file.display());
+                continue;
+            }
+
+            for key in changed.iter().filter(|key| RESTART_KEYS.contains(key)) {
+                warn!("`{key}` changed, restart `serve` for it to take effect");
+            }
+            let (live, unused): (Vec<_>, Vec<_>) = changed
+                .into_iter()
+                .filter(|key| !RESTART_KEYS.contains(key))
+                .partition(|key| LIVE_KEYS.contains(key));
+            if !live.is_empty() {
+                info!("Applying the new `{}`", live.join("`, `"));
+            }
+            if !unused.is_empty() {
+                debug!(
+                    "`{}` changed, but `serve` doesn't use them",
+                    unused.join("`, `")
+                );
+            }
+
+            if reloads_tx.send(config.clone()).is_err() {
+                return;
+            }
+            current = config;
+        }
+    });
+
+    Ok((ConfigWatcher { _watcher: watcher }, reloads))
+}
+
+// The settings which differ between `old` and `new`.
+fn changed_keys(old: &Config, new: &Config) -> Vec<&'static str> {
+    let (Ok(toml::Value::Table(old)), Ok(toml::Value::Table(new))) =
+        (toml::Value::try_from(old), toml::Value::try_from(new))
+    else {
+        return Vec::new();
+    };
+    CONFIG_KEYS
+        .iter()
+        .chain(&["templates"])
+        .copied()
+        .filter(|key| lookup(&old, key) != lookup(&new, key))
+        .collect()
+}
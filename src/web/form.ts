@@ -18,7 +18,7 @@ async function submit() {
 	let response = await fetch('/api/v1/new', options);
 
 	if (response.ok) {
-		window.location.href = '/query';
+		window.location.href = '/logins';
 		if (window.localStorage.getItem('show-add-success') == null) {
 			window.localStorage.setItem(
 				'show-add-success',
@@ -40,6 +40,40 @@ async function submit() {
 	window.alert('Failed to add a login.');
 }
 
+// Saves the changes made on the edit page. Only the fields on the page are sent, so anything else
+// about the login is kept as it was.
+async function update(id: string) {
+	let url: URL = new URL('/api/v1/update', window.location.origin);
+	url.searchParams.append('id', id);
+
+	let body: BodyInit = JSON.stringify(
+		new Login(
+			(<HTMLInputElement>document.getElementById('name')).value,
+			(<HTMLInputElement>document.getElementById('username')).value,
+			(<HTMLInputElement>document.getElementById('password')).value
+		)
+	);
+
+	let response = await fetch(url, {
+		method: 'PATCH',
+		body: body,
+		headers: [['Content-Type', 'application/json']],
+	});
+
+	if (response.ok) {
+		window.location.href = '/logins';
+		return;
+	}
+
+	console.log(response.status);
+	console.log(response.statusText);
+	if (response.status == 409) {
+		window.alert('Another login already has that name and username.');
+	} else {
+		window.alert('Failed to save the login.');
+	}
+}
+
 class Login {
 	name: string;
 	username: string;
@@ -35,3 +35,167 @@ async function remove_login(id: string) {
 	console.error(res.url);
 	window.alert('Failed to delete the login');
 }
+
+// A login as the JSON API returns it. Only the fields the page shows are listed.
+interface Login {
+	name: string;
+	username: string;
+	password: string;
+	favorite: boolean;
+	custom_fields: { [key: string]: string };
+}
+
+// Fills in the `/logins` page from the JSON API. Everything from the database is put into the page
+// as text, never as HTML, so a login can't change the page by what's in it. Passwords are fetched
+// masked, and each one is only fetched for real when it's shown.
+async function render_logins() {
+	const grid = document.getElementById('logins');
+	if (grid == null) {
+		// The server-rendered `/query` page, which is filled in already.
+		return;
+	}
+
+	const params = new URLSearchParams(window.location.search);
+	const query = params.get('query') ?? '';
+	const folder = params.get('folder');
+	(<HTMLInputElement>document.getElementById('query')).value = query;
+
+	const url = new URL('/api/v1/query', window.location.origin);
+	url.searchParams.append('query', query);
+	if (folder != null) {
+		url.searchParams.append('folder', folder);
+	}
+	url.searchParams.append('masked', 'true');
+
+	try {
+		const [matches, folders] = await Promise.all([
+			fetch_json<[string, Login][]>(url),
+			fetch_json<string[]>(
+				new URL('/api/v1/folders', window.location.origin)
+			),
+		]);
+		render_folders(folders, query);
+		grid.replaceChildren(
+			...matches.map(([id, login]) => render_card(id, login))
+		);
+	} catch (e) {
+		console.error(e);
+		const error = document.getElementById('error')!;
+		error.textContent = 'Failed to load the logins.';
+		error.hidden = false;
+	}
+}
+
+async function fetch_json<T>(url: URL): Promise<T> {
+	const response = await fetch(url);
+	if (!response.ok) {
+		throw new Error(`${response.url} returned ${response.status}`);
+	}
+	return response.json();
+}
+
+// Links to each folder, indented by how deep it is, which keep the current search. The folders
+// come sorted, so each one is listed straight after the folder it's inside.
+function render_folders(folders: string[], query: string) {
+	if (folders.length == 0) {
+		return;
+	}
+
+	const link = (text: string, folder: string | null, depth: number) => {
+		const href = new URL('/logins', window.location.origin);
+		href.searchParams.append('query', query);
+		if (folder != null) {
+			href.searchParams.append('folder', folder);
+		}
+
+		const a = document.createElement('a');
+		a.className = 'hover:underline';
+		a.href = href.toString();
+		a.textContent = text;
+		const li = document.createElement('li');
+		li.style.paddingLeft = `${depth}rem`;
+		li.append(a);
+		return li;
+	};
+
+	document
+		.getElementById('folders')!
+		.replaceChildren(
+			link('All logins', null, 0),
+			...folders.map((folder) =>
+				link(
+					folder.split('/').pop()!,
+					folder,
+					folder.split('/').length
+				)
+			)
+		);
+}
+
+function render_card(id: string, login: Login): HTMLElement {
+	const card = clone_template('card');
+	card.id = id;
+	fill(card, 'name', (login.favorite ? '★ ' : '') + login.name);
+	fill(card, 'username', login.username);
+	fill(card, 'password', login.password);
+
+	render_fields(card, login);
+
+	const edit = new URL('/edit', window.location.origin);
+	edit.searchParams.append('id', id);
+	(<HTMLAnchorElement>card.querySelector('[data-field="edit"]')).href =
+		edit.toString();
+	card.querySelector('[data-action="remove"]')!.addEventListener(
+		'click',
+		() => remove_login(id)
+	);
+	card.querySelector('[data-action="show"]')!.addEventListener(
+		'click',
+		() => show_secrets(card, id)
+	);
+
+	return card;
+}
+
+// Replaces the masked password and secret fields on the card with the real ones.
+async function show_secrets(card: HTMLElement, id: string) {
+	const url = new URL('/api/v1/login', window.location.origin);
+	url.searchParams.append('id', id);
+
+	let login: Login;
+	try {
+		login = await fetch_json<Login>(url);
+	} catch (e) {
+		console.error(e);
+		window.alert('Failed to get the password.');
+		return;
+	}
+
+	fill(card, 'password', login.password);
+	render_fields(card, login);
+	card.querySelector('[data-action="show"]')!.remove();
+}
+
+function render_fields(card: HTMLElement, login: Login) {
+	card.querySelector('[data-field="custom_fields"]')!.replaceChildren(
+		...Object.keys(login.custom_fields)
+			.sort()
+			.map((key) => {
+				const field = clone_template('field');
+				fill(field, 'key', key);
+				fill(field, 'value', login.custom_fields[key]);
+				return field;
+			})
+	);
+}
+
+function clone_template(id: string): HTMLElement {
+	const template = <HTMLTemplateElement>document.getElementById(id);
+	return <HTMLElement>template.content.firstElementChild!.cloneNode(true);
+}
+
+function fill(element: HTMLElement, field: string, text: string) {
+	element.querySelector(`[data-field="${field}"]`)!.textContent = text;
+}
+
+document.addEventListener('DOMContentLoaded', render_logins);
@@ -0,0 +1,376 @@
+use std::{
+    env, fs,
+    path::PathBuf,
+    process,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use chrono::DateTime;
+use itertools::Itertools;
+use locket::{Database, Login, MergePolicy, SCHEMA_VERSION};
+use uuid::Uuid;
+
+// A database in a directory of its own, which is removed again when it's dropped.
+struct TestDatabase {
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl TestDatabase {
+    fn new(name: &str) -> (Self, Database) {
+        let dir = env::temp_dir().join(format!("locket-test-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("locket.db");
+        let db = Database::init(&path).unwrap();
+        (Self { dir, path }, db)
+    }
+}
+
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn login(name: &str) -> Login {
+    Login::new(
+        String::from(name),
+        String::from("me"),
+        String::from("hunter2"),
+    )
+}
+
+fn with_password(name: &str, password: &str) -> Login {
+    Login {
+        password: String::from(password),
+        ..login(name)
+    }
+}
+
+// A database which only exists in memory, holding `logins` under the ids given with them.
+fn database(logins: impl IntoIterator<Item = (u128, Login)>) -> Database {
+    let mut db = Database::default();
+    db.logins = logins
+        .into_iter()
+        .map(|(id, login)| (Uuid::from_u128(id), login))
+        .collect();
+    db
+}
+
+fn names(db: &Database) -> Vec<&str> {
+    db.logins
+        .values()
+        .map(|login| login.name.as_str())
+        .sorted()
+        .collect()
+}
+
+#[test]
+fn queries_and_syncs_from_many_threads_agree() {
+    const WRITERS: usize = 4;
+    const READERS: usize = 4;
+    const LOGINS_PER_WRITER: usize = 25;
+
+    let (file, db) = TestDatabase::new("queries_and_syncs_from_many_threads_agree");
+    let db = Arc::new(Mutex::new(db));
+
+    let writers: Vec<_> = (0..WRITERS)
+        .map(|writer| {
+            let db = Arc::clone(&db);
+            thread::spawn(move || {
+                for i in 0..LOGINS_PER_WRITER {
+                    let mut db = db.lock().unwrap();
+                    db.add_login(login(&format!("{writer}-{i}"))).unwrap();
+                    db.sync().unwrap();
+                }
+            })
+        })
+        .collect();
+    let readers: Vec<_> = (0..READERS)
+        .map(|_| {
+            let db = Arc::clone(&db);
+            let path = file.path.clone();
+            thread::spawn(move || {
+                let mut last = 0;
+                for _ in 0..LOGINS_PER_WRITER {
+                    let count = db.lock().unwrap().query(None).len();
+                    assert!(count >= last, "{count} logins after seeing {last}");
+                    assert!(count <= WRITERS * LOGINS_PER_WRITER);
+                    last = count;
+
+                    // The file is replaced as a whole, so reading it without the lock never sees
+                    // half of a sync.
+                    let on_disk = Database::open(&path).unwrap().logins.len();
+                    assert!(on_disk <= WRITERS * LOGINS_PER_WRITER);
+                }
+            })
+        })
+        .collect();
+    for thread in writers.into_iter().chain(readers) {
+        thread.join().unwrap();
+    }
+
+    assert_eq!(
+        db.lock().unwrap().query(None).len(),
+        WRITERS * LOGINS_PER_WRITER
+    );
+    assert_eq!(
+        Database::open(&file.path).unwrap().logins.len(),
+        WRITERS * LOGINS_PER_WRITER
+    );
+}
+
+#[test]
+fn the_file_version_is_kept_after_upgrading() {
+    let (file, mut db) = TestDatabase::new("the_file_version_is_kept_after_upgrading");
+    db.version = 0;
+    db.sync().unwrap();
+
+    let db = Database::open(&file.path).unwrap();
+    assert_eq!(db.file_version(), 0);
+    assert_eq!(db.version, SCHEMA_VERSION);
+}
+
+#[test]
+fn login_times_are_written_as_seconds() {
+    let mut accessed = login("mail");
+    accessed.last_accessed = DateTime::from_timestamp(1_700_000_000, 0);
+
+    let json = serde_json::to_value(&accessed).unwrap();
+    assert_eq!(json["last_accessed"], 1_700_000_000);
+    assert_eq!(json["updated_at"], serde_json::Value::Null);
+    assert_eq!(serde_json::from_value::<Login>(json).unwrap(), accessed);
+}
+
+#[test]
+fn merge_adds_new_logins_and_skips_identical_ones() {
+    let mut db = database([(1, login("mail"))]);
+    let other = database([(1, login("mail")), (2, login("bank"))]);
+
+    let report = db.merge(other, MergePolicy::Skip).unwrap();
+
+    assert_eq!(report.added.len(), 1);
+    assert_eq!(report.skipped.len(), 1);
+    assert_eq!(report.conflicted, 0);
+    // New logins keep the id they had in the other database.
+    assert_eq!(db.logins[&Uuid::from_u128(2)].name, "bank");
+}
+
+#[test]
+fn merge_matches_by_id_before_name_and_username() {
+    // The same id, renamed in the other database.
+    let mut db = database([(1, login("mail"))]);
+    let other = database([(1, login("email"))]);
+    let report = db.merge(other, MergePolicy::Overwrite).unwrap();
+    assert_eq!((report.conflicted, report.overwritten.len()), (1, 1));
+    assert_eq!(names(&db), ["email"]);
+
+    // A different id, but the same name and username.
+    let mut db = database([(1, with_password("mail", "old"))]);
+    let other = database([(2, with_password("mail", "new"))]);
+    let report = db.merge(other, MergePolicy::Overwrite).unwrap();
+    assert_eq!((report.conflicted, report.overwritten.len()), (1, 1));
+    assert_eq!(db.logins.len(), 1);
+    assert_eq!(db.logins[&Uuid::from_u128(1)].password, "new");
+}
+
+#[test]
+fn merge_policies_decide_conflicts() {
+    let here = || database([(1, with_password("mail", "old"))]);
+    let there = || database([(2, with_password("mail", "new"))]);
+
+    let mut db = here();
+    let report = db.merge(there(), MergePolicy::Skip).unwrap();
+    assert_eq!((report.conflicted, report.skipped.len()), (1, 1));
+    assert_eq!(db.logins[&Uuid::from_u128(1)].password, "old");
+
+    let mut db = here();
+    let report = db.merge(there(), MergePolicy::Overwrite).unwrap();
+    assert_eq!((report.conflicted, report.overwritten.len()), (1, 1));
+    assert_eq!(db.logins[&Uuid::from_u128(1)].password, "new");
+
+    let mut db = here();
+    let report = db.merge(there(), MergePolicy::KeepBoth).unwrap();
+    assert_eq!((report.conflicted, report.added.len()), (1, 1));
+    assert_eq!(db.logins[&Uuid::from_u128(1)].password, "old");
+    // Kept under the id it had, which is free here.
+    let kept = &db.logins[&Uuid::from_u128(2)];
+    assert_eq!(
+        (kept.name.as_str(), kept.password.as_str()),
+        ("mail (2)", "new")
+    );
+}
+
+#[test]
+fn merge_keeping_both_renames_past_every_copy() {
+    let mut db = database([(1, login("mail")), (2, login("mail (2)"))]);
+    // Matched by id, so keeping both needs a new id as well as a new name.
+    let other = database([(1, with_password("mail", "new"))]);
+
+    let report = db.merge(other, MergePolicy::KeepBoth).unwrap();
+
+    assert_eq!(report.added.len(), 1);
+    assert_eq!(names(&db), ["mail", "mail (2)", "mail (3)"]);
+    assert_eq!(db.logins[&Uuid::from_u128(1)].password, "hunter2");
+}
+
+#[test]
+fn merged_logins_count_as_changed_here() {
+    let mut db = database([(1, with_password("mail", "old"))]);
+    let other = || {
+        let mut other = database([(1, with_password("mail", "new")), (2, login("bank"))]);
+        for login in other.logins.values_mut() {
+            login.updated_at = DateTime::from_timestamp(1, 0);
+        }
+        other
+    };
+
+    db.merge(other(), MergePolicy::Overwrite).unwrap();
+    assert!(db
+        .logins
+        .values()
+        .all(|login| login.updated_at > DateTime::from_timestamp(1, 0)));
+
+    // Merging the same logins again finds nothing to do, even though they were changed here.
+    let report = db.merge(other(), MergePolicy::Overwrite).unwrap();
+    assert_eq!((report.conflicted, report.skipped.len()), (0, 2));
+}
+
+#[test]
+fn merge_refuses_to_grow_past_max_logins() {
+    let mut db = database([(1, login("mail"))]);
+    db.max_logins = 2;
+    let other = || database([(1, with_password("mail", "new")), (2, login("bank"))]);
+
+    // The new login fits, but keeping both copies of the conflicting one too wouldn't.
+    assert!(db.merge(other(), MergePolicy::KeepBoth).is_err());
+    assert_eq!(names(&db), ["mail"]);
+
+    let report = db.merge(other(), MergePolicy::Overwrite).unwrap();
+    assert_eq!(report.added.len(), 1);
+    assert_eq!(names(&db), ["bank", "mail"]);
+}
+
+#[test]
+fn diff_matches_logins_like_merge() {
+    let here = database([
+        (1, login("mail")),
+        (2, with_password("bank", "old")),
+        (3, login("forum")),
+        (4, login("only here")),
+    ]);
+    let mut accessed = login("forum");
+    accessed.last_accessed = DateTime::from_timestamp(1_700_000_000, 0);
+    let there = database([
+        // Renamed, but matched by its id.
+        (1, login("email")),
+        // A different id, but matched by name and username.
+        (20, with_password("bank", "new")),
+        (3, accessed),
+        (5, login("only there")),
+    ]);
+
+    let report = here.diff(&there);
+
+    assert_eq!(report.only_here, [login("only here").to_string()]);
+    assert_eq!(report.only_there, [login("only there").to_string()]);
+    // Only being accessed at a different time doesn't count as a change, and passwords are masked.
+    assert_eq!(report.unchanged, 1);
+    let changed: Vec<_> = report
+        .changed
+        .iter()
+        .map(|changed| {
+            let fields: Vec<_> = changed
+                .fields
+                .iter()
+                .map(|change| {
+                    (
+                        change.field.as_str(),
+                        change.here.as_str(),
+                        change.there.as_str(),
+                    )
+                })
+                .collect();
+            (changed.login.as_str(), fields)
+        })
+        .collect();
+    assert_eq!(
+        changed,
+        [
+            (
+                login("bank").to_string().as_str(),
+                vec![("password", "********", "******** (different)")]
+            ),
+            (
+                login("mail").to_string().as_str(),
+                vec![("name", "mail", "email")]
+            ),
+        ]
+    );
+}
+
+#[test]
+fn plan_import_matches_logins_by_name_and_username() {
+    let db = database([(1, with_password("mail", "old")), (2, login("bank"))]);
+    let incoming = vec![
+        with_password("mail", "new"),
+        login("bank"),
+        login("forum"),
+        login("forum"),
+    ];
+
+    let plan = db.plan_import(incoming, MergePolicy::Overwrite);
+
+    assert_eq!(plan.new, [login("forum")]);
+    // The same as a login already there, and a repeat of one earlier in the import.
+    assert_eq!(plan.duplicates, [login("bank"), login("forum")]);
+    let [conflict] = plan.conflicts.as_slice() else {
+        panic!("expected one conflict, got {:?}", plan.conflicts);
+    };
+    assert_eq!(conflict.existing, Uuid::from_u128(1));
+    assert_eq!(conflict.login.password, "new");
+    assert_eq!(conflict.resolution, MergePolicy::Overwrite);
+}
+
+#[test]
+fn apply_import_follows_each_resolution() {
+    let mut db = database([
+        (1, with_password("mail", "old")),
+        (2, with_password("bank", "old")),
+        (3, with_password("forum", "old")),
+        (4, login("forum (2)")),
+    ]);
+    let incoming = vec![
+        with_password("mail", "new"),
+        with_password("bank", "new"),
+        with_password("forum", "new"),
+        login("shop"),
+    ];
+    let mut plan = db.plan_import(incoming, MergePolicy::Skip);
+    for conflict in &mut plan.conflicts {
+        conflict.resolution = match conflict.login.name.as_str() {
+            "mail" => MergePolicy::Skip,
+            "bank" => MergePolicy::Overwrite,
+            _ => MergePolicy::KeepBoth,
+        };
+    }
+
+    db.apply_import(plan).unwrap();
+
+    assert_eq!(db.logins[&Uuid::from_u128(1)].password, "old");
+    assert_eq!(db.logins[&Uuid::from_u128(2)].password, "new");
+    assert_eq!(db.logins[&Uuid::from_u128(3)].password, "old");
+    // Kept alongside the original, under the first name which isn't taken.
+    let kept = db
+        .logins
+        .values()
+        .find(|login| login.password == "new" && login.name.starts_with("forum"))
+        .unwrap();
+    assert_eq!(kept.name, "forum (3)");
+    assert_eq!(
+        names(&db),
+        ["bank", "forum", "forum (2)", "forum (3)", "mail", "shop"]
+    );
+}
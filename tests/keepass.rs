@@ -0,0 +1,178 @@
+use std::fmt::Write;
+
+use locket::{parse_keepass_xml, write_keepass_csv, Login, Progress};
+
+struct NoProgress;
+
+impl Progress for NoProgress {
+    fn advance(&mut self) {}
+}
+
+fn login(name: &str, folder: Option<&str>, tags: &[&str], notes: &str) -> Login {
+    Login {
+        url: format!("https://{name}.example"),
+        notes: String::from(notes),
+        folder: folder.map(String::from),
+        tags: tags.iter().map(|tag| String::from(*tag)).collect(),
+        ..Login::new(
+            String::from(name),
+            String::from("me@example.com"),
+            String::from("p\"a,s<s>&"),
+        )
+    }
+}
+
+// Reads the CSV `write_keepass_csv()` writes, where every field is quoted, and quotes and line
+// breaks only appear inside them.
+fn read_quoted_csv(csv: &str) -> Vec<Vec<String>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = csv.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\n', false) => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            (c, _) => field.push(c),
+        }
+    }
+    rows
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Builds the XML export KeePassXC would write after importing `csv`, with each group path turned
+// into nested groups under the database's own root group.
+fn csv_to_keepass_xml(csv: &str) -> String {
+    let rows = read_quoted_csv(csv);
+    let (header, rows) = rows.split_first().unwrap();
+    assert_eq!(
+        header,
+        &["Group", "Title", "Username", "Password", "URL", "Notes"]
+    );
+
+    let mut xml = String::from("<KeePassFile><Root><Group><Name>Root</Name>");
+    for row in rows {
+        let [group, title, username, password, url, notes] = row.as_slice() else {
+            panic!("expected six fields, got {row:?}");
+        };
+        let groups: Vec<&str> = group.split('/').filter(|name| !name.is_empty()).collect();
+        for name in &groups {
+            write!(xml, "<Group><Name>{}</Name>", escape_xml(name)).unwrap();
+        }
+        xml.push_str("<Entry>");
+        for (key, value) in [
+            ("Title", title),
+            ("UserName", username),
+            ("Password", password),
+            ("URL", url),
+            ("Notes", notes),
+        ] {
+            write!(
+                xml,
+                "<String><Key>{key}</Key><Value>{}</Value></String>",
+                escape_xml(value)
+            )
+            .unwrap();
+        }
+        xml.push_str("</Entry>");
+        xml.push_str(&"</Group>".repeat(groups.len()));
+    }
+    xml.push_str("</Group></Root></KeePassFile>");
+    xml
+}
+
+#[test]
+fn keepass_csv_exports_import_again() {
+    let logins = [
+        login(
+            "mail",
+            Some("Work/Cloud"),
+            &["ignored"],
+            "two\nlines, \"quoted\"",
+        ),
+        login("bank", None, &["Finance", "Home"], ""),
+        login("forum", None, &[], "<b>bold</b>"),
+    ];
+
+    let mut csv = Vec::new();
+    write_keepass_csv(&logins.iter().collect::<Vec<_>>(), &mut csv).unwrap();
+    let xml = csv_to_keepass_xml(&String::from_utf8(csv).unwrap());
+    let (imported, skipped) = parse_keepass_xml(xml.as_bytes(), &mut NoProgress).unwrap();
+
+    assert!(skipped.is_empty(), "{skipped:?}");
+    assert_eq!(imported.len(), logins.len());
+    for (exported, imported) in logins.iter().zip(&imported) {
+        assert_eq!(imported.name, exported.name);
+        assert_eq!(imported.username, exported.username);
+        assert_eq!(imported.password, exported.password);
+        assert_eq!(imported.url, exported.url);
+        assert_eq!(imported.notes, exported.notes);
+    }
+    // The folder is the group, and without one the first tag stands in for it.
+    assert_eq!(imported[0].folder.as_deref(), Some("Work/Cloud"));
+    assert_eq!(imported[1].folder.as_deref(), Some("Finance"));
+    assert_eq!(imported[2].folder, None);
+}
+
+#[test]
+fn keepass_xml_skips_history_and_untitled_entries() {
+    let xml = r#"
+        <KeePassFile><Root><Group><Name>Database</Name>
+            <Entry>
+                <String><Key>Title</Key><Value>mail</Value></String>
+                <String><Key>Password</Key><Value>current</Value></String>
+                <String><Key>Custom</Key><Value>unsupported</Value></String>
+                <History><Entry>
+                    <String><Key>Title</Key><Value>mail</Value></String>
+                    <String><Key>Password</Key><Value>old</Value></String>
+                </Entry></History>
+            </Entry>
+            <Group><Name>Work</Name>
+                <Entry><String><Key>Password</Key><Value>nameless</Value></String></Entry>
+                <Group><Name>Cloud</Name>
+                    <Entry>
+                        <String><Key>Title</Key><Value>aws</Value></String>
+                        <String><Key>Password</Key><Value>a &amp; <![CDATA[<b>]]></Value></String>
+                    </Entry>
+                </Group>
+            </Group>
+        </Group></Root></KeePassFile>
+    "#;
+
+    let (logins, skipped) = parse_keepass_xml(xml.as_bytes(), &mut NoProgress).unwrap();
+
+    let found: Vec<_> = logins
+        .iter()
+        .map(|login| {
+            (
+                login.name.as_str(),
+                login.password.as_str(),
+                login.folder.as_deref(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        found,
+        [
+            ("mail", "current", None),
+            ("aws", "a & <b>", Some("Work/Cloud"))
+        ]
+    );
+    assert_eq!(skipped.len(), 1);
+    assert!(skipped[0].contains("\"Work\""), "{}", skipped[0]);
+}
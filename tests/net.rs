@@ -0,0 +1,884 @@
+#![cfg(feature = "web")]
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    net::TcpStream,
+    path::PathBuf,
+    process,
+    sync::{atomic::AtomicBool, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use locket::{Database, Login, Server};
+use serde_json::{json, Value};
+
+// The servers started by these tests are never asked to shut down, they just go away when the test
+// binary exits.
+static NEVER: AtomicBool = AtomicBool::new(false);
+
+struct TestServer {
+    base: String,
+    db_path: PathBuf,
+    assets_dir: Option<PathBuf>,
+}
+
+impl TestServer {
+    fn start(name: &str) -> Self {
+        Self::start_with_assets(name, false)
+    }
+
+    // If `assets` is true, the server is given an empty assets directory of its own, which can be
+    // found in `assets_dir`.
+    fn start_with_assets(name: &str, assets: bool) -> Self {
+        Self::start_with_db(name, assets, |_| ())
+    }
+
+    // `configure` is given the database before the server starts, to set what would otherwise be
+    // copied from the configuration file.
+    fn start_with_db(name: &str, assets: bool, configure: impl FnOnce(&mut Database)) -> Self {
+        Self::start_with_server(name, assets, configure, |server| server)
+    }
+
+    // Likewise, `configure_server` is given the server before it starts, for the settings which
+    // would be taken from the configuration file by `serve`.
+    fn start_with_server(
+        name: &str,
+        assets: bool,
+        configure: impl FnOnce(&mut Database),
+        configure_server: impl FnOnce(Server) -> Server,
+    ) -> Self {
+        let db_path = env::temp_dir().join(format!("locket-test-{}-{name}.db", process::id()));
+        let _ = fs::remove_file(&db_path);
+        let mut db = Database::init(&db_path).unwrap();
+        configure(&mut db);
+        let db = Arc::new(Mutex::new(db));
+
+        let assets_dir = assets.then(|| {
+            let dir = env::temp_dir().join(format!("locket-test-{}-{name}", process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join("assets")).unwrap();
+            dir.join("assets")
+        });
+
+        let server = configure_server(
+            Server::bind(0)
+                .unwrap()
+                .with_assets_dir(assets_dir.clone())
+                .with_idempotency_ttl(Duration::from_secs(60)),
+        );
+        let base = format!("http://127.0.0.1:{}", server.port());
+        thread::spawn(move || server.run(&db, &NEVER));
+
+        Self {
+            base,
+            db_path,
+            assets_dir,
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.db_path);
+        let _ = fs::remove_file(self.db_path.with_extension("db.lock"));
+        if let Some(dir) = self.assets_dir.as_ref().and_then(|dir| dir.parent()) {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+fn status(result: Result<ureq::Response, ureq::Error>) -> u16 {
+    match result {
+        Ok(response) => response.status(),
+        Err(ureq::Error::Status(status, _)) => status,
+        Err(e) => panic!("request failed: {e}"),
+    }
+}
+
+#[test]
+fn query_starts_empty() {
+    let server = TestServer::start("query_starts_empty");
+
+    let response = ureq::get(&server.url("/api/v1/query")).call().unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.into_json::<Value>().unwrap(), json!([]));
+}
+
+#[test]
+fn new_then_query_then_remove() {
+    let server = TestServer::start("new_then_query_then_remove");
+
+    let response = ureq::post(&server.url("/api/v1/new"))
+        .send_json(json!([{ "name": "example", "username": "me", "password": "hunter2" }]))
+        .unwrap();
+    assert_eq!(response.status(), 201);
+    let location = response.header("Location").unwrap().to_owned();
+    let created: Value = response.into_json().unwrap();
+    let [created_id] = created["ids"].as_array().unwrap().as_slice() else {
+        panic!("expected exactly one id, got {created}");
+    };
+
+    let matches: Value = ureq::get(&server.url("/api/v1/query?query=example"))
+        .call()
+        .unwrap()
+        .into_json()
+        .unwrap();
+    let [entry] = matches.as_array().unwrap().as_slice() else {
+        panic!("expected exactly one match, got {matches}");
+    };
+    let id = entry[0].as_str().unwrap();
+    assert_eq!(created_id, id);
+    assert_eq!(location, format!("/api/v1/login?id={id}"));
+    assert_eq!(entry[1]["name"], "example");
+    assert_eq!(entry[1]["username"], "me");
+    assert_eq!(entry[1]["password"], "hunter2");
+
+    let login: Value = ureq::get(&server.url(&location))
+        .call()
+        .unwrap()
+        .into_json()
+        .unwrap();
+    assert_eq!(login["name"], "example");
+
+    let remove = server.url(&format!("/api/v1/remove?id={id}"));
+    assert_eq!(status(ureq::delete(&remove).call()), 204);
+    assert_eq!(status(ureq::delete(&remove).call()), 404);
+    assert_eq!(status(ureq::get(&server.url(&location)).call()), 404);
+}
+
+#[test]
+fn remove_with_echo_returns_the_removed_login() {
+    let server = TestServer::start("remove_with_echo_returns_the_removed_login");
+    let created: Value = ureq::post(&server.url("/api/v1/new"))
+        .send_json(json!([{ "name": "example", "username": "me", "password": "hunter2" }]))
+        .unwrap()
+        .into_json()
+        .unwrap();
+    let id = created["ids"][0].as_str().unwrap();
+
+    let response = ureq::delete(&server.url(&format!("/api/v1/remove?id={id}&echo=true")))
+        .call()
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let removed: Value = response.into_json().unwrap();
+    assert_eq!(removed["name"], "example");
+    assert_eq!(removed["username"], "me");
+}
+
+#[test]
+fn query_ignores_accents() {
+    let server = TestServer::start("query_ignores_accents");
+    ureq::post(&server.url("/api/v1/new"))
+        .send_json(json!([
+            { "name": "Résumé", "username": "me", "password": "hunter2" },
+            { "name": "Почта", "username": "me", "password": "hunter2" }
+        ]))
+        .unwrap();
+    let names = |query: &str| -> Vec<String> {
+        let matches: Value = ureq::get(&server.url("/api/v1/query"))
+            .query("query", query)
+            .call()
+            .unwrap()
+            .into_json()
+            .unwrap();
+        matches
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry[1]["name"].as_str().unwrap().to_owned())
+            .collect()
+    };
+
+    assert_eq!(names("resume"), ["Résumé"]);
+    assert_eq!(names("RÉSUMÉ"), ["Résumé"]);
+    assert_eq!(names("почта"), ["Почта"]);
+}
+
+#[test]
+fn query_filters_by_folder() {
+    let server = TestServer::start("query_filters_by_folder");
+    ureq::post(&server.url("/api/v1/new"))
+        .send_json(json!([
+            { "name": "AWS", "username": "me", "password": "hunter2", "folder": "/Work//Cloud/" },
+            { "name": "Jira", "username": "me", "password": "hunter2", "folder": "Work" },
+            { "name": "Bank", "username": "me", "password": "hunter2", "folder": "Workshop" },
+            { "name": "Email", "username": "me", "password": "hunter2" }
+        ]))
+        .unwrap();
+    let names = |folder: &str| -> Vec<String> {
+        let matches: Value = ureq::get(&server.url("/api/v1/query"))
+            .query("folder", folder)
+            .call()
+            .unwrap()
+            .into_json()
+            .unwrap();
+        let mut names: Vec<String> = matches
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry[1]["name"].as_str().unwrap().to_owned())
+            .collect();
+        names.sort();
+        names
+    };
+
+    // Folders are tidied up when logins are added, and include the folders inside them.
+    assert_eq!(names("Work"), ["AWS", "Jira"]);
+    assert_eq!(names("Work/Cloud"), ["AWS"]);
+    assert_eq!(names("Workshop"), ["Bank"]);
+    assert_eq!(names(""), ["AWS", "Bank", "Email", "Jira"]);
+}
+
+#[test]
+fn query_can_mask_secrets() {
+    let server = TestServer::start("query_can_mask_secrets");
+    ureq::post(&server.url("/api/v1/new"))
+        .send_json(json!([{
+            "name": "example",
+            "username": "me",
+            "password": "hunter2",
+            "custom_fields": { "pin": "1234", "note": "hello" },
+            "secret_fields": ["pin"]
+        }]))
+        .unwrap();
+
+    let matches: Value = ureq::get(&server.url("/api/v1/query"))
+        .query("masked", "true")
+        .call()
+        .unwrap()
+        .into_json()
+        .unwrap();
+    let login = &matches[0][1];
+    assert_eq!(login["name"], "example");
+    assert_eq!(login["password"], "********");
+    assert_eq!(login["custom_fields"]["pin"], "********");
+    assert_eq!(login["custom_fields"]["note"], "hello");
+
+    let id = matches[0][0].as_str().unwrap();
+    let unmasked: Value = ureq::get(&server.url("/api/v1/login"))
+        .query("id", id)
+        .call()
+        .unwrap()
+        .into_json()
+        .unwrap();
+    assert_eq!(unmasked["password"], "hunter2");
+}
+
+#[test]
+fn folders_include_the_folders_they_are_in() {
+    let server = TestServer::start("folders_include_the_folders_they_are_in");
+    ureq::post(&server.url("/api/v1/new"))
+        .send_json(json!([
+            { "name": "AWS", "username": "me", "password": "hunter2", "folder": "Work/Cloud" },
+            { "name": "Bank", "username": "me", "password": "hunter2", "folder": "Home" },
+            { "name": "Email", "username": "me", "password": "hunter2" }
+        ]))
+        .unwrap();
+
+    let folders: Value = ureq::get(&server.url("/api/v1/folders"))
+        .call()
+        .unwrap()
+        .into_json()
+        .unwrap();
+    assert_eq!(folders, json!(["Home", "Work", "Work/Cloud"]));
+}
+
+#[test]
+fn query_page_is_escaped() {
+    let server = TestServer::start("query_page_is_escaped");
+    ureq::post(&server.url("/api/v1/new"))
+        .send_json(json!([{
+            "name": "<script>alert(1)</script>",
+            "username": "<b>",
+            "password": "hunter2",
+            "folder": "<i>"
+        }]))
+        .unwrap();
+
+    let page = ureq::get(&server.url("/query"))
+        .call()
+        .unwrap()
+        .into_string()
+        .unwrap();
+    assert!(page.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+    assert!(page.contains("&lt;b&gt;"));
+    assert!(page.contains("&lt;i&gt;"));
+    assert!(!page.contains("<script>alert"));
+    // Passwords are masked, as they are on the page rendered by the browser.
+    assert!(!page.contains("hunter2"));
+}
+
+#[test]
+fn sync_only_writes_when_something_changed() {
+    let server = TestServer::start("sync_only_writes_when_something_changed");
+    let synced = || {
+        let response = ureq::get(&server.url("/api/v1/sync")).call().unwrap();
+        assert_eq!(response.status(), 204);
+        response.header("X-Synced").unwrap().to_owned()
+    };
+
+    // A new database has never been written.
+    assert_eq!(synced(), "true");
+    assert_eq!(synced(), "false");
+    ureq::post(&server.url("/api/v1/new"))
+        .send_json(json!([{ "name": "example", "username": "me", "password": "hunter2" }]))
+        .unwrap();
+    assert_eq!(synced(), "true");
+    assert_eq!(synced(), "false");
+}
+
+#[test]
+fn logins_can_be_given_by_path() {
+    let server = TestServer::start("logins_can_be_given_by_path");
+    let added: Value = ureq::post(&server.url("/api/v1/new"))
+        .send_json(json!([{ "name": "example", "username": "me", "password": "hunter2" }]))
+        .unwrap()
+        .into_json()
+        .unwrap();
+    let id = added["ids"][0].as_str().unwrap();
+    let login = server.url(&format!("/api/v1/login/{id}"));
+
+    let fetched: Value = ureq::get(&login).call().unwrap().into_json().unwrap();
+    assert_eq!(fetched["name"], "example");
+    let updated: Value = ureq::request("PATCH", &login)
+        .send_json(json!({ "username": "you" }))
+        .unwrap()
+        .into_json()
+        .unwrap();
+    assert_eq!(updated["username"], "you");
+
+    let attachment = format!("{login}/attachments/key.bin");
+    assert_eq!(status(ureq::post(&attachment).send_bytes(b"key")), 201);
+    let mut body = Vec::new();
+    ureq::get(&attachment)
+        .call()
+        .unwrap()
+        .into_reader()
+        .read_to_end(&mut body)
+        .unwrap();
+    assert_eq!(body, b"key");
+
+    assert_eq!(status(ureq::delete(&login).call()), 204);
+    assert_eq!(status(ureq::get(&login).call()), 404);
+}
+
+#[test]
+fn wrong_methods_are_not_allowed() {
+    let server = TestServer::start("wrong_methods_are_not_allowed");
+    match ureq::get(&server.url("/api/v1/new")).call() {
+        Err(ureq::Error::Status(405, response)) => {
+            assert_eq!(response.header("Allow"), Some("POST"));
+        }
+        other => panic!("expected a 405, got {other:?}"),
+    }
+    let allowed = ureq::request("PUT", &server.url("/api/v1/login/1234")).call();
+    match allowed {
+        Err(ureq::Error::Status(405, response)) => {
+            assert_eq!(response.header("Allow"), Some("GET, PATCH, DELETE"));
+        }
+        other => panic!("expected a 405, got {other:?}"),
+    }
+}
+
+#[test]
+fn snapshot_is_forbidden_by_default() {
+    let server = TestServer::start("snapshot_is_forbidden_by_default");
+
+    assert_eq!(
+        status(ureq::get(&server.url("/api/v1/snapshot")).call()),
+        403
+    );
+}
+
+#[test]
+fn snapshot_is_a_readable_backup() {
+    let server = TestServer::start_with_server(
+        "snapshot_is_a_readable_backup",
+        false,
+        |_| (),
+        |server| server.with_snapshots(true),
+    );
+    ureq::post(&server.url("/api/v1/new"))
+        .send_json(json!([{ "name": "example", "username": "me", "password": "hunter2" }]))
+        .unwrap();
+
+    let response = ureq::get(&server.url("/api/v1/snapshot")).call().unwrap();
+    assert_eq!(response.content_type(), "application/octet-stream");
+    let disposition = response.header("Content-Disposition").unwrap();
+    assert!(disposition.starts_with("attachment; filename=\"locket-"));
+    assert!(disposition.ends_with(".db\""));
+
+    let snapshot = Database::from_reader(response.into_reader()).unwrap();
+    let logins: Vec<_> = snapshot.logins.values().collect();
+    assert_eq!(logins.len(), 1);
+    assert_eq!(logins[0].password, "hunter2");
+}
+
+#[test]
+fn new_refuses_logins_beyond_max_logins() {
+    let server = TestServer::start_with_db("new_refuses_logins_beyond_max_logins", false, |db| {
+        db.max_logins = 2;
+    });
+    let new = || ureq::post(&server.url("/api/v1/new"));
+
+    assert_eq!(
+        status(new().send_json(json!([{ "name": "a", "username": "me", "password": "hunter2" }]))),
+        201
+    );
+    // The batch is refused as a whole, even though one of them would fit.
+    let refused = new().send_json(json!([
+        { "name": "b", "username": "me", "password": "hunter2" },
+        { "name": "c", "username": "me", "password": "hunter2" }
+    ]));
+    let Err(ureq::Error::Status(507, response)) = refused else {
+        panic!("expected a 507");
+    };
+    let message = response.into_string().unwrap();
+    assert!(message.contains("has 1 logins"), "{message}");
+    assert!(message.contains("at most 2"), "{message}");
+
+    // Duplicates aren't added, so they don't count towards the limit.
+    assert_eq!(
+        status(new().send_json(json!([
+            { "name": "a", "username": "me", "password": "hunter2" },
+            { "name": "b", "username": "me", "password": "hunter2" }
+        ]))),
+        201
+    );
+    let logins: Value = ureq::get(&server.url("/api/v1/query"))
+        .call()
+        .unwrap()
+        .into_json()
+        .unwrap();
+    assert_eq!(logins.as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn update_changes_only_the_given_fields() {
+    let server = TestServer::start("update_changes_only_the_given_fields");
+    let created: Value = ureq::post(&server.url("/api/v1/new"))
+        .send_json(json!([
+            { "name": "example", "username": "me", "password": "hunter2", "url": "example.com" },
+            { "name": "other", "username": "me", "password": "hunter2" }
+        ]))
+        .unwrap()
+        .into_json()
+        .unwrap();
+    let id = created["ids"][0].as_str().unwrap();
+    let update = server.url(&format!("/api/v1/update?id={id}"));
+
+    let updated: Value = ureq::request("PATCH", &update)
+        .send_json(json!({ "password": "correct horse" }))
+        .unwrap()
+        .into_json()
+        .unwrap();
+    assert_eq!(updated["password"], "correct horse");
+    assert_eq!(updated["name"], "example");
+    assert_eq!(updated["url"], "example.com");
+
+    let renamed = ureq::request("PATCH", &update).send_json(json!({ "name": "other" }));
+    assert_eq!(status(renamed), 409);
+    let typo = ureq::request("PATCH", &update).send_json(json!({ "pasword": "oops" }));
+    assert_eq!(status(typo), 400);
+    let nil = "00000000-0000-0000-0000-000000000000";
+    let missing = ureq::request("PATCH", &server.url(&format!("/api/v1/update?id={nil}")))
+        .send_json(json!({ "password": "correct horse" }));
+    assert_eq!(status(missing), 404);
+}
+
+#[test]
+fn password_references_are_refused() {
+    let server = TestServer::start_with_db("password_references_are_refused", false, |db| {
+        db.add_login(Login::new(
+            String::from("ssh"),
+            String::from("me"),
+            String::from("file:~/.ssh/id_ed25519"),
+        ))
+        .unwrap();
+    });
+    let new = || ureq::post(&server.url("/api/v1/new"));
+
+    for password in ["file:~/.ssh/id_ed25519", "cmd:cat /etc/passwd"] {
+        let refused =
+            new().send_json(json!([{ "name": "stolen", "username": "me", "password": password }]));
+        assert_eq!(status(refused), 400);
+    }
+
+    // Escaped, it's stored as it is, and sent back as the password it stands for.
+    let created: Value = new()
+        .send_json(json!([{ "name": "odd", "username": "me", "password": "\\file:x" }]))
+        .unwrap()
+        .into_json()
+        .unwrap();
+    let id = created["ids"][0].as_str().unwrap();
+    let login: Value = ureq::get(&server.url(&format!("/api/v1/login/{id}")))
+        .call()
+        .unwrap()
+        .into_json()
+        .unwrap();
+    assert_eq!(login["password"], "file:x");
+
+    // Sending it back as it was given out keeps it escaped, rather than making it a reference.
+    let update = server.url(&format!("/api/v1/login/{id}"));
+    let unchanged = ureq::request("PATCH", &update).send_json(json!({ "password": "file:x" }));
+    assert_eq!(status(unchanged), 200);
+    let changed =
+        ureq::request("PATCH", &update).send_json(json!({ "password": "file:/etc/shadow" }));
+    assert_eq!(status(changed), 400);
+
+    // A reference which was already there is sent as it's written, and can be sent back unchanged.
+    let matches: Value = ureq::get(&server.url("/api/v1/query?query=ssh"))
+        .call()
+        .unwrap()
+        .into_json()
+        .unwrap();
+    let id = matches[0][0].as_str().unwrap();
+    let url = server.url(&format!("/api/v1/login/{id}"));
+    let login: Value = ureq::get(&url).call().unwrap().into_json().unwrap();
+    assert_eq!(login["password"], "file:~/.ssh/id_ed25519");
+    let unchanged = ureq::request("PATCH", &url)
+        .send_json(json!({ "password": "file:~/.ssh/id_ed25519", "notes": "kept" }));
+    assert_eq!(status(unchanged), 200);
+}
+
+#[test]
+fn other_hosts_are_forbidden() {
+    let server = TestServer::start("other_hosts_are_forbidden");
+    let address = server.base.trim_start_matches("http://");
+    let port = address.rsplit_once(':').unwrap().1;
+
+    let respond = |host: &str| {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "GET /api/v1/query HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n"
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+    for host in [format!("localhost:{port}"), format!("127.0.0.1:{port}")] {
+        assert!(respond(&host).starts_with("HTTP/1.1 200"), "{host}");
+    }
+    for host in [
+        format!("attacker.example:{port}"),
+        String::from("localhost:1"),
+    ] {
+        assert!(respond(&host).starts_with("HTTP/1.1 403"), "{host}");
+    }
+}
+
+#[test]
+fn edit_page_is_prefilled_and_escaped() {
+    let server = TestServer::start("edit_page_is_prefilled_and_escaped");
+    let added: Value = ureq::post(&server.url("/api/v1/new"))
+        .send_json(json!([
+            { "name": "<script>alert(1)</script>", "username": "\"me\"", "password": "hunter2" }
+        ]))
+        .unwrap()
+        .into_json()
+        .unwrap();
+    let id = added["ids"][0].as_str().unwrap();
+
+    let page = ureq::get(&server.url("/edit"))
+        .query("id", id)
+        .call()
+        .unwrap()
+        .into_string()
+        .unwrap();
+    assert!(page.contains(r#"value="&lt;script&gt;alert(1)&lt;/script&gt;""#));
+    assert!(page.contains(r#"value="&quot;me&quot;""#));
+    assert!(page.contains(r#"value="hunter2""#));
+    assert!(!page.contains("<script>alert"));
+
+    let unknown = ureq::get(&server.url("/edit"))
+        .query("id", "00000000-0000-0000-0000-000000000000")
+        .call();
+    assert_eq!(status(unknown), 404);
+}
+
+#[test]
+fn attachments_round_trip() {
+    let server = TestServer::start("attachments_round_trip");
+    let added: Value = ureq::post(&server.url("/api/v1/new"))
+        .send_json(json!([{ "name": "example", "username": "me", "password": "hunter2" }]))
+        .unwrap()
+        .into_json()
+        .unwrap();
+    let id = added["ids"][0].as_str().unwrap();
+    let url = server.url("/api/v1/attachment");
+    let bytes = [0, 159, 146, 150, 255];
+
+    let created = ureq::post(&url)
+        .query("id", id)
+        .query("name", "key.bin")
+        .send_bytes(&bytes);
+    assert_eq!(status(created), 201);
+    let replaced = ureq::post(&url)
+        .query("id", id)
+        .query("name", "key.bin")
+        .send_bytes(&bytes);
+    assert_eq!(status(replaced), 200);
+
+    let response = ureq::get(&url)
+        .query("id", id)
+        .query("name", "key.bin")
+        .call()
+        .unwrap();
+    assert_eq!(response.content_type(), "application/octet-stream");
+    let mut body = Vec::new();
+    response.into_reader().read_to_end(&mut body).unwrap();
+    assert_eq!(body, bytes);
+
+    let unknown = ureq::get(&url)
+        .query("id", id)
+        .query("name", "other.bin")
+        .call();
+    assert_eq!(status(unknown), 404);
+    let unnamed = ureq::get(&url).query("id", id).call();
+    assert_eq!(status(unnamed), 400);
+}
+
+#[test]
+fn new_skips_duplicates_but_adds_the_rest() {
+    let server = TestServer::start("new_skips_duplicates_but_adds_the_rest");
+    let login = json!({ "name": "example", "username": "me", "password": "hunter2" });
+    let other = json!({ "name": "other", "username": "me", "password": "hunter2" });
+
+    let response = ureq::post(&server.url("/api/v1/new"))
+        .send_json(json!([login, login, other]))
+        .unwrap();
+    assert_eq!(response.status(), 201);
+    let created: Value = response.into_json().unwrap();
+    assert_eq!(created["ids"].as_array().unwrap().len(), 2);
+    let results = created["results"].as_array().unwrap();
+    assert!(results[0]["id"].is_string());
+    assert_eq!(results[1]["error"], "duplicate");
+    assert!(results[2]["id"].is_string());
+
+    // Nothing new this time, so nothing is created.
+    let response = ureq::post(&server.url("/api/v1/new"))
+        .send_json(json!([login]))
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let created: Value = response.into_json().unwrap();
+    assert_eq!(created["ids"], json!([]));
+    assert_eq!(created["results"][0]["error"], "duplicate");
+}
+
+#[test]
+fn new_warns_about_empty_passwords() {
+    let server = TestServer::start("new_warns_about_empty_passwords");
+
+    let created: Value = ureq::post(&server.url("/api/v1/new"))
+        .send_json(json!([
+            { "name": "notes only", "username": "", "password": "" },
+            { "name": "example", "username": "me", "password": "hunter2" }
+        ]))
+        .unwrap()
+        .into_json()
+        .unwrap();
+    assert_eq!(created["ids"].as_array().unwrap().len(), 2);
+    assert_eq!(created["results"][0]["warnings"], json!(["empty_password"]));
+    assert!(created["results"][1].get("warnings").is_none());
+}
+
+#[test]
+fn new_with_the_same_idempotency_key_only_adds_once() {
+    let server = TestServer::start("new_with_the_same_idempotency_key_only_adds_once");
+    let post = || {
+        ureq::post(&server.url("/api/v1/new"))
+            .set("Idempotency-Key", "retry-me")
+            .send_json(json!([{ "name": "example", "username": "me", "password": "hunter2" }]))
+            .unwrap()
+    };
+
+    let first = post();
+    let second = post();
+    assert_eq!(first.status(), 201);
+    assert_eq!(second.status(), 201);
+    assert_eq!(first.header("Location"), second.header("Location"));
+    assert_eq!(
+        first.into_json::<Value>().unwrap(),
+        second.into_json::<Value>().unwrap()
+    );
+
+    let matches: Value = ureq::get(&server.url("/api/v1/query"))
+        .call()
+        .unwrap()
+        .into_json()
+        .unwrap();
+    assert_eq!(matches.as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn new_rejects_other_content_types() {
+    let server = TestServer::start("new_rejects_other_content_types");
+
+    let result = ureq::post(&server.url("/api/v1/new"))
+        .set("Content-Type", "text/plain")
+        .send_string("[]");
+    assert_eq!(status(result), 415);
+}
+
+#[test]
+fn new_rejects_malformed_json() {
+    let server = TestServer::start("new_rejects_malformed_json");
+
+    let result = ureq::post(&server.url("/api/v1/new"))
+        .set("Content-Type", "application/json")
+        .send_string("[{");
+    let Err(ureq::Error::Status(400, response)) = result else {
+        panic!("expected a 400, got {result:?}");
+    };
+    assert_eq!(response.content_type(), "application/json");
+    let error: Value = response.into_json().unwrap();
+    assert_eq!(error["error"], "bad_request");
+    assert!(
+        error["message"].as_str().unwrap().contains("EOF"),
+        "{error}"
+    );
+}
+
+#[test]
+fn remove_rejects_invalid_ids() {
+    let server = TestServer::start("remove_rejects_invalid_ids");
+
+    assert_eq!(
+        status(ureq::delete(&server.url("/api/v1/remove?id=nope")).call()),
+        404
+    );
+    assert_eq!(
+        status(ureq::delete(&server.url("/api/v1/remove")).call()),
+        404
+    );
+}
+
+#[test]
+fn unparseable_url_is_a_bad_request() {
+    let server = TestServer::start("unparseable_url_is_a_bad_request");
+
+    // `ureq` won't send a url this broken, so the request is written by hand.
+    let mut stream = TcpStream::connect(server.base.trim_start_matches("http://")).unwrap();
+    stream
+        .write_all(b"GET //[ HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 400"), "{response}");
+
+    // The server should still be up afterwards.
+    assert_eq!(status(ureq::get(&server.url("/api/v1/query")).call()), 200);
+}
+
+#[test]
+fn unknown_paths_are_not_found() {
+    let server = TestServer::start("unknown_paths_are_not_found");
+
+    assert_eq!(status(ureq::get(&server.url("/nothing/here")).call()), 404);
+}
+
+#[test]
+fn assets_are_served_from_the_assets_dir() {
+    let server = TestServer::start_with_assets("assets_are_served_from_the_assets_dir", true);
+    let assets_dir = server.assets_dir.as_ref().unwrap();
+    fs::write(assets_dir.join("index.css"), "body {}").unwrap();
+
+    let response = ureq::get(&server.url("/index.css")).call().unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.header("Content-Type"),
+        Some("text/css; charset=utf8")
+    );
+    assert_eq!(response.into_string().unwrap(), "body {}");
+}
+
+#[test]
+fn only_the_web_uis_files_are_served_from_the_assets_dir() {
+    let server = TestServer::start_with_assets(
+        "only_the_web_uis_files_are_served_from_the_assets_dir",
+        true,
+    );
+    let assets_dir = server.assets_dir.as_ref().unwrap();
+    fs::write(assets_dir.join("notes.txt"), "not for sharing").unwrap();
+    fs::create_dir(assets_dir.join("css")).unwrap();
+    fs::write(assets_dir.join("css").join("index.css"), "body {}").unwrap();
+
+    for path in ["/notes.txt", "/css/index.css", "/index.css%00"] {
+        assert_eq!(status(ureq::get(&server.url(path)).call()), 404, "{path}");
+    }
+}
+
+#[test]
+fn traversal_out_of_the_assets_dir_is_forbidden() {
+    let server =
+        TestServer::start_with_assets("traversal_out_of_the_assets_dir_is_forbidden", true);
+    let assets_dir = server.assets_dir.as_ref().unwrap();
+    // Something worth stealing, just outside of the assets directory.
+    fs::write(assets_dir.parent().unwrap().join("secret"), "hunter2").unwrap();
+
+    for path in [
+        "/..%2Fsecret",
+        "/..%2F..%2F..%2F..%2F..%2Fetc%2Fpasswd",
+        "/%2Fetc%2Fpasswd",
+        "/index.css%2F..%2F..%2Fsecret",
+    ] {
+        assert_eq!(status(ureq::get(&server.url(path)).call()), 403, "{path}");
+    }
+}
+
+#[test]
+fn traversal_without_an_assets_dir_is_forbidden() {
+    let server = TestServer::start("traversal_without_an_assets_dir_is_forbidden");
+
+    assert_eq!(
+        status(ureq::get(&server.url("/..%2F..%2F..%2Fetc%2Fpasswd")).call()),
+        403
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_out_of_the_assets_dir_are_forbidden() {
+    let server =
+        TestServer::start_with_assets("symlinks_out_of_the_assets_dir_are_forbidden", true);
+    let assets_dir = server.assets_dir.as_ref().unwrap();
+    let secret = assets_dir.parent().unwrap().join("secret");
+    fs::write(&secret, "hunter2").unwrap();
+    std::os::unix::fs::symlink(&secret, assets_dir.join("index.css")).unwrap();
+
+    assert_eq!(status(ureq::get(&server.url("/index.css")).call()), 403);
+}
+
+#[test]
+fn openapi_document_describes_the_api() {
+    let server = TestServer::start("openapi_document_describes_the_api");
+
+    let document: Value = ureq::get(&server.url("/api/v1/openapi.json"))
+        .call()
+        .unwrap()
+        .into_json()
+        .unwrap();
+    assert!(document["openapi"].as_str().unwrap().starts_with("3."));
+    for path in [
+        "/api/v1/query",
+        "/api/v1/folders",
+        "/api/v1/new",
+        "/api/v1/login",
+        "/api/v1/update",
+        "/api/v1/remove",
+        "/api/v1/attachment",
+        "/api/v1/login/{id}",
+        "/api/v1/login/{id}/attachments/{name}",
+    ] {
+        assert!(document["paths"][path].is_object(), "{path}");
+    }
+    assert!(document["components"]["schemas"]["Login"]["properties"]["password"].is_object());
+}
@@ -0,0 +1,78 @@
+#![cfg(feature = "web")]
+
+use locket::{RouteError, Router};
+use tiny_http::Method;
+
+fn router() -> Router<&'static str> {
+    Router::new()
+        .route(Method::Get, "/api/v1/login", "by query")
+        .route(Method::Get, "/api/v1/login/new", "literal")
+        .route(Method::Get, "/api/v1/login/:id", "get")
+        .route(Method::Delete, "/api/v1/login/:id", "delete")
+        .route(
+            Method::Get,
+            "/api/v1/login/:id/attachments/:name",
+            "attachment",
+        )
+}
+
+#[test]
+fn params_are_taken_from_the_path() {
+    let router = router();
+    let (handler, params) = router
+        .find(
+            &Method::Get,
+            "/api/v1/login/1234/attachments/key%20file.bin",
+        )
+        .unwrap();
+    assert_eq!(*handler, "attachment");
+    assert_eq!(params.get("id"), Some("1234"));
+    assert_eq!(params.get("name"), Some("key file.bin"));
+    assert_eq!(params.get("other"), None);
+}
+
+#[test]
+fn routes_are_tried_in_order() {
+    let router = router();
+    assert_eq!(
+        *router.find(&Method::Get, "/api/v1/login/new").unwrap().0,
+        "literal"
+    );
+    assert_eq!(
+        *router.find(&Method::Get, "/api/v1/login/old").unwrap().0,
+        "get"
+    );
+    assert_eq!(
+        *router.find(&Method::Get, "/api/v1/login").unwrap().0,
+        "by query"
+    );
+}
+
+#[test]
+fn unmatched_paths_are_not_found() {
+    let router = router();
+    for path in [
+        "/",
+        "/api/v1/login/",
+        "/api/v1/login/1234/attachments",
+        "/api/v1/login/1234/extra/key.bin",
+    ] {
+        assert_eq!(
+            router.find(&Method::Get, path).err(),
+            Some(RouteError::NotFound),
+            "{path}"
+        );
+    }
+}
+
+#[test]
+fn other_methods_are_listed() {
+    let router = router();
+    assert_eq!(
+        router.find(&Method::Patch, "/api/v1/login/1234").err(),
+        Some(RouteError::MethodNotAllowed(vec![
+            Method::Get,
+            Method::Delete
+        ]))
+    );
+}
@@ -0,0 +1,137 @@
+#![cfg(feature = "web")]
+
+use std::{sync::mpsc, time::Duration};
+
+use locket::{Threadpool, TryExecError};
+
+#[test]
+fn a_panicking_job_doesnt_stop_the_pool() {
+    let pool = Threadpool::new(1).unwrap();
+    let (sender, receiver) = mpsc::channel();
+
+    pool.exec(|| panic!("this job panics on purpose"));
+    for i in 0..3 {
+        let sender = sender.clone();
+        pool.exec(move || sender.send(i).unwrap());
+    }
+
+    // The only worker ran the panicking job, so these only arrive if it survived.
+    for i in 0..3 {
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(i));
+    }
+}
+
+#[test]
+fn a_full_queue_gives_jobs_back() {
+    let pool = Threadpool::with_capacity(1, 1).unwrap();
+    let (started_sender, started) = mpsc::channel();
+    let (release, released) = mpsc::channel::<()>();
+
+    // Keep the only worker busy, so that nothing is taken off the queue.
+    pool.exec(move || {
+        started_sender.send(()).unwrap();
+        released.recv().unwrap();
+    });
+    started.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    let (sender, receiver) = mpsc::channel();
+    let queued = sender.clone();
+    assert!(pool
+        .try_exec(move || queued.send("queued").unwrap())
+        .is_ok());
+    assert!(pool.is_full());
+    let rejected = pool.try_exec(move || sender.send("rejected").unwrap());
+    let Err(TryExecError::Full(job)) = rejected else {
+        panic!("expected the queue to be full, got {rejected:?}");
+    };
+
+    release.send(()).unwrap();
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok("queued"));
+    // The job given back can still be run by the caller.
+    job();
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(5)),
+        Ok("rejected")
+    );
+}
+
+#[test]
+fn shutdown_waits_for_queued_jobs() {
+    let pool = Threadpool::new(2).unwrap();
+    let (sender, receiver) = mpsc::channel();
+
+    for i in 0..8 {
+        let sender = sender.clone();
+        pool.exec(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            sender.send(i).unwrap();
+        });
+    }
+    drop(sender);
+
+    assert!(pool.shutdown_timeout(Duration::from_secs(5)).is_empty());
+    let mut ran: Vec<_> = receiver.try_iter().collect();
+    ran.sort_unstable();
+    assert_eq!(ran, (0..8).collect::<Vec<_>>());
+}
+
+#[test]
+fn shutdown_gives_up_on_stuck_workers() {
+    let pool = Threadpool::new(2).unwrap();
+    let (started_sender, started) = mpsc::channel();
+    let (release, released) = mpsc::channel::<()>();
+
+    pool.exec(move || {
+        started_sender.send(()).unwrap();
+        let _ = released.recv();
+    });
+    started.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    let running = pool.shutdown_timeout(Duration::from_millis(50));
+    assert_eq!(running.len(), 1);
+    // The stuck worker is left to finish by itself.
+    drop(release);
+}
+
+#[test]
+fn dropping_the_pool_runs_every_job_first() {
+    let pool = Threadpool::new(4).unwrap();
+    let (sender, receiver) = mpsc::channel();
+
+    for i in 0..32 {
+        let sender = sender.clone();
+        pool.exec(move || sender.send(i).unwrap());
+    }
+    drop(sender);
+
+    // Dropping the pool joins every worker, so this would hang if any of them never saw the queue
+    // close, and the test runs it on another thread to give up on it instead.
+    let (dropped_sender, dropped) = mpsc::channel();
+    std::thread::spawn(move || {
+        drop(pool);
+        dropped_sender.send(()).unwrap();
+    });
+    dropped.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    let mut ran: Vec<_> = receiver.try_iter().collect();
+    ran.sort_unstable();
+    assert_eq!(ran, (0..32).collect::<Vec<_>>());
+}
+
+#[test]
+fn jobs_can_give_back_results() {
+    let pool = Threadpool::new(2).unwrap();
+
+    let results: Vec<_> = (1..=4u64)
+        .map(|i| pool.exec_with_result(move || i * i))
+        .collect();
+    let panicked = pool.exec_with_result(|| -> u64 { panic!("this job panics on purpose") });
+
+    let squares: Vec<_> = results
+        .into_iter()
+        .map(|result| result.recv_timeout(Duration::from_secs(5)).unwrap())
+        .collect();
+    assert_eq!(squares, [1, 4, 9, 16]);
+    // A job which panics has nothing to give back, so its channel just closes.
+    assert!(panicked.recv_timeout(Duration::from_secs(5)).is_err());
+}